@@ -0,0 +1,117 @@
+#[cfg(not(feature = "std"))]
+use crate::lib::*;
+
+use super::{Instr, Module};
+
+/// Lists the structural differences between two modules, one line each,
+/// such as `types length 3 vs 4` or `funcs[2].body differs at instr 5`.
+/// Returns an empty list for equal modules.
+pub fn diff(a: &Module, b: &Module) -> Vec<String> {
+    let mut out = vec![];
+    if a.version != b.version {
+        out.push(format!("version {} vs {}", a.version, b.version));
+    }
+    items(&mut out, "types", &a.types, &b.types);
+    items(&mut out, "imports", &a.imports, &b.imports);
+
+    if a.funcs.len() != b.funcs.len() {
+        out.push(format!(
+            "funcs length {} vs {}",
+            a.funcs.len(),
+            b.funcs.len()
+        ));
+    }
+    for (i, (fa, fb)) in a.funcs.iter().zip(&b.funcs).enumerate() {
+        if fa.typeidx != fb.typeidx {
+            out.push(format!(
+                "funcs[{}].typeidx {} vs {}",
+                i, fa.typeidx, fb.typeidx
+            ));
+        }
+        if fa.locals != fb.locals {
+            out.push(format!("funcs[{}].locals differ", i));
+        }
+        let (ba, bb) = (&fa.body.0, &fb.body.0);
+        if let Some(pc) = ba.iter().zip(bb).position(|(x, y)| !same_instr(x, y)) {
+            out.push(format!(
+                "funcs[{}].body differs at instr {}: {} vs {}",
+                i, pc, ba[pc], bb[pc]
+            ));
+        } else if ba.len() != bb.len() {
+            out.push(format!(
+                "funcs[{}].body length {} vs {}",
+                i,
+                ba.len(),
+                bb.len()
+            ));
+        }
+    }
+
+    items(&mut out, "tables", &a.tables, &b.tables);
+    items(&mut out, "mems", &a.mems, &b.mems);
+    items(&mut out, "globals", &a.globals, &b.globals);
+    items(&mut out, "exports", &a.exports, &b.exports);
+    if a.start != b.start {
+        out.push(format!("start {:?} vs {:?}", a.start, b.start));
+    }
+    items(&mut out, "elems", &a.elems, &b.elems);
+    items(&mut out, "datas", &a.datas, &b.datas);
+    out
+}
+
+fn items<T: PartialEq>(out: &mut Vec<String>, name: &str, a: &[T], b: &[T]) {
+    if a.len() != b.len() {
+        out.push(format!("{} length {} vs {}", name, a.len(), b.len()));
+    }
+    for (i, _) in a.iter().zip(b).enumerate().filter(|(_, (x, y))| x != y) {
+        out.push(format!("{}[{}] differs", name, i));
+    }
+}
+
+/// Equality that treats float constants with the same bits, NaNs included,
+/// as equal.
+fn same_instr(a: &Instr, b: &Instr) -> bool {
+    match (a, b) {
+        (Instr::F32Const(x), Instr::F32Const(y)) => x.to_bits() == y.to_bits(),
+        (Instr::F64Const(x), Instr::F64Const(y)) => x.to_bits() == y.to_bits(),
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diff;
+    use crate::binary::Instr;
+    use crate::loader::parser::Parser;
+    use crate::tests::wat2wasm;
+
+    #[test]
+    fn one_instr() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func (result f32) (f32.const nan))
+                  (func (result i32)
+                    (i32.add (i32.const 1) (i32.const 2))))"#,
+        )
+        .unwrap();
+        let a = Parser::new(&wasm).module().unwrap();
+        assert!(diff(&a, &a.clone()).is_empty());
+
+        let mut b = a.clone();
+        b.funcs[1].body.0[1] = Instr::I32Const(3);
+        assert_eq!(
+            diff(&a, &b),
+            vec!["funcs[1].body differs at instr 1: i32.const 2 vs i32.const 3"]
+        );
+
+        b.types.pop();
+        b.funcs[1].body.0.pop();
+        assert_eq!(
+            diff(&a, &b),
+            vec![
+                "types length 2 vs 1",
+                "funcs[1].body differs at instr 1: i32.const 2 vs i32.const 3",
+            ]
+        );
+    }
+}
@@ -0,0 +1,719 @@
+//! Serializes a [`Module`] back to the binary format, the inverse of
+//! `Parser::module`.
+
+#[cfg(not(feature = "std"))]
+use crate::lib::*;
+
+use super::*;
+
+/// Encodes `module` with its sections in canonical order. Parsing the
+/// result yields a module equal to `module`; custom sections other than the
+/// function names are not kept by the parser and so are not written.
+pub fn encode(module: &Module) -> Vec<u8> {
+    let mut out = b"\0asm".to_vec();
+    out.extend([module.version, 0x00, 0x00, 0x00]);
+
+    if !module.types.is_empty() {
+        section(&mut out, 1, |out| vec(out, &module.types, functype));
+    }
+    if !module.imports.is_empty() {
+        section(&mut out, 2, |out| vec(out, &module.imports, import));
+    }
+    if !module.funcs.is_empty() {
+        section(&mut out, 3, |out| {
+            vec(out, &module.funcs, |out, func| u32(out, func.typeidx))
+        });
+    }
+    if !module.tables.is_empty() {
+        section(&mut out, 4, |out| vec(out, &module.tables, table_entry));
+    }
+    if !module.mems.is_empty() {
+        section(&mut out, 5, |out| vec(out, &module.mems, memory));
+    }
+    if !module.globals.is_empty() {
+        section(&mut out, 6, |out| {
+            vec(out, &module.globals, |out, global| {
+                globaltype(out, &global.type_);
+                expr(out, &global.value);
+            })
+        });
+    }
+    if !module.exports.is_empty() {
+        section(&mut out, 7, |out| vec(out, &module.exports, export));
+    }
+    if let Some(start) = module.start {
+        section(&mut out, 8, |out| u32(out, start));
+    }
+    if !module.elems.is_empty() {
+        section(&mut out, 9, |out| vec(out, &module.elems, elem));
+    }
+    if !module.datas.is_empty() {
+        section(&mut out, 12, |out| u32(out, module.datas.len() as u32));
+    }
+    if !module.funcs.is_empty() {
+        section(&mut out, 10, |out| vec(out, &module.funcs, code));
+    }
+    if !module.datas.is_empty() {
+        section(&mut out, 11, |out| vec(out, &module.datas, data));
+    }
+    if !module.func_names.is_empty() {
+        section(&mut out, 0, |out| {
+            name(out, "name");
+            // Function names subsection.
+            section(out, 1, |out| {
+                vec(out, &module.func_names, |out, (idx, func)| {
+                    u32(out, *idx);
+                    name(out, func);
+                })
+            });
+        });
+    }
+    out
+}
+
+fn section(out: &mut Vec<u8>, id: u8, f: impl FnOnce(&mut Vec<u8>)) {
+    out.push(id);
+    sized(out, f);
+}
+
+/// Writes what `f` produces prefixed with its size.
+fn sized(out: &mut Vec<u8>, f: impl FnOnce(&mut Vec<u8>)) {
+    let mut body = vec![];
+    f(&mut body);
+    u32(out, body.len() as u32);
+    out.extend(body);
+}
+
+fn vec<T>(out: &mut Vec<u8>, items: &[T], mut f: impl FnMut(&mut Vec<u8>, &T)) {
+    u32(out, items.len() as u32);
+    for item in items {
+        f(out, item);
+    }
+}
+
+fn u32(out: &mut Vec<u8>, v: u32) {
+    let mut v = v;
+    loop {
+        let byte = (v & 0x7F) as u8;
+        v >>= 7;
+        if v == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn s64(out: &mut Vec<u8>, v: i64) {
+    let mut v = v;
+    loop {
+        let byte = (v & 0x7F) as u8;
+        v >>= 7;
+        if (v == 0 && byte & 0x40 == 0) || (v == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn name(out: &mut Vec<u8>, name: &str) {
+    u32(out, name.len() as u32);
+    out.extend(name.as_bytes());
+}
+
+fn valtype(out: &mut Vec<u8>, valtype: &ValType) {
+    out.push(match valtype {
+        ValType::I32 => 0x7F,
+        ValType::I64 => 0x7E,
+        ValType::F32 => 0x7D,
+        ValType::F64 => 0x7C,
+        ValType::V128 => 0x7B,
+        ValType::FuncRef => 0x70,
+        ValType::ExternRef => 0x6F,
+    });
+}
+
+fn reftype(out: &mut Vec<u8>, reftype: &RefType) {
+    out.push(match reftype {
+        RefType::FuncRef => 0x70,
+        RefType::ExternRef => 0x6F,
+    });
+}
+
+fn functype(out: &mut Vec<u8>, FuncType(params, results): &FuncType) {
+    out.push(0x60);
+    vec(out, &params.0, valtype);
+    vec(out, &results.0, valtype);
+}
+
+fn limits(out: &mut Vec<u8>, limits: &Limits) {
+    match limits {
+        Limits::Min(min) => {
+            out.push(0x00);
+            u32(out, *min);
+        }
+        Limits::MinMax(min, max) => {
+            out.push(0x01);
+            u32(out, *min);
+            u32(out, *max);
+        }
+    }
+}
+
+fn table(out: &mut Vec<u8>, table: &Table) {
+    reftype(out, &table.reftype);
+    limits(out, &table.limits);
+}
+
+fn table_entry(out: &mut Vec<u8>, entry: &Table) {
+    match &entry.init {
+        Some(init) => {
+            out.extend([0x40, 0x00]);
+            table(out, entry);
+            expr(out, init);
+        }
+        None => table(out, entry),
+    }
+}
+
+fn memory(out: &mut Vec<u8>, memory: &Memory) {
+    let start = out.len();
+    limits(out, &memory.limits);
+    if memory.shared {
+        // Flags 0x02 and 0x03 are the shared variants of 0x00 and 0x01.
+        out[start] |= 0x02;
+    }
+}
+
+fn globaltype(out: &mut Vec<u8>, type_: &GlobalType) {
+    valtype(out, &type_.valtype);
+    out.push(match type_.mut_ {
+        Mut::Const => 0x00,
+        Mut::Var => 0x01,
+    });
+}
+
+fn import(out: &mut Vec<u8>, import: &Import) {
+    name(out, &import.module);
+    name(out, &import.name);
+    match &import.desc {
+        ImportDesc::Func(typeidx) => {
+            out.push(0x00);
+            u32(out, *typeidx);
+        }
+        ImportDesc::Table(t) => {
+            out.push(0x01);
+            table(out, t);
+        }
+        ImportDesc::Mem(m) => {
+            out.push(0x02);
+            memory(out, m);
+        }
+        ImportDesc::Global(g) => {
+            out.push(0x03);
+            globaltype(out, g);
+        }
+    }
+}
+
+fn export(out: &mut Vec<u8>, export: &Export) {
+    name(out, &export.name);
+    let (kind, idx) = match export.desc {
+        ExportDesc::Func(idx) => (0x00, idx),
+        ExportDesc::Table(idx) => (0x01, idx),
+        ExportDesc::Mem(idx) => (0x02, idx),
+        ExportDesc::Global(idx) => (0x03, idx),
+    };
+    out.push(kind);
+    u32(out, idx);
+}
+
+/// Always uses the expression encodings (flags 4 to 7), since the parser
+/// turns function indices into `ref.func` expressions anyway.
+fn elem(out: &mut Vec<u8>, elem: &Elem) {
+    match &elem.mode {
+        ElemMode::Active {
+            tableidx: 0,
+            offset,
+        } if elem.type_ == RefType::FuncRef => {
+            out.push(4);
+            expr(out, offset);
+        }
+        ElemMode::Active { tableidx, offset } => {
+            out.push(6);
+            u32(out, *tableidx);
+            expr(out, offset);
+            reftype(out, &elem.type_);
+        }
+        ElemMode::Passiv => {
+            out.push(5);
+            reftype(out, &elem.type_);
+        }
+        ElemMode::Declarative => {
+            out.push(7);
+            reftype(out, &elem.type_);
+        }
+    }
+    vec(out, &elem.init, expr);
+}
+
+fn code(out: &mut Vec<u8>, func: &Func) {
+    sized(out, |out| {
+        let mut locals: Vec<(u32, ValType)> = vec![];
+        for &local in &func.locals {
+            match locals.last_mut() {
+                Some((n, type_)) if *type_ == local => *n += 1,
+                _ => locals.push((1, local)),
+            }
+        }
+        vec(out, &locals, |out, (n, type_)| {
+            u32(out, *n);
+            valtype(out, type_);
+        });
+        expr(out, &func.body);
+    });
+}
+
+fn data(out: &mut Vec<u8>, data: &Data) {
+    match &data.mode {
+        DataMode::Active { memidx: 0, offset } => {
+            out.push(0);
+            expr(out, offset);
+        }
+        DataMode::Active { memidx, offset } => {
+            out.push(2);
+            u32(out, *memidx);
+            expr(out, offset);
+        }
+        DataMode::Passive => out.push(1),
+    }
+    vec(out, &data.init, |out, b| out.push(*b));
+}
+
+/// Writes `expr` followed by `end`, turning the flattened control flow back
+/// into structured instructions: the `pop_label` that ends a block becomes
+/// `end`, except at the end of a then branch, where it and the following
+/// `rjump` become `else`.
+fn expr(out: &mut Vec<u8>, expr: &Expr) {
+    let mut instrs = expr.0.iter().peekable();
+    while let Some(i) = instrs.next() {
+        match i {
+            Instr::PopLabel => {
+                if let Some(Instr::RJump(_)) = instrs.peek() {
+                    instrs.next();
+                    out.push(0x05);
+                } else {
+                    out.push(0x0B);
+                }
+            }
+            Instr::RJump(_) => unreachable!("rjump outside of an if"),
+            i => instr(out, i),
+        }
+    }
+    out.push(0x0B);
+}
+
+fn blocktype(out: &mut Vec<u8>, bt: &Block) {
+    match bt {
+        Block::Empty => out.push(0x40),
+        Block::ValType(t) => valtype(out, t),
+        Block::TypeIdx(idx) => s64(out, i64::from(*idx)),
+    }
+}
+
+fn memarg(out: &mut Vec<u8>, memarg: &MemArg) {
+    u32(out, memarg.align);
+    u32(out, memarg.offset);
+}
+
+fn prefixed(out: &mut Vec<u8>, prefix: u8, op: u32) {
+    out.push(prefix);
+    u32(out, op);
+}
+
+/// The opcode table of `Parser::instr`, in reverse. Instructions without
+/// immediates come from the table the parser shares.
+fn instr(out: &mut Vec<u8>, instr: &Instr) {
+    match instr.plain_opcode() {
+        Some((op, None)) => return out.push(op),
+        Some((prefix, Some(sub))) => return prefixed(out, prefix, sub),
+        None => {}
+    }
+    match instr {
+        Instr::I32Load(m) => {
+            out.push(0x28);
+            memarg(out, m);
+        }
+        Instr::I64Load(m) => {
+            out.push(0x29);
+            memarg(out, m);
+        }
+        Instr::F32Load(m) => {
+            out.push(0x2A);
+            memarg(out, m);
+        }
+        Instr::F64Load(m) => {
+            out.push(0x2B);
+            memarg(out, m);
+        }
+        Instr::I32Load8S(m) => {
+            out.push(0x2C);
+            memarg(out, m);
+        }
+        Instr::I32Load8U(m) => {
+            out.push(0x2D);
+            memarg(out, m);
+        }
+        Instr::I32Load16S(m) => {
+            out.push(0x2E);
+            memarg(out, m);
+        }
+        Instr::I32Load16U(m) => {
+            out.push(0x2F);
+            memarg(out, m);
+        }
+        Instr::I64Load8S(m) => {
+            out.push(0x30);
+            memarg(out, m);
+        }
+        Instr::I64Load8U(m) => {
+            out.push(0x31);
+            memarg(out, m);
+        }
+        Instr::I64Load16S(m) => {
+            out.push(0x32);
+            memarg(out, m);
+        }
+        Instr::I64Load16U(m) => {
+            out.push(0x33);
+            memarg(out, m);
+        }
+        Instr::I64Load32S(m) => {
+            out.push(0x34);
+            memarg(out, m);
+        }
+        Instr::I64Load32U(m) => {
+            out.push(0x35);
+            memarg(out, m);
+        }
+        Instr::I32Store(m) => {
+            out.push(0x36);
+            memarg(out, m);
+        }
+        Instr::I64Store(m) => {
+            out.push(0x37);
+            memarg(out, m);
+        }
+        Instr::F32Store(m) => {
+            out.push(0x38);
+            memarg(out, m);
+        }
+        Instr::F64Store(m) => {
+            out.push(0x39);
+            memarg(out, m);
+        }
+        Instr::I32Store8(m) => {
+            out.push(0x3A);
+            memarg(out, m);
+        }
+        Instr::I32Store16(m) => {
+            out.push(0x3B);
+            memarg(out, m);
+        }
+        Instr::I64Store8(m) => {
+            out.push(0x3C);
+            memarg(out, m);
+        }
+        Instr::I64Store16(m) => {
+            out.push(0x3D);
+            memarg(out, m);
+        }
+        Instr::I64Store32(m) => {
+            out.push(0x3E);
+            memarg(out, m);
+        }
+        Instr::Block { bt, .. } => {
+            out.push(0x02);
+            blocktype(out, bt);
+        }
+        Instr::Loop { bt } => {
+            out.push(0x03);
+            blocktype(out, bt);
+        }
+        Instr::If { bt, .. } => {
+            out.push(0x04);
+            blocktype(out, bt);
+        }
+        Instr::Br(l) => {
+            out.push(0x0C);
+            u32(out, *l);
+        }
+        Instr::BrIf(l) => {
+            out.push(0x0D);
+            u32(out, *l);
+        }
+        Instr::BrTable { indexs, default } => {
+            out.push(0x0E);
+            vec(out, indexs, |out, l| u32(out, *l));
+            u32(out, *default);
+        }
+        Instr::Call(x) => {
+            out.push(0x10);
+            u32(out, *x);
+        }
+        Instr::CallIndirect(typeidx, tableidx) => {
+            out.push(0x11);
+            u32(out, *typeidx);
+            u32(out, *tableidx);
+        }
+        Instr::RefNull(t) => {
+            out.push(0xD0);
+            reftype(out, t);
+        }
+        Instr::RefFunc(x) => {
+            out.push(0xD2);
+            u32(out, *x);
+        }
+        Instr::LocalGet(x) => {
+            out.push(0x20);
+            u32(out, *x);
+        }
+        Instr::LocalSet(x) => {
+            out.push(0x21);
+            u32(out, *x);
+        }
+        Instr::LocalTee(x) => {
+            out.push(0x22);
+            u32(out, *x);
+        }
+        Instr::GlobalGet(x) => {
+            out.push(0x23);
+            u32(out, *x);
+        }
+        Instr::GlobalSet(x) => {
+            out.push(0x24);
+            u32(out, *x);
+        }
+        Instr::TableGet(x) => {
+            out.push(0x25);
+            u32(out, *x);
+        }
+        Instr::TableSet(x) => {
+            out.push(0x26);
+            u32(out, *x);
+        }
+        Instr::MemorySize(x) => {
+            out.push(0x3F);
+            u32(out, *x);
+        }
+        Instr::MemoryGrow(x) => {
+            out.push(0x40);
+            u32(out, *x);
+        }
+        Instr::I32Const(v) => {
+            out.push(0x41);
+            s64(out, i64::from(*v));
+        }
+        Instr::I64Const(v) => {
+            out.push(0x42);
+            s64(out, *v);
+        }
+        Instr::F32Const(v) => {
+            out.push(0x43);
+            out.extend(v.to_le_bytes());
+        }
+        Instr::F64Const(v) => {
+            out.push(0x44);
+            out.extend(v.to_le_bytes());
+        }
+        Instr::MemoryInit(x) => {
+            prefixed(out, 0xFC, 8);
+            u32(out, *x);
+            out.push(0x00);
+        }
+        Instr::DataDrop(x) => {
+            prefixed(out, 0xFC, 9);
+            u32(out, *x);
+        }
+        Instr::MemoryCopy => {
+            prefixed(out, 0xFC, 10);
+            out.extend([0x00, 0x00]);
+        }
+        Instr::MemoryFill => {
+            prefixed(out, 0xFC, 11);
+            out.push(0x00);
+        }
+        Instr::TableInit(elemidx, tableidx) => {
+            prefixed(out, 0xFC, 12);
+            u32(out, *elemidx);
+            u32(out, *tableidx);
+        }
+        Instr::ElemDrop(x) => {
+            prefixed(out, 0xFC, 13);
+            u32(out, *x);
+        }
+        Instr::TableCopy(dst, src) => {
+            prefixed(out, 0xFC, 14);
+            u32(out, *dst);
+            u32(out, *src);
+        }
+        Instr::TableGrow(x) => {
+            prefixed(out, 0xFC, 15);
+            u32(out, *x);
+        }
+        Instr::TableSize(x) => {
+            prefixed(out, 0xFC, 16);
+            u32(out, *x);
+        }
+        Instr::TableFill(x) => {
+            prefixed(out, 0xFC, 17);
+            u32(out, *x);
+        }
+        Instr::V128Load(m) => {
+            prefixed(out, 0xFD, 0);
+            memarg(out, m);
+        }
+        Instr::V128Store(m) => {
+            prefixed(out, 0xFD, 11);
+            memarg(out, m);
+        }
+        Instr::V128Const(v) => {
+            prefixed(out, 0xFD, 12);
+            out.extend(v);
+        }
+        Instr::I8x16ExtractLaneS(l) => lane(out, 21, *l),
+        Instr::I8x16ExtractLaneU(l) => lane(out, 22, *l),
+        Instr::I16x8ExtractLaneS(l) => lane(out, 24, *l),
+        Instr::I16x8ExtractLaneU(l) => lane(out, 25, *l),
+        Instr::I32x4ExtractLane(l) => lane(out, 27, *l),
+        Instr::I64x2ExtractLane(l) => lane(out, 29, *l),
+        Instr::F32x4ExtractLane(l) => lane(out, 31, *l),
+        Instr::F64x2ExtractLane(l) => lane(out, 33, *l),
+        Instr::MemoryAtomicNotify(m) => {
+            prefixed(out, 0xFE, 0);
+            memarg(out, m);
+        }
+        Instr::MemoryAtomicWait32(m) => {
+            prefixed(out, 0xFE, 1);
+            memarg(out, m);
+        }
+        Instr::MemoryAtomicWait64(m) => {
+            prefixed(out, 0xFE, 2);
+            memarg(out, m);
+        }
+        Instr::AtomicFence => {
+            prefixed(out, 0xFE, 3);
+            out.push(0x00);
+        }
+        Instr::PopLabel | Instr::RJump(_) => unreachable!("handled by expr"),
+        _ => unreachable!("encoded from the shared opcode table"),
+    }
+}
+
+fn lane(out: &mut Vec<u8>, op: u32, lane: LaneIdx) {
+    prefixed(out, 0xFD, op);
+    out.push(lane);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, instr};
+    use crate::binary::Instr;
+    use crate::loader::parser::Parser;
+    use crate::test_support::harness;
+    use crate::tests::wat2wasm;
+    use crate::Value;
+
+    #[test]
+    fn round_trip() {
+        let wasm = wat2wasm(
+            r#"(module
+                (type $t (func (param i32) (result i32)))
+                (import "env" "f" (func $imp (type $t)))
+                (import "env" "g" (global i64))
+                (import "env" "m" (memory 1 2 shared))
+                (table $tab 2 8 funcref)
+                (table externref (elem (ref.null extern)))
+                (global $g (mut f64) (f64.const -1.5))
+                (func $f (export "f") (param i32) (result i32) (local i64 i64 f32 v128)
+                  (block $out (result i32)
+                    (loop $l
+                      (br_table $out $l (i32.const 7) (local.get 0))))
+                  (if (result i32) (i32.eqz (local.get 0))
+                    (then (i32.const -129))
+                    (else (call_indirect $tab (type $t) (i32.const 1) (i32.const 0))))
+                  (if (i32.const 0) (then nop))
+                  (memory.init $passive (i32.const 0) (i32.const 0) (i32.const 0))
+                  (data.drop $passive)
+                  (table.copy $tab $tab (i32.const 0) (i32.const 0) (i32.const 0))
+                  (local.set 4 (v128.const i32x4 1 2 3 4))
+                  (i32x4.extract_lane 3 (local.get 4))
+                  (i64.store offset=8 (i32.const 0) (i64.const -9223372036854775808))
+                  (drop (f32.const 3.25))
+                  i32.add
+                  i32.add)
+                (func $start)
+                (start $start)
+                (elem (i32.const 0) $f $imp)
+                (elem $e funcref (ref.func $f) (ref.null func))
+                (elem declare func $start)
+                (data (i32.const 16) "hello")
+                (data $passive "\00\01\ff"))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        assert!(!module.func_names.is_empty());
+
+        let encoded = encode(&module);
+        assert_eq!(Parser::new(&encoded).module(), Ok(module));
+    }
+
+    #[test]
+    fn plain_instrs_round_trip() {
+        for i in Instr::plain_instrs() {
+            let mut out = vec![];
+            instr(&mut out, &i);
+            assert_eq!(Parser::new(&out).instr(), Ok(vec![i]));
+        }
+    }
+
+    #[test]
+    fn nan_payload_round_trip() {
+        // Signaling NaNs, which a round trip through text would quiet.
+        let (f32_bits, f64_bits) = (0x7FA0_0001, 0x7FF4_0000_0000_0001);
+        let wasm = wat2wasm(r#"(module (func (result f32 f64) f32.const 0 f64.const 0))"#).unwrap();
+        let mut module = Parser::new(&wasm).module().unwrap();
+        module.funcs[0].body.0[..2].clone_from_slice(&[
+            Instr::F32Const(f32::from_bits(f32_bits)),
+            Instr::F64Const(f64::from_bits(f64_bits)),
+        ]);
+
+        let parsed = Parser::new(&encode(&module)).module().unwrap();
+        match parsed.funcs[0].body.0[..2] {
+            [Instr::F32Const(x), Instr::F64Const(y)] => {
+                assert_eq!((x.to_bits(), y.to_bits()), (f32_bits, f64_bits));
+            }
+            ref body => panic!("{:?}", body),
+        }
+    }
+
+    #[test]
+    fn patch_data() {
+        let wasm = wat2wasm(
+            r#"(module
+                (memory 1)
+                (data (i32.const 0) "\01")
+                (func (export "get") (result i32) (i32.load8_u (i32.const 0))))"#,
+        )
+        .unwrap();
+        let mut module = Parser::new(&wasm).module().unwrap();
+        module.datas[0].init = vec![42];
+
+        let (mut runtime, mut store, mut env) = harness(&encode(&module));
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "get", vec![]),
+            Ok(vec![Value::I32(42)])
+        );
+    }
+}
@@ -1,9 +1,10 @@
 use super::{
-    module::{ElemIdx, FuncIdx, GlobalIdx, LabelIdx, LocalIdx, TableIdx, TypeIdx},
+    module::{ElemIdx, FuncIdx, GlobalIdx, LabelIdx, LaneIdx, LocalIdx, MemIdx, TableIdx, TypeIdx},
     types::{RefType, ValType},
 };
 #[cfg(not(feature = "std"))]
 use crate::lib::*;
+use core::fmt::{self, Write};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Block {
@@ -25,6 +26,93 @@ impl Expr {
     pub fn new(instrs: Vec<Instr>) -> Self {
         Self(instrs)
     }
+
+    /// Number of instructions, counting the `PopLabel` and `RJump` the
+    /// loader inserts for control flow.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether any instruction, nested ones included, satisfies `pred`.
+    pub fn contains(&self, pred: impl Fn(&Instr) -> bool) -> bool {
+        self.0.iter().any(pred)
+    }
+
+    /// Whether this is a constant expression: a single constant,
+    /// `ref.null`, `ref.func` or `global.get`.
+    pub fn is_valid_const(&self) -> bool {
+        matches!(
+            self.0[..],
+            [Instr::I32Const(_)
+                | Instr::I64Const(_)
+                | Instr::F32Const(_)
+                | Instr::F64Const(_)
+                | Instr::V128Const(_)
+                | Instr::RefNull(_)
+                | Instr::RefFunc(_)
+                | Instr::GlobalGet(_)]
+        )
+    }
+
+    /// Prints the instructions one per line, recovering `block`/`loop`/`if`
+    /// nesting from the flattened stream and indenting their bodies.
+    pub fn disassemble(&self) -> String {
+        // For each open block, the position of the `PopLabel` that ends the
+        // then branch of an `if` with an `else`.
+        let mut open: Vec<Option<usize>> = vec![];
+        let mut out = String::new();
+        let mut pc = 0;
+        while pc < self.0.len() {
+            let instr = &self.0[pc];
+            match instr {
+                Instr::PopLabel if open.last() == Some(&Some(pc)) => {
+                    // The then branch ends with `PopLabel` followed by
+                    // `RJump` over the else branch.
+                    *open.last_mut().unwrap() = None;
+                    writeln!(out, "{:indent$}else", "", indent = (open.len() - 1) * 2).unwrap();
+                    pc += 2;
+                    continue;
+                }
+                Instr::PopLabel if !open.is_empty() => {
+                    open.pop();
+                    writeln!(out, "{:indent$}end", "", indent = open.len() * 2).unwrap();
+                }
+                _ => writeln!(out, "{:indent$}{}", "", instr, indent = open.len() * 2).unwrap(),
+            }
+            match instr {
+                Instr::Block { .. } | Instr::Loop { .. } => open.push(None),
+                Instr::If { else_offset, .. } => {
+                    open.push(else_offset.map(|else_offset| pc + else_offset - 2))
+                }
+                _ => {}
+            }
+            pc += 1;
+        }
+        out
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Block::Empty => Ok(()),
+            Block::ValType(t) => write!(f, " (result {})", t),
+            Block::TypeIdx(x) => write!(f, " (type {})", x),
+        }
+    }
+}
+
+impl fmt::Display for MemArg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.offset != 0 {
+            write!(f, " offset={}", self.offset)?;
+        }
+        write!(f, " align={}", 1u64 << self.align)
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -99,8 +187,8 @@ pub enum Instr {
     I64Store8(MemArg),
     I64Store16(MemArg),
     I64Store32(MemArg),
-    MemorySize,
-    MemoryGrow,
+    MemorySize(MemIdx),
+    MemoryGrow(MemIdx),
     MemoryInit(u32),
     DataDrop(u32),
     MemoryCopy,
@@ -258,7 +346,602 @@ pub enum Instr {
     I64TruncSatF64S,
     I64TruncSatF64U,
 
+    // Vector Instructions
+    V128Load(MemArg),
+    V128Store(MemArg),
+    V128Const([u8; 16]),
+
+    I8x16Splat,
+    I16x8Splat,
+    I32x4Splat,
+    I64x2Splat,
+    F32x4Splat,
+    F64x2Splat,
+
+    I8x16ExtractLaneS(LaneIdx),
+    I8x16ExtractLaneU(LaneIdx),
+    I16x8ExtractLaneS(LaneIdx),
+    I16x8ExtractLaneU(LaneIdx),
+    I32x4ExtractLane(LaneIdx),
+    I64x2ExtractLane(LaneIdx),
+    F32x4ExtractLane(LaneIdx),
+    F64x2ExtractLane(LaneIdx),
+
+    I32x4Add,
+    I32x4Sub,
+    I32x4Mul,
+    F32x4Add,
+    F32x4Sub,
+    F32x4Mul,
+    F32x4Div,
+
+    // Atomic Instructions
+    MemoryAtomicNotify(MemArg),
+    MemoryAtomicWait32(MemArg),
+    MemoryAtomicWait64(MemArg),
+    AtomicFence,
+
     // Pseudo Instructions
     RJump(usize),
     PopLabel,
 }
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instr::Block { bt, .. } => write!(f, "block{}", bt),
+            Instr::Loop { bt } => write!(f, "loop{}", bt),
+            Instr::If { bt, .. } => write!(f, "if{}", bt),
+            Instr::Br(l) => write!(f, "br {}", l),
+            Instr::BrIf(l) => write!(f, "br_if {}", l),
+            Instr::BrTable { indexs, default } => {
+                write!(f, "br_table")?;
+                for l in indexs {
+                    write!(f, " {}", l)?;
+                }
+                write!(f, " {}", default)
+            }
+            Instr::Call(x) => write!(f, "call {}", x),
+            Instr::CallIndirect(y, x) => write!(f, "call_indirect {} (type {})", x, y),
+            Instr::RefNull(t) => write!(
+                f,
+                "ref.null {}",
+                match t {
+                    RefType::FuncRef => "func",
+                    RefType::ExternRef => "extern",
+                }
+            ),
+            Instr::RefFunc(x) => write!(f, "ref.func {}", x),
+            Instr::LocalGet(x) => write!(f, "local.get {}", x),
+            Instr::LocalSet(x) => write!(f, "local.set {}", x),
+            Instr::LocalTee(x) => write!(f, "local.tee {}", x),
+            Instr::GlobalGet(x) => write!(f, "global.get {}", x),
+            Instr::GlobalSet(x) => write!(f, "global.set {}", x),
+            Instr::TableGet(x) => write!(f, "table.get {}", x),
+            Instr::TableSet(x) => write!(f, "table.set {}", x),
+            Instr::ElemDrop(x) => write!(f, "elem.drop {}", x),
+            Instr::TableGrow(x) => write!(f, "table.grow {}", x),
+            Instr::TableSize(x) => write!(f, "table.size {}", x),
+            Instr::TableFill(x) => write!(f, "table.fill {}", x),
+            Instr::MemoryInit(x) => write!(f, "memory.init {}", x),
+            Instr::DataDrop(x) => write!(f, "data.drop {}", x),
+            Instr::I32Const(x) => write!(f, "i32.const {}", x),
+            Instr::I64Const(x) => write!(f, "i64.const {}", x),
+            Instr::TableInit(y, x) => write!(f, "table.init {} {}", x, y),
+            Instr::TableCopy(x, y) => write!(f, "table.copy {} {}", x, y),
+            Instr::MemorySize(0) => write!(f, "memory.size"),
+            Instr::MemorySize(x) => write!(f, "memory.size {}", x),
+            Instr::MemoryGrow(0) => write!(f, "memory.grow"),
+            Instr::MemoryGrow(x) => write!(f, "memory.grow {}", x),
+            Instr::F32Const(z) if z.is_nan() => write!(
+                f,
+                "f32.const {}nan:0x{:x}",
+                if z.is_sign_negative() { "-" } else { "" },
+                z.to_bits() & 0x7F_FFFF
+            ),
+            Instr::F32Const(z) => write!(f, "f32.const {}", z),
+            Instr::F64Const(z) if z.is_nan() => write!(
+                f,
+                "f64.const {}nan:0x{:x}",
+                if z.is_sign_negative() { "-" } else { "" },
+                z.to_bits() & 0xF_FFFF_FFFF_FFFF
+            ),
+            Instr::F64Const(z) => write!(f, "f64.const {}", z),
+            Instr::RJump(r) => write!(f, "rjump +{}", r),
+            Instr::PopLabel => write!(f, "pop_label"),
+            Instr::I32Load(m) => write!(f, "i32.load{}", m),
+            Instr::I64Load(m) => write!(f, "i64.load{}", m),
+            Instr::F32Load(m) => write!(f, "f32.load{}", m),
+            Instr::F64Load(m) => write!(f, "f64.load{}", m),
+            Instr::I32Load8S(m) => write!(f, "i32.load8_s{}", m),
+            Instr::I32Load8U(m) => write!(f, "i32.load8_u{}", m),
+            Instr::I32Load16S(m) => write!(f, "i32.load16_s{}", m),
+            Instr::I32Load16U(m) => write!(f, "i32.load16_u{}", m),
+            Instr::I64Load8S(m) => write!(f, "i64.load8_s{}", m),
+            Instr::I64Load8U(m) => write!(f, "i64.load8_u{}", m),
+            Instr::I64Load16S(m) => write!(f, "i64.load16_s{}", m),
+            Instr::I64Load16U(m) => write!(f, "i64.load16_u{}", m),
+            Instr::I64Load32S(m) => write!(f, "i64.load32_s{}", m),
+            Instr::I64Load32U(m) => write!(f, "i64.load32_u{}", m),
+            Instr::I32Store(m) => write!(f, "i32.store{}", m),
+            Instr::I64Store(m) => write!(f, "i64.store{}", m),
+            Instr::F32Store(m) => write!(f, "f32.store{}", m),
+            Instr::F64Store(m) => write!(f, "f64.store{}", m),
+            Instr::I32Store8(m) => write!(f, "i32.store8{}", m),
+            Instr::I32Store16(m) => write!(f, "i32.store16{}", m),
+            Instr::I64Store8(m) => write!(f, "i64.store8{}", m),
+            Instr::I64Store16(m) => write!(f, "i64.store16{}", m),
+            Instr::I64Store32(m) => write!(f, "i64.store32{}", m),
+            Instr::Unreachable => write!(f, "unreachable"),
+            Instr::Nop => write!(f, "nop"),
+            Instr::Return => write!(f, "return"),
+            Instr::RefIsNull => write!(f, "ref.is_null"),
+            Instr::Drop => write!(f, "drop"),
+            Instr::Select => write!(f, "select"),
+            Instr::MemoryCopy => write!(f, "memory.copy"),
+            Instr::MemoryFill => write!(f, "memory.fill"),
+            Instr::I32Eqz => write!(f, "i32.eqz"),
+            Instr::I32Eq => write!(f, "i32.eq"),
+            Instr::I32Ne => write!(f, "i32.ne"),
+            Instr::I32LtS => write!(f, "i32.lt_s"),
+            Instr::I32LtU => write!(f, "i32.lt_u"),
+            Instr::I32GtS => write!(f, "i32.gt_s"),
+            Instr::I32GtU => write!(f, "i32.gt_u"),
+            Instr::I32LeS => write!(f, "i32.le_s"),
+            Instr::I32LeU => write!(f, "i32.le_u"),
+            Instr::I32GeS => write!(f, "i32.ge_s"),
+            Instr::I32GeU => write!(f, "i32.ge_u"),
+            Instr::I64Eqz => write!(f, "i64.eqz"),
+            Instr::I64Eq => write!(f, "i64.eq"),
+            Instr::I64Ne => write!(f, "i64.ne"),
+            Instr::I64LtS => write!(f, "i64.lt_s"),
+            Instr::I64LtU => write!(f, "i64.lt_u"),
+            Instr::I64GtS => write!(f, "i64.gt_s"),
+            Instr::I64GtU => write!(f, "i64.gt_u"),
+            Instr::I64LeS => write!(f, "i64.le_s"),
+            Instr::I64LeU => write!(f, "i64.le_u"),
+            Instr::I64GeS => write!(f, "i64.ge_s"),
+            Instr::I64GeU => write!(f, "i64.ge_u"),
+            Instr::F32Eq => write!(f, "f32.eq"),
+            Instr::F32Ne => write!(f, "f32.ne"),
+            Instr::F32Lt => write!(f, "f32.lt"),
+            Instr::F32Gt => write!(f, "f32.gt"),
+            Instr::F32Le => write!(f, "f32.le"),
+            Instr::F32Ge => write!(f, "f32.ge"),
+            Instr::F64Eq => write!(f, "f64.eq"),
+            Instr::F64Ne => write!(f, "f64.ne"),
+            Instr::F64Lt => write!(f, "f64.lt"),
+            Instr::F64Gt => write!(f, "f64.gt"),
+            Instr::F64Le => write!(f, "f64.le"),
+            Instr::F64Ge => write!(f, "f64.ge"),
+            Instr::I32Clz => write!(f, "i32.clz"),
+            Instr::I32Ctz => write!(f, "i32.ctz"),
+            Instr::I32Popcnt => write!(f, "i32.popcnt"),
+            Instr::I32Add => write!(f, "i32.add"),
+            Instr::I32Sub => write!(f, "i32.sub"),
+            Instr::I32Mul => write!(f, "i32.mul"),
+            Instr::I32DivS => write!(f, "i32.div_s"),
+            Instr::I32DivU => write!(f, "i32.div_u"),
+            Instr::I32RemS => write!(f, "i32.rem_s"),
+            Instr::I32RemU => write!(f, "i32.rem_u"),
+            Instr::I32And => write!(f, "i32.and"),
+            Instr::I32Or => write!(f, "i32.or"),
+            Instr::I32Xor => write!(f, "i32.xor"),
+            Instr::I32Shl => write!(f, "i32.shl"),
+            Instr::I32ShrS => write!(f, "i32.shr_s"),
+            Instr::I32ShrU => write!(f, "i32.shr_u"),
+            Instr::I32RotL => write!(f, "i32.rotl"),
+            Instr::I32RotR => write!(f, "i32.rotr"),
+            Instr::I64Clz => write!(f, "i64.clz"),
+            Instr::I64Ctz => write!(f, "i64.ctz"),
+            Instr::I64Popcnt => write!(f, "i64.popcnt"),
+            Instr::I64Add => write!(f, "i64.add"),
+            Instr::I64Sub => write!(f, "i64.sub"),
+            Instr::I64Mul => write!(f, "i64.mul"),
+            Instr::I64DivS => write!(f, "i64.div_s"),
+            Instr::I64DivU => write!(f, "i64.div_u"),
+            Instr::I64RemS => write!(f, "i64.rem_s"),
+            Instr::I64RemU => write!(f, "i64.rem_u"),
+            Instr::I64And => write!(f, "i64.and"),
+            Instr::I64Or => write!(f, "i64.or"),
+            Instr::I64Xor => write!(f, "i64.xor"),
+            Instr::I64Shl => write!(f, "i64.shl"),
+            Instr::I64ShrS => write!(f, "i64.shr_s"),
+            Instr::I64ShrU => write!(f, "i64.shr_u"),
+            Instr::I64RotL => write!(f, "i64.rotl"),
+            Instr::I64RotR => write!(f, "i64.rotr"),
+            Instr::F32Abs => write!(f, "f32.abs"),
+            Instr::F32Neg => write!(f, "f32.neg"),
+            Instr::F32Ceil => write!(f, "f32.ceil"),
+            Instr::F32Floor => write!(f, "f32.floor"),
+            Instr::F32Trunc => write!(f, "f32.trunc"),
+            Instr::F32Nearest => write!(f, "f32.nearest"),
+            Instr::F32Sqrt => write!(f, "f32.sqrt"),
+            Instr::F32Add => write!(f, "f32.add"),
+            Instr::F32Sub => write!(f, "f32.sub"),
+            Instr::F32Mul => write!(f, "f32.mul"),
+            Instr::F32Div => write!(f, "f32.div"),
+            Instr::F32Min => write!(f, "f32.min"),
+            Instr::F32Max => write!(f, "f32.max"),
+            Instr::F32Copysign => write!(f, "f32.copysign"),
+            Instr::F64Abs => write!(f, "f64.abs"),
+            Instr::F64Neg => write!(f, "f64.neg"),
+            Instr::F64Ceil => write!(f, "f64.ceil"),
+            Instr::F64Floor => write!(f, "f64.floor"),
+            Instr::F64Trunc => write!(f, "f64.trunc"),
+            Instr::F64Nearest => write!(f, "f64.nearest"),
+            Instr::F64Sqrt => write!(f, "f64.sqrt"),
+            Instr::F64Add => write!(f, "f64.add"),
+            Instr::F64Sub => write!(f, "f64.sub"),
+            Instr::F64Mul => write!(f, "f64.mul"),
+            Instr::F64Div => write!(f, "f64.div"),
+            Instr::F64Min => write!(f, "f64.min"),
+            Instr::F64Max => write!(f, "f64.max"),
+            Instr::F64Copysign => write!(f, "f64.copysign"),
+            Instr::I32WrapI64 => write!(f, "i32.wrap_i64"),
+            Instr::I32TruncF32S => write!(f, "i32.trunc_f32_s"),
+            Instr::I32TruncF32U => write!(f, "i32.trunc_f32_u"),
+            Instr::I32TruncF64S => write!(f, "i32.trunc_f64_s"),
+            Instr::I32TruncF64U => write!(f, "i32.trunc_f64_u"),
+            Instr::I64ExtendI32S => write!(f, "i64.extend_i32_s"),
+            Instr::I64ExtendI32U => write!(f, "i64.extend_i32_u"),
+            Instr::I64TruncF32S => write!(f, "i64.trunc_f32_s"),
+            Instr::I64TruncF32U => write!(f, "i64.trunc_f32_u"),
+            Instr::I64TruncF64S => write!(f, "i64.trunc_f64_s"),
+            Instr::I64TruncF64U => write!(f, "i64.trunc_f64_u"),
+            Instr::F32ConvertI32S => write!(f, "f32.convert_i32_s"),
+            Instr::F32ConvertI32U => write!(f, "f32.convert_i32_u"),
+            Instr::F32ConvertI64S => write!(f, "f32.convert_i64_s"),
+            Instr::F32ConvertI64U => write!(f, "f32.convert_i64_u"),
+            Instr::F32DemoteF64 => write!(f, "f32.demote_f64"),
+            Instr::F64ConvertI32S => write!(f, "f64.convert_i32_s"),
+            Instr::F64ConvertI32U => write!(f, "f64.convert_i32_u"),
+            Instr::F64ConvertI64S => write!(f, "f64.convert_i64_s"),
+            Instr::F64ConvertI64U => write!(f, "f64.convert_i64_u"),
+            Instr::F64PromoteF32 => write!(f, "f64.promote_f32"),
+            Instr::I32ReinterpretF32 => write!(f, "i32.reinterpret_f32"),
+            Instr::I64ReinterpretF64 => write!(f, "i64.reinterpret_f64"),
+            Instr::F32ReinterpretI32 => write!(f, "f32.reinterpret_i32"),
+            Instr::F64ReinterpretI64 => write!(f, "f64.reinterpret_i64"),
+            Instr::I32Extend8S => write!(f, "i32.extend8_s"),
+            Instr::I32Extend16S => write!(f, "i32.extend16_s"),
+            Instr::I64Extend8S => write!(f, "i64.extend8_s"),
+            Instr::I64Extend16S => write!(f, "i64.extend16_s"),
+            Instr::I64Extend32S => write!(f, "i64.extend32_s"),
+            Instr::I32TruncSatF32S => write!(f, "i32.trunc_sat_f32_s"),
+            Instr::I32TruncSatF32U => write!(f, "i32.trunc_sat_f32_u"),
+            Instr::I32TruncSatF64S => write!(f, "i32.trunc_sat_f64_s"),
+            Instr::I32TruncSatF64U => write!(f, "i32.trunc_sat_f64_u"),
+            Instr::I64TruncSatF32S => write!(f, "i64.trunc_sat_f32_s"),
+            Instr::I64TruncSatF32U => write!(f, "i64.trunc_sat_f32_u"),
+            Instr::I64TruncSatF64S => write!(f, "i64.trunc_sat_f64_s"),
+            Instr::I64TruncSatF64U => write!(f, "i64.trunc_sat_f64_u"),
+            Instr::V128Load(m) => write!(f, "v128.load{}", m),
+            Instr::V128Store(m) => write!(f, "v128.store{}", m),
+            Instr::V128Const(v) => {
+                write!(f, "v128.const i32x4")?;
+                for lane in v.chunks_exact(4) {
+                    let lane = u32::from_le_bytes([lane[0], lane[1], lane[2], lane[3]]);
+                    write!(f, " {:#010x}", lane)?;
+                }
+                Ok(())
+            }
+            Instr::I8x16Splat => write!(f, "i8x16.splat"),
+            Instr::I16x8Splat => write!(f, "i16x8.splat"),
+            Instr::I32x4Splat => write!(f, "i32x4.splat"),
+            Instr::I64x2Splat => write!(f, "i64x2.splat"),
+            Instr::F32x4Splat => write!(f, "f32x4.splat"),
+            Instr::F64x2Splat => write!(f, "f64x2.splat"),
+            Instr::I8x16ExtractLaneS(l) => write!(f, "i8x16.extract_lane_s {}", l),
+            Instr::I8x16ExtractLaneU(l) => write!(f, "i8x16.extract_lane_u {}", l),
+            Instr::I16x8ExtractLaneS(l) => write!(f, "i16x8.extract_lane_s {}", l),
+            Instr::I16x8ExtractLaneU(l) => write!(f, "i16x8.extract_lane_u {}", l),
+            Instr::I32x4ExtractLane(l) => write!(f, "i32x4.extract_lane {}", l),
+            Instr::I64x2ExtractLane(l) => write!(f, "i64x2.extract_lane {}", l),
+            Instr::F32x4ExtractLane(l) => write!(f, "f32x4.extract_lane {}", l),
+            Instr::F64x2ExtractLane(l) => write!(f, "f64x2.extract_lane {}", l),
+            Instr::I32x4Add => write!(f, "i32x4.add"),
+            Instr::I32x4Sub => write!(f, "i32x4.sub"),
+            Instr::I32x4Mul => write!(f, "i32x4.mul"),
+            Instr::F32x4Add => write!(f, "f32x4.add"),
+            Instr::F32x4Sub => write!(f, "f32x4.sub"),
+            Instr::F32x4Mul => write!(f, "f32x4.mul"),
+            Instr::F32x4Div => write!(f, "f32x4.div"),
+            Instr::MemoryAtomicNotify(m) => write!(f, "memory.atomic.notify{}", m),
+            Instr::MemoryAtomicWait32(m) => write!(f, "memory.atomic.wait32{}", m),
+            Instr::MemoryAtomicWait64(m) => write!(f, "memory.atomic.wait64{}", m),
+            Instr::AtomicFence => write!(f, "atomic.fence"),
+        }
+    }
+}
+
+/// Generates both directions of the opcode table for the instructions
+/// without immediates, so that `Parser::instr` and `encode` cannot disagree.
+/// The first group holds single-byte opcodes, the second prefixed ones.
+macro_rules! plain_instrs {
+    (
+        [$($op:literal => $name:ident,)*]
+        [$($prefix:literal $sub:literal => $pname:ident,)*]
+    ) => {
+        impl Instr {
+            /// The instruction without immediates encoded by `op`.
+            pub(crate) fn from_opcode(op: u8) -> Option<Instr> {
+                match op {
+                    $($op => Some(Instr::$name),)*
+                    _ => None,
+                }
+            }
+
+            /// The instruction without immediates encoded by `sub` after
+            /// the `prefix` byte.
+            pub(crate) fn from_prefixed_opcode(prefix: u8, sub: u32) -> Option<Instr> {
+                match (prefix, sub) {
+                    $(($prefix, $sub) => Some(Instr::$pname),)*
+                    _ => None,
+                }
+            }
+
+            /// The opcode of an instruction without immediates, with its
+            /// subopcode if it is prefixed.
+            pub(crate) fn plain_opcode(&self) -> Option<(u8, Option<u32>)> {
+                match self {
+                    $(Instr::$name => Some(($op, None)),)*
+                    $(Instr::$pname => Some(($prefix, Some($sub))),)*
+                    _ => None,
+                }
+            }
+
+            #[cfg(test)]
+            pub(crate) fn plain_instrs() -> Vec<Instr> {
+                vec![$(Instr::$name,)* $(Instr::$pname,)*]
+            }
+        }
+    };
+}
+
+plain_instrs! {
+    [
+        0x00 => Unreachable,
+        0x01 => Nop,
+        0x0F => Return,
+        0xD1 => RefIsNull,
+        0x1A => Drop,
+        0x1B => Select,
+        0x45 => I32Eqz,
+        0x46 => I32Eq,
+        0x47 => I32Ne,
+        0x48 => I32LtS,
+        0x49 => I32LtU,
+        0x4A => I32GtS,
+        0x4B => I32GtU,
+        0x4C => I32LeS,
+        0x4D => I32LeU,
+        0x4E => I32GeS,
+        0x4F => I32GeU,
+        0x50 => I64Eqz,
+        0x51 => I64Eq,
+        0x52 => I64Ne,
+        0x53 => I64LtS,
+        0x54 => I64LtU,
+        0x55 => I64GtS,
+        0x56 => I64GtU,
+        0x57 => I64LeS,
+        0x58 => I64LeU,
+        0x59 => I64GeS,
+        0x5A => I64GeU,
+        0x5B => F32Eq,
+        0x5C => F32Ne,
+        0x5D => F32Lt,
+        0x5E => F32Gt,
+        0x5F => F32Le,
+        0x60 => F32Ge,
+        0x61 => F64Eq,
+        0x62 => F64Ne,
+        0x63 => F64Lt,
+        0x64 => F64Gt,
+        0x65 => F64Le,
+        0x66 => F64Ge,
+        0x67 => I32Clz,
+        0x68 => I32Ctz,
+        0x69 => I32Popcnt,
+        0x6A => I32Add,
+        0x6B => I32Sub,
+        0x6C => I32Mul,
+        0x6D => I32DivS,
+        0x6E => I32DivU,
+        0x6F => I32RemS,
+        0x70 => I32RemU,
+        0x71 => I32And,
+        0x72 => I32Or,
+        0x73 => I32Xor,
+        0x74 => I32Shl,
+        0x75 => I32ShrS,
+        0x76 => I32ShrU,
+        0x77 => I32RotL,
+        0x78 => I32RotR,
+        0x79 => I64Clz,
+        0x7A => I64Ctz,
+        0x7B => I64Popcnt,
+        0x7C => I64Add,
+        0x7D => I64Sub,
+        0x7E => I64Mul,
+        0x7F => I64DivS,
+        0x80 => I64DivU,
+        0x81 => I64RemS,
+        0x82 => I64RemU,
+        0x83 => I64And,
+        0x84 => I64Or,
+        0x85 => I64Xor,
+        0x86 => I64Shl,
+        0x87 => I64ShrS,
+        0x88 => I64ShrU,
+        0x89 => I64RotL,
+        0x8A => I64RotR,
+        0x8B => F32Abs,
+        0x8C => F32Neg,
+        0x8D => F32Ceil,
+        0x8E => F32Floor,
+        0x8F => F32Trunc,
+        0x90 => F32Nearest,
+        0x91 => F32Sqrt,
+        0x92 => F32Add,
+        0x93 => F32Sub,
+        0x94 => F32Mul,
+        0x95 => F32Div,
+        0x96 => F32Min,
+        0x97 => F32Max,
+        0x98 => F32Copysign,
+        0x99 => F64Abs,
+        0x9A => F64Neg,
+        0x9B => F64Ceil,
+        0x9C => F64Floor,
+        0x9D => F64Trunc,
+        0x9E => F64Nearest,
+        0x9F => F64Sqrt,
+        0xA0 => F64Add,
+        0xA1 => F64Sub,
+        0xA2 => F64Mul,
+        0xA3 => F64Div,
+        0xA4 => F64Min,
+        0xA5 => F64Max,
+        0xA6 => F64Copysign,
+        0xA7 => I32WrapI64,
+        0xA8 => I32TruncF32S,
+        0xA9 => I32TruncF32U,
+        0xAA => I32TruncF64S,
+        0xAB => I32TruncF64U,
+        0xAC => I64ExtendI32S,
+        0xAD => I64ExtendI32U,
+        0xAE => I64TruncF32S,
+        0xAF => I64TruncF32U,
+        0xB0 => I64TruncF64S,
+        0xB1 => I64TruncF64U,
+        0xB2 => F32ConvertI32S,
+        0xB3 => F32ConvertI32U,
+        0xB4 => F32ConvertI64S,
+        0xB5 => F32ConvertI64U,
+        0xB6 => F32DemoteF64,
+        0xB7 => F64ConvertI32S,
+        0xB8 => F64ConvertI32U,
+        0xB9 => F64ConvertI64S,
+        0xBA => F64ConvertI64U,
+        0xBB => F64PromoteF32,
+        0xBC => I32ReinterpretF32,
+        0xBD => I64ReinterpretF64,
+        0xBE => F32ReinterpretI32,
+        0xBF => F64ReinterpretI64,
+        0xC0 => I32Extend8S,
+        0xC1 => I32Extend16S,
+        0xC2 => I64Extend8S,
+        0xC3 => I64Extend16S,
+        0xC4 => I64Extend32S,
+    ]
+    [
+        0xFC 0 => I32TruncSatF32S,
+        0xFC 1 => I32TruncSatF32U,
+        0xFC 2 => I32TruncSatF64S,
+        0xFC 3 => I32TruncSatF64U,
+        0xFC 4 => I64TruncSatF32S,
+        0xFC 5 => I64TruncSatF32U,
+        0xFC 6 => I64TruncSatF64S,
+        0xFC 7 => I64TruncSatF64U,
+        0xFD 15 => I8x16Splat,
+        0xFD 16 => I16x8Splat,
+        0xFD 17 => I32x4Splat,
+        0xFD 18 => I64x2Splat,
+        0xFD 19 => F32x4Splat,
+        0xFD 20 => F64x2Splat,
+        0xFD 174 => I32x4Add,
+        0xFD 177 => I32x4Sub,
+        0xFD 181 => I32x4Mul,
+        0xFD 228 => F32x4Add,
+        0xFD 229 => F32x4Sub,
+        0xFD 230 => F32x4Mul,
+        0xFD 231 => F32x4Div,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Instr;
+    use crate::loader::parser::Parser;
+    use crate::tests::wat2wasm;
+
+    #[test]
+    fn disassemble() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func (param i32) (result i32)
+                    (if (result i32) (local.get 0)
+                      (then
+                        (if (result i32) (i32.eqz (local.get 0))
+                          (then (i32.const 1))
+                          (else (i32.const 2))))
+                      (else
+                        (block (br 0))
+                        (i32.const 3)))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        assert_eq!(
+            module.funcs[0].body.disassemble(),
+            "local.get 0
+if (result i32)
+  local.get 0
+  i32.eqz
+  if (result i32)
+    i32.const 1
+  else
+    i32.const 2
+  end
+else
+  block
+    br 0
+  end
+  i32.const 3
+end
+"
+        );
+    }
+
+    #[test]
+    fn display() {
+        use super::{Instr, MemArg};
+        assert_eq!(
+            format!(
+                "{}",
+                Instr::I64Load32U(MemArg {
+                    align: 2,
+                    offset: 8
+                })
+            ),
+            "i64.load32_u offset=8 align=4"
+        );
+        assert_eq!(format!("{}", Instr::I32TruncSatF64U), "i32.trunc_sat_f64_u");
+        assert_eq!(
+            format!("{}", Instr::F32Const(f32::NAN)),
+            "f32.const nan:0x400000"
+        );
+    }
+
+    #[test]
+    fn len_and_contains() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (memory 1)
+                  (func (result i32)
+                    (block (drop (memory.grow (i32.const 1))))
+                    (i32.const 0))
+                  (func))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let (grows, empty) = (&module.funcs[0].body, &module.funcs[1].body);
+        // block, i32.const, memory.grow, drop, pop_label, i32.const
+        assert_eq!(grows.len(), 6);
+        assert!(grows.contains(|instr| matches!(instr, Instr::MemoryGrow(_))));
+        assert!(!grows.contains(|instr| matches!(instr, Instr::Call(_))));
+        assert!(empty.is_empty());
+        assert!(!empty.contains(|_| true));
+    }
+}
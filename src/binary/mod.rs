@@ -1,7 +1,11 @@
+mod diff;
+mod encode;
 mod instr;
 mod module;
 mod types;
 
+pub use diff::diff;
+pub use encode::encode;
 pub use instr::*;
 pub use module::*;
 pub use types::*;
@@ -4,6 +4,8 @@ use crate::lib::*;
 #[cfg(feature = "std")]
 use std::vec::IntoIter;
 
+use core::ops::Range;
+
 use super::{
     instr::Expr,
     types::{FuncType, GlobalType, Limits, RefType, ValType},
@@ -18,6 +20,7 @@ pub type ElemIdx = u32;
 pub type DataIdx = u32;
 pub type LocalIdx = u32;
 pub type LabelIdx = u32;
+pub type LaneIdx = u8;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Func {
@@ -32,7 +35,7 @@ pub struct Global {
     pub value: Expr,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Import {
     pub module: String,
     pub name: String,
@@ -59,7 +62,35 @@ pub enum ElemMode {
     Declarative,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+impl Elem {
+    pub fn segment_mode(&self) -> SegmentMode {
+        match self.mode {
+            ElemMode::Passiv => SegmentMode::Passive,
+            ElemMode::Active { tableidx, .. } => SegmentMode::Active(tableidx),
+            ElemMode::Declarative => SegmentMode::Declarative,
+        }
+    }
+
+    /// Number of entries in the segment.
+    pub fn len(&self) -> usize {
+        self.init.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.init.is_empty()
+    }
+}
+
+/// How a data or element segment is used, with the memory or table index
+/// an active segment targets.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SegmentMode {
+    Active(u32),
+    Passive,
+    Declarative,
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum ImportDesc {
     Func(u32),
     Table(Table),
@@ -99,20 +130,43 @@ pub struct Data {
     pub mode: DataMode,
 }
 
+impl Data {
+    pub fn segment_mode(&self) -> SegmentMode {
+        match self.mode {
+            DataMode::Passive => SegmentMode::Passive,
+            DataMode::Active { memidx, .. } => SegmentMode::Active(memidx),
+        }
+    }
+
+    /// Number of bytes in the segment.
+    pub fn len(&self) -> usize {
+        self.init.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.init.is_empty()
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum DataMode {
     Passive,
     Active { memidx: MemIdx, offset: Expr },
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Table {
     pub reftype: RefType,
     pub limits: Limits,
+    /// Initial value of every slot, `ref.null` when absent.
+    pub init: Option<Expr>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub struct Memory(pub Limits);
+pub struct Memory {
+    pub limits: Limits,
+    pub shared: bool,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Custom {
@@ -179,7 +233,7 @@ pub struct CustomSecList {
     pub sec13: Vec<Custom>,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Default)]
 pub struct Module {
     pub version: u8,
     pub types: Vec<FuncType>,
@@ -192,4 +246,167 @@ pub struct Module {
     pub start: Option<FuncIdx>,
     pub imports: Vec<Import>,
     pub exports: Vec<Export>,
+    /// Byte range of each function body in the original binary, parallel to
+    /// `funcs`. Empty unless the parser was asked to record them.
+    pub code_ranges: Vec<Range<usize>>,
+    /// Function names from the `name` section, sorted by index.
+    pub func_names: Vec<(FuncIdx, String)>,
+}
+
+/// The parts of a module needed to index it, without any code.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ModuleHeader {
+    pub version: u8,
+    pub types: Vec<FuncType>,
+    pub imports: Vec<Import>,
+    pub exports: Vec<Export>,
+}
+
+/// How a WASI host should run a module, from the entry point it exports.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum WasiKind {
+    /// Exports `_start`, run once as a program.
+    Command,
+    /// Exports `_initialize`, called once before its other exports.
+    Reactor,
+    Unknown,
+}
+
+impl Module {
+    /// Limits of the module's memory, imported or defined, if it has one.
+    pub fn memory_requirements(&self) -> Option<Limits> {
+        self.imports
+            .iter()
+            .filter_map(|import| match &import.desc {
+                ImportDesc::Mem(memory) => Some(&memory.limits),
+                _ => None,
+            })
+            .chain(self.mems.iter().map(|memory| &memory.limits))
+            .next()
+            .cloned()
+    }
+
+    /// Reference type and limits of every table, in table index order.
+    pub fn table_requirements(&self) -> Vec<(RefType, Limits)> {
+        self.imports
+            .iter()
+            .filter_map(|import| match &import.desc {
+                ImportDesc::Table(table) => Some(table),
+                _ => None,
+            })
+            .chain(self.tables.iter())
+            .map(|table| (table.reftype, table.limits.clone()))
+            .collect()
+    }
+
+    /// `Command` if the module exports a `_start` function, otherwise
+    /// `Reactor` if it exports an `_initialize` function.
+    pub fn wasi_kind(&self) -> WasiKind {
+        let exports_func = |name: &str| {
+            self.exports
+                .iter()
+                .any(|export| export.name == name && matches!(export.desc, ExportDesc::Func(_)))
+        };
+        if exports_func("_start") {
+            WasiKind::Command
+        } else if exports_func("_initialize") {
+            WasiKind::Reactor
+        } else {
+            WasiKind::Unknown
+        }
+    }
+
+    pub fn data_segments(&self) -> &[Data] {
+        &self.datas
+    }
+
+    pub fn elem_segments(&self) -> &[Elem] {
+        &self.elems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SegmentMode, WasiKind};
+    use crate::binary::types::{Limits, RefType};
+    use crate::loader::parser::Parser;
+    use crate::tests::wat2wasm;
+
+    #[test]
+    fn requirements() {
+        let wasm = wat2wasm(
+            r#"(module
+                (import "env" "table" (table 1 funcref))
+                (table 3 8 externref)
+                (memory 2 10))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        assert_eq!(module.memory_requirements(), Some(Limits::MinMax(2, 10)));
+        assert_eq!(
+            module.table_requirements(),
+            vec![
+                (RefType::FuncRef, Limits::Min(1)),
+                (RefType::ExternRef, Limits::MinMax(3, 8))
+            ]
+        );
+
+        let wasm = wat2wasm("(module)").unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        assert_eq!(module.memory_requirements(), None);
+    }
+
+    #[test]
+    fn segments() {
+        let wasm = wat2wasm(
+            r#"(module
+                (memory 1)
+                (table 2 funcref)
+                (func $f)
+                (data (i32.const 8) "abc")
+                (data "hello")
+                (elem (i32.const 0) $f $f)
+                (elem declare func $f))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let datas = module
+            .data_segments()
+            .iter()
+            .map(|data| (data.segment_mode(), data.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            datas,
+            vec![(SegmentMode::Active(0), 3), (SegmentMode::Passive, 5)]
+        );
+        let elems = module
+            .elem_segments()
+            .iter()
+            .map(|elem| (elem.segment_mode(), elem.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            elems,
+            vec![(SegmentMode::Active(0), 2), (SegmentMode::Declarative, 1)]
+        );
+    }
+
+    #[test]
+    fn wasi_kind() {
+        let kind = |wat| {
+            let wasm = wat2wasm(wat).unwrap();
+            Parser::new(&wasm).module().unwrap().wasi_kind()
+        };
+        assert_eq!(
+            kind(r#"(module (func (export "_start")))"#),
+            WasiKind::Command
+        );
+        assert_eq!(
+            kind(r#"(module (func (export "_initialize")) (func (export "f")))"#),
+            WasiKind::Reactor
+        );
+        assert_eq!(
+            kind(r#"(module (global (export "_start") i32 (i32.const 0)))"#),
+            WasiKind::Unknown
+        );
+    }
 }
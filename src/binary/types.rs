@@ -4,7 +4,7 @@ pub trait FromByte: Sized {
     fn from_byte(b: u8) -> Option<Self>;
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum RefType {
     FuncRef,
     ExternRef,
@@ -20,16 +20,40 @@ impl FromByte for RefType {
     }
 }
 
+impl core::fmt::Display for RefType {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RefType::FuncRef => write!(f, "funcref"),
+            RefType::ExternRef => write!(f, "externref"),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ValType {
     I32,
     I64,
     F32,
     F64,
+    V128,
     FuncRef,
     ExternRef,
 }
 
+impl core::fmt::Display for ValType {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ValType::I32 => write!(f, "i32"),
+            ValType::I64 => write!(f, "i64"),
+            ValType::F32 => write!(f, "f32"),
+            ValType::F64 => write!(f, "f64"),
+            ValType::V128 => write!(f, "v128"),
+            ValType::FuncRef => write!(f, "funcref"),
+            ValType::ExternRef => write!(f, "externref"),
+        }
+    }
+}
+
 impl FromByte for ValType {
     fn from_byte(n: u8) -> Option<Self> {
         match n {
@@ -39,8 +63,9 @@ impl FromByte for ValType {
             0x7D => Some(ValType::F32),
             0x7c => Some(ValType::F64),
             // Vector Type
-            0x70 => Some(ValType::FuncRef),
+            0x7B => Some(ValType::V128),
             // Reference Type
+            0x70 => Some(ValType::FuncRef),
             0x6F => Some(ValType::ExternRef),
             _ => None,
         }
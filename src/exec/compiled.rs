@@ -0,0 +1,169 @@
+#[cfg(not(feature = "std"))]
+use crate::lib::*;
+use alloc::sync::Arc;
+
+use super::importer::Importer;
+use super::runtime::{check_flattened, Runtime, RuntimeError};
+use super::store::Store;
+use crate::binary::{FuncType, Instr, Module, TypeIdx, ValType};
+use crate::loader::{self, error::Error};
+
+/// A decoded and validated module, with its function bodies lowered once
+/// into the code the runtime executes.
+///
+/// The code, the types and the function metadata sit behind `Arc`, so every
+/// instance refers to the same copy and cloning is cheap. It holds no
+/// runtime state, so a single `CompiledModule` can be shared across threads
+/// while each thread instantiates it into its own `Runtime` and `Store`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledModule {
+    /// The module, its function bodies moved into `code`.
+    module: Arc<Module>,
+    pub(crate) code: Arc<Vec<Instr>>,
+    pub(crate) types: Arc<Vec<FuncType>>,
+    pub(crate) funcs: Arc<Vec<CompiledFunc>>,
+}
+
+/// A function defined by the module.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CompiledFunc {
+    pub typeidx: TypeIdx,
+    pub locals: Vec<ValType>,
+    /// Index of its first instruction in `CompiledModule::code`.
+    pub start: usize,
+}
+
+impl CompiledModule {
+    pub fn new(input: &[u8]) -> Result<Self, Error> {
+        Ok(loader::parse(input)?.into())
+    }
+
+    /// The decoded module. Its function bodies are empty; the lowered
+    /// instructions are in `code`.
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+
+    /// The bodies of all functions back to back, each ending in a return.
+    pub fn code(&self) -> &[Instr] {
+        &self.code
+    }
+}
+
+impl From<Module> for CompiledModule {
+    fn from(mut module: Module) -> Self {
+        let mut code = vec![];
+        let mut funcs = vec![];
+        for func in &mut module.funcs {
+            let start = code.len();
+            let body = core::mem::take(&mut func.body.0);
+            // A body ending in an unconditional branch never reaches the
+            // implicit return; at the top level `br` can only target the
+            // function itself.
+            let terminated = matches!(
+                body.last(),
+                Some(Instr::Return | Instr::Unreachable | Instr::Br(_) | Instr::BrTable { .. })
+            );
+            code.extend(body);
+            if !terminated {
+                code.push(Instr::Return);
+            }
+            debug_assert_eq!(check_flattened(&code[start..]), Ok(()));
+            funcs.push(CompiledFunc {
+                typeidx: func.typeidx,
+                locals: func.locals.clone(),
+                start,
+            });
+        }
+        CompiledModule {
+            types: Arc::new(module.types.clone()),
+            module: Arc::new(module),
+            code: Arc::new(code),
+            funcs: Arc::new(funcs),
+        }
+    }
+}
+
+impl Runtime {
+    /// Instantiate `compiled` as the root module of this runtime.
+    pub fn instantiate(
+        &mut self,
+        store: &mut Store,
+        compiled: &CompiledModule,
+    ) -> Result<(), RuntimeError> {
+        struct EmptyImporter {}
+        impl Importer for EmptyImporter {
+            fn import(&mut self, _: &str) -> Option<Module> {
+                panic!()
+            }
+        }
+
+        let instance = self.new_instance(store, compiled, &mut EmptyImporter {}, false)?;
+        self.instances.push(instance);
+
+        self.root = self.instances.len() - 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CompiledModule;
+    use crate::exec::env::DebugEnv;
+    use crate::exec::runtime::Runtime;
+    use crate::exec::store::Store;
+    use crate::exec::value::Value;
+    use crate::tests::wat2wasm;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn shared_between_threads() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (global $g (mut i32) (i32.const 0))
+                  (func (export "bump") (param i32) (result i32)
+                    global.get $g
+                    local.get 0
+                    i32.add
+                    global.set $g
+                    global.get $g))"#,
+        )
+        .unwrap();
+        let compiled = Arc::new(CompiledModule::new(&wasm).unwrap());
+
+        let handles: Vec<_> = [1, 2]
+            .into_iter()
+            .map(|n| {
+                let compiled = Arc::clone(&compiled);
+                thread::spawn(move || {
+                    let mut store = Store::new();
+                    let mut runtime = Runtime::new("env");
+                    runtime.instantiate(&mut store, &compiled).unwrap();
+                    // The instance runs the compiled code itself, not a copy.
+                    assert!(Arc::ptr_eq(&runtime.instances[0].code, &compiled.code));
+                    let mut results = vec![];
+                    for _ in 0..3 {
+                        results.push(runtime.invoke(
+                            &mut store,
+                            &mut DebugEnv {},
+                            "bump",
+                            vec![Value::I32(n)],
+                        ));
+                    }
+                    results
+                })
+            })
+            .collect();
+        for (n, handle) in [1, 2].into_iter().zip(handles) {
+            assert_eq!(
+                handle.join().unwrap(),
+                vec![
+                    Ok(vec![Value::I32(n)]),
+                    Ok(vec![Value::I32(2 * n)]),
+                    Ok(vec![Value::I32(3 * n)]),
+                ]
+            );
+        }
+    }
+}
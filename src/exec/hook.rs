@@ -0,0 +1,60 @@
+#[cfg(not(feature = "std"))]
+use crate::lib::*;
+
+use crate::binary::MemArg;
+use core::fmt::{self, Debug};
+
+/// Called with the effective address, access size and memory length of a
+/// load or store that is about to trap with `MemoryOutOfBounds`.
+pub type MemViolationHook = Box<dyn FnMut(usize, usize, usize)>;
+
+/// Receives diagnostic messages about the running code.
+pub type LogHook = Box<dyn FnMut(&str)>;
+
+/// Optional debugging callbacks consulted while executing.
+#[derive(Default)]
+pub struct Hooks {
+    pub mem_violation: Option<MemViolationHook>,
+    pub log: Option<LogHook>,
+    /// Log loads and stores whose alignment hint is not the natural one.
+    pub warn_misaligned: bool,
+    /// Trap with `UnexpectedNaN` as soon as float arithmetic yields a NaN.
+    pub trap_on_nan: bool,
+}
+
+impl Hooks {
+    pub fn mem_violation(&mut self, ea: usize, size: usize, mem_len: usize) {
+        if let Some(hook) = &mut self.mem_violation {
+            hook(ea, size, mem_len);
+        }
+    }
+
+    pub fn log(&mut self, message: &str) {
+        if let Some(hook) = &mut self.log {
+            hook(message);
+        }
+    }
+
+    /// Alignment is only a hint and never traps, but a mismatch can point
+    /// at slow guest code.
+    pub fn check_align(&mut self, memarg: &MemArg, size: usize, ea: usize) {
+        let natural = size.trailing_zeros();
+        if self.warn_misaligned && memarg.align != natural {
+            self.log(&format!(
+                "{}-byte access at {:#x} has alignment 2^{}, expected 2^{}",
+                size, ea, memarg.align, natural
+            ));
+        }
+    }
+}
+
+impl Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("mem_violation", &self.mem_violation.is_some())
+            .field("log", &self.log.is_some())
+            .field("warn_misaligned", &self.warn_misaligned)
+            .field("trap_on_nan", &self.trap_on_nan)
+            .finish()
+    }
+}
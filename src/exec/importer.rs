@@ -16,6 +16,12 @@ pub mod default {
         modules: BTreeMap<String, Module>,
     }
 
+    impl Default for DefaultImporter {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
     impl DefaultImporter {
         pub fn new() -> Self {
             Self {
@@ -26,6 +32,31 @@ pub mod default {
         pub fn add_module(&mut self, module: Module, modname: &str) {
             self.modules.insert(modname.into(), module);
         }
+
+        /// Parses the module at `path`. With the `mmap` feature the file is
+        /// mapped and parsed in place; otherwise it is read into a buffer.
+        pub fn load_file<P: AsRef<std::path::Path>>(path: P) -> Option<Module> {
+            #[cfg(feature = "mmap")]
+            return Self::load_mapped(path.as_ref());
+            #[cfg(not(feature = "mmap"))]
+            return Self::load_buffered(path.as_ref());
+        }
+
+        #[cfg(any(not(feature = "mmap"), test))]
+        fn load_buffered(path: &std::path::Path) -> Option<Module> {
+            let buf = std::fs::read(path).ok()?;
+            crate::loader::parse(&buf).ok()
+        }
+
+        #[cfg(feature = "mmap")]
+        fn load_mapped(path: &std::path::Path) -> Option<Module> {
+            let file = std::fs::File::open(path).ok()?;
+            // SAFETY: the map is only read while parsing, and the parsed
+            // module owns its data. A file truncated by another process
+            // meanwhile is outside what this importer guards against.
+            let map = unsafe { memmap2::Mmap::map(&file) }.ok()?;
+            crate::loader::parse(&map).ok()
+        }
     }
 
     impl Importer for DefaultImporter {
@@ -34,19 +65,158 @@ pub mod default {
                 return Some(module.clone());
             }
 
-            use crate::loader::parser::Parser;
-            use std::fs::File;
-            use std::io::prelude::*;
+            let module = Self::load_file(modname)?;
+            self.modules.insert(modname.into(), module.clone());
+
+            Some(module)
+        }
+    }
 
-            let mut file = File::open(modname).ok()?;
-            let mut buf = vec![];
-            file.read_to_end(&mut buf).ok()?;
-            let mut parser = Parser::new(&buf);
+    #[cfg(test)]
+    mod tests {
+        use super::DefaultImporter;
+        use crate::exec::importer::Importer;
+        use crate::loader::parser::Parser;
+        use crate::tests::wat2wasm;
 
-            let module = parser.module().ok()?;
+        #[test]
+        fn load_file() {
+            let wasm =
+                wat2wasm(r#"(module (func (export "f") (result i32) i32.const 7))"#).unwrap();
+            let path =
+                std::env::temp_dir().join(format!("wasper-load-{}.wasm", std::process::id()));
+            std::fs::write(&path, &wasm).unwrap();
+
+            let expected = Parser::new(&wasm).module().unwrap();
+            assert_eq!(DefaultImporter::load_file(&path), Some(expected.clone()));
+            let mut importer = DefaultImporter::new();
+            assert_eq!(importer.import(path.to_str().unwrap()), Some(expected));
+
+            // Parses, but fails validation.
+            let wasm = wat2wasm(r#"(module (func (result i32) i64.const 0))"#).unwrap();
+            std::fs::write(&path, &wasm).unwrap();
+            assert_eq!(DefaultImporter::load_file(&path), None);
+
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        #[cfg(feature = "mmap")]
+        #[test]
+        fn load_mapped() {
+            let wasm = wat2wasm(r#"(module (memory 1) (data (i32.const 0) "wasper"))"#).unwrap();
+            let path =
+                std::env::temp_dir().join(format!("wasper-mmap-{}.wasm", std::process::id()));
+            std::fs::write(&path, &wasm).unwrap();
+
+            let mapped = DefaultImporter::load_mapped(&path);
+            assert!(mapped.is_some());
+            assert_eq!(mapped, DefaultImporter::load_buffered(&path));
+
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+pub mod archive {
+    use crate::binary::Module;
+    use alloc::collections::BTreeMap;
+    use std::{fs, io, path::Path};
+
+    use super::Importer;
+
+    /// Resolves imports from a bundle of `name -> wasm bytes`, parsing each
+    /// module the first time it is requested.
+    #[derive(Debug, Default)]
+    pub struct ArchiveImporter {
+        sources: BTreeMap<String, Vec<u8>>,
+        modules: BTreeMap<String, Module>,
+    }
+
+    impl ArchiveImporter {
+        pub fn new(sources: BTreeMap<String, Vec<u8>>) -> Self {
+            Self {
+                sources,
+                modules: BTreeMap::new(),
+            }
+        }
+
+        /// Bundles every `*.wasm` file in `dir`, named by its file stem.
+        pub fn from_dir<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+            let mut sources = BTreeMap::new();
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.extension().map_or(true, |ext| ext != "wasm") {
+                    continue;
+                }
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    sources.insert(name.to_string(), fs::read(&path)?);
+                }
+            }
+            Ok(Self::new(sources))
+        }
+    }
+
+    impl Importer for ArchiveImporter {
+        fn import(&mut self, modname: &str) -> Option<Module> {
+            if let Some(module) = self.modules.get(modname) {
+                return Some(module.clone());
+            }
+
+            let bytes = self.sources.get(modname)?;
+            let module = crate::loader::parse(bytes).ok()?;
             self.modules.insert(modname.into(), module.clone());
 
             Some(module)
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::ArchiveImporter;
+        use crate::exec::env::DebugEnv;
+        use crate::exec::importer::Importer;
+        use crate::exec::runtime::Runtime;
+        use crate::exec::store::Store;
+        use crate::exec::value::Value;
+        use crate::tests::wat2wasm;
+        use alloc::collections::BTreeMap;
+
+        #[test]
+        fn link_from_map() {
+            let lib = wat2wasm(
+                r#"(module
+                      (func (export "double") (param i32) (result i32)
+                        (i32.mul (local.get 0) (i32.const 2))))"#,
+            )
+            .unwrap();
+            let app = wat2wasm(
+                r#"(module
+                      (import "lib" "double" (func $double (param i32) (result i32)))
+                      (func (export "run") (result i32)
+                        (call $double (i32.const 21))))"#,
+            )
+            .unwrap();
+            let mut sources = BTreeMap::new();
+            sources.insert("lib".to_string(), lib);
+            sources.insert("app".to_string(), app);
+            // Parses, but returns an i64 from a function declared to return
+            // an i32.
+            let invalid = wat2wasm(r#"(module (func (result i32) i64.const 0))"#).unwrap();
+            sources.insert("invalid".to_string(), invalid);
+            let mut importer = ArchiveImporter::new(sources);
+            assert!(importer.import("missing").is_none());
+            assert!(importer.import("invalid").is_none());
+
+            let mut store = Store::new();
+            let mut runtime = Runtime::new("env");
+            runtime
+                .import_module(&mut store, &mut importer, "app")
+                .unwrap();
+            assert_eq!(
+                runtime.invoke(&mut store, &mut DebugEnv {}, "run", vec![]),
+                Ok(vec![Value::I32(42)])
+            );
+        }
+    }
 }
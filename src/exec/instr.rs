@@ -1,26 +1,31 @@
+use super::hook::Hooks;
 use super::runtime::{ExecState, Instance};
-use super::stack::{Frame, Label, Stack};
+use super::stack::{Frame, Label, Stack, MAX_CALL_DEPTH};
 use super::store::{FuncInst, Store};
 use super::table::*;
 use super::trap::Trap;
 use super::value::{Ref, Value};
-use super::{cast, memory};
-use crate::binary::Instr;
-use crate::binary::ValType;
+use super::{cast, memory, simd};
+use crate::binary::{Instr, LabelIdx};
+use crate::binary::{RefType, ValType};
 #[cfg(not(feature = "std"))]
 use crate::lib::*;
 use core::ops::Neg;
 use num_traits::float::Float;
 
 pub fn step(
-    instances: &mut Vec<Instance>,
-    instrs: &Vec<Instr>,
+    instances: &mut [Instance],
+    instrs: &[Instr],
     pc: usize,
     store: &mut Store,
     stack: &mut Stack,
+    hooks: &mut Hooks,
 ) -> Result<ExecState, Trap> {
-    let frame = stack.top_frame().clone();
+    let frame = stack.top_frame()?.clone();
     let instance = &mut instances[frame.instance_addr];
+    if stack.values_len() < frame.stack_offset + operand_count(&instrs[pc]) {
+        return Err(Trap::StackUnderflow);
+    }
     match &instrs[pc] {
         //////////////////////////
         // Control Instructions //
@@ -30,15 +35,16 @@ pub fn step(
         Instr::Block { bt, end_offset } => {
             stack.push_label(Label {
                 n: instance.block_to_arity(bt),
-                stack_offset: stack.values_len(),
+                stack_offset: block_offset(instance.block_params(bt), &frame, stack)?,
                 pc: end_offset + pc,
                 cont: false,
             });
         }
+        // Branching to a loop restarts it, so it carries the parameters.
         Instr::Loop { bt } => {
             stack.push_label(Label {
-                n: instance.block_to_arity(bt),
-                stack_offset: stack.values_len(),
+                n: instance.block_params(bt),
+                stack_offset: block_offset(instance.block_params(bt), &frame, stack)?,
                 pc,
                 cont: true,
             });
@@ -48,18 +54,18 @@ pub fn step(
             else_offset,
             end_offset,
         } => {
-            let c = stack.pop_value::<i32>();
+            let c = stack.pop_value::<i32>()?;
             if c != 0 {
                 stack.push_label(Label {
                     n: instance.block_to_arity(bt),
-                    stack_offset: stack.values_len(),
+                    stack_offset: block_offset(instance.block_params(bt), &frame, stack)?,
                     pc: end_offset + pc,
                     cont: false,
                 });
             } else if let Some(else_offset) = else_offset {
                 stack.push_label(Label {
                     n: instance.block_to_arity(bt),
-                    stack_offset: stack.values_len(),
+                    stack_offset: block_offset(instance.block_params(bt), &frame, stack)?,
                     pc: end_offset + pc,
                     cont: false,
                 });
@@ -68,55 +74,20 @@ pub fn step(
                 return Ok(ExecState::Continue(end_offset + pc));
             }
         }
-        Instr::Br(l) => {
-            if *l as usize >= stack.labels_len() {
-                return match unwind_stack(&frame, stack) {
-                    Some(new_pc) => Ok(ExecState::Continue(new_pc)),
-                    None => Ok(ExecState::Return),
-                };
-            }
-            let new_pc = stack.jump(*l as usize);
-            return Ok(ExecState::Continue(new_pc));
-        }
+        Instr::Br(l) => return branch(*l, &frame, stack),
         Instr::BrIf(l) => {
-            let c = stack.pop_value::<i32>();
+            let c = stack.pop_value::<i32>()?;
             if c != 0 {
-                if *l as usize >= stack.labels_len() {
-                    return match unwind_stack(&frame, stack) {
-                        Some(new_pc) => Ok(ExecState::Continue(new_pc)),
-                        None => Ok(ExecState::Return),
-                    };
-                }
-                let new_pc = stack.jump(*l as usize);
-                return Ok(ExecState::Continue(new_pc));
+                return branch(*l, &frame, stack);
             }
         }
         Instr::BrTable { indexs, default } => {
-            let i = stack.pop_value::<i32>() as usize;
-            return if i < indexs.len() {
-                let l = indexs[i] as usize;
-                if l >= stack.labels_len() {
-                    return match unwind_stack(&frame, stack) {
-                        Some(new_pc) => Ok(ExecState::Continue(new_pc)),
-                        None => Ok(ExecState::Return),
-                    };
-                }
-                let new_pc = stack.jump(indexs[i] as usize);
-                Ok(ExecState::Continue(new_pc))
-            } else {
-                let l = *default as usize;
-                if l >= stack.labels_len() {
-                    return match unwind_stack(&frame, stack) {
-                        Some(new_pc) => Ok(ExecState::Continue(new_pc)),
-                        None => Ok(ExecState::Return),
-                    };
-                }
-                let new_pc = stack.jump(l as usize);
-                return Ok(ExecState::Continue(new_pc));
-            };
+            let i = stack.pop_value::<i32>()? as u32 as usize;
+            let l = indexs.get(i).unwrap_or(default);
+            return branch(*l, &frame, stack);
         }
         Instr::Return => {
-            return match unwind_stack(&frame, stack) {
+            return match unwind_stack(&frame, stack)? {
                 Some(new_pc) => Ok(ExecState::Continue(new_pc)),
                 None => Ok(ExecState::Return),
             };
@@ -131,29 +102,29 @@ pub fn step(
             let ta = instance.tableaddrs[*tableidx as usize];
             let tab = &store.tables[ta];
             let ft = &instance.types[*typeidx as usize];
-            let i = stack.pop_value::<i32>() as usize;
+            let i = stack.pop_value::<i32>()? as usize;
             if i >= tab.elem.len() {
                 return Err(Trap::UndefinedElement);
             }
-            let r = tab.elem[i];
-            if let Ref::Func(a) = r {
-                let func = &store.funcs[a];
-                if func.functype() != ft {
-                    return Err(Trap::IndirectCallTypeMismatch);
-                }
-                return attach(func, stack, pc);
-            } else {
-                return Err(Trap::NotFundRef);
+            let a = match tab.elem[i] {
+                Ref::Func(a) => a,
+                Ref::Null(_) => return Err(Trap::UninitializedElement),
+                Ref::Extern(_) => return Err(Trap::NotFundRef),
+            };
+            let func = &store.funcs[a];
+            if func.functype() != ft {
+                return Err(Trap::IndirectCallTypeMismatch);
             }
+            return attach(func, stack, pc);
         }
 
         ////////////////////////////
         // Reference Instructions //
         ////////////////////////////
-        Instr::RefNull(_) => stack.push_value(Value::Ref(Ref::Null)),
+        Instr::RefNull(t) => stack.push_value(Value::Ref(Ref::Null(*t))),
         Instr::RefIsNull => {
-            let c = match stack.pop_value::<Value>() {
-                Value::Ref(Ref::Null) => Value::I32(1),
+            let c = match stack.pop_value::<Value>()? {
+                Value::Ref(Ref::Null(_)) => Value::I32(1),
                 _ => Value::I32(0),
             };
             stack.push_value(c);
@@ -166,13 +137,11 @@ pub fn step(
         /////////////////////////////
         // Parametric Instructions //
         /////////////////////////////
-        Instr::Drop => {
-            stack.pop_value::<Value>();
-        }
+        Instr::Drop => stack.drop_values(1),
         Instr::Select => {
-            let c = stack.pop_value::<i32>();
-            let val2 = stack.pop_value::<Value>();
-            let val1 = stack.pop_value::<Value>();
+            let c = stack.pop_value::<i32>()?;
+            let val2 = stack.pop_value::<Value>()?;
+            let val1 = stack.pop_value::<Value>()?;
             if c != 0 {
                 stack.push_value(val1);
             } else {
@@ -188,21 +157,27 @@ pub fn step(
             stack.push_value(value);
         }
         Instr::LocalSet(l) => {
-            let value = stack.pop_value();
-            stack.top_frame_mut().local[*l as usize] = value;
+            let value = stack.pop_value()?;
+            stack.top_frame_mut()?.local[*l as usize] = value;
         }
         Instr::LocalTee(l) => {
-            let value: Value = stack.pop_value();
+            let value: Value = stack.pop_value()?;
             stack.push_value(value);
-            stack.top_frame_mut().local[*l as usize] = value;
+            stack.top_frame_mut()?.local[*l as usize] = value;
         }
         Instr::GlobalGet(i) => {
-            let globalindex = instance.globaladdrs[*i as usize];
+            let globalindex = *instance
+                .globaladdrs
+                .get(*i as usize)
+                .ok_or(Trap::UndefinedGlobal)?;
             stack.push_value(store.globals[globalindex].value);
         }
         Instr::GlobalSet(i) => {
-            let value = stack.pop_value();
-            let globalindex = instance.globaladdrs[*i as usize];
+            let globalindex = *instance
+                .globaladdrs
+                .get(*i as usize)
+                .ok_or(Trap::UndefinedGlobal)?;
+            let value = stack.pop_value()?;
             store.globals[globalindex].value = value;
         }
 
@@ -213,7 +188,7 @@ pub fn step(
         Instr::TableSet(x) => table_set(x, instance, store, stack)?,
         Instr::TableInit(x, y) => table_init(x, y, instance, store, stack)?,
         Instr::TableCopy(x, y) => table_copy(x, y, instance, store, stack)?,
-        Instr::TableGrow(x) => table_grow(x, instance, store, stack),
+        Instr::TableGrow(x) => table_grow(x, instance, store, stack)?,
         Instr::TableSize(x) => table_size(x, instance, store, stack),
         Instr::TableFill(x) => table_fill(x, instance, store, stack)?,
         Instr::ElemDrop(x) => elem_drop(x, instance, store),
@@ -221,31 +196,31 @@ pub fn step(
         /////////////////////////
         // Memory Instructions //
         /////////////////////////
-        Instr::I32Load(memarg) => memory::i32_load(memarg, instance, store, stack)?,
-        Instr::I64Load(memarg) => memory::i64_load(memarg, instance, store, stack)?,
-        Instr::F32Load(memarg) => memory::f32_load(memarg, instance, store, stack)?,
-        Instr::F64Load(memarg) => memory::f64_load(memarg, instance, store, stack)?,
-        Instr::I32Load8S(memarg) => memory::i32_load_8s(memarg, instance, store, stack)?,
-        Instr::I32Load8U(memarg) => memory::i32_load_8u(memarg, instance, store, stack)?,
-        Instr::I32Load16S(memarg) => memory::i32_load_16s(memarg, instance, store, stack)?,
-        Instr::I32Load16U(memarg) => memory::i32_load_16u(memarg, instance, store, stack)?,
-        Instr::I64Load8S(memarg) => memory::i64_load_8s(memarg, instance, store, stack)?,
-        Instr::I64Load8U(memarg) => memory::i64_load_8u(memarg, instance, store, stack)?,
-        Instr::I64Load16S(memarg) => memory::i64_load_16s(memarg, instance, store, stack)?,
-        Instr::I64Load16U(memarg) => memory::i64_load_16u(memarg, instance, store, stack)?,
-        Instr::I64Load32S(memarg) => memory::i64_load_32s(memarg, instance, store, stack)?,
-        Instr::I64Load32U(memarg) => memory::i64_load_32u(memarg, instance, store, stack)?,
-        Instr::I32Store(memarg) => memory::i32_store(memarg, instance, store, stack)?,
-        Instr::I64Store(memarg) => memory::i64_store(memarg, instance, store, stack)?,
-        Instr::F32Store(memarg) => memory::f32_store(memarg, instance, store, stack)?,
-        Instr::F64Store(memarg) => memory::f64_store(memarg, instance, store, stack)?,
-        Instr::I32Store8(memarg) => memory::i32_store_8(memarg, instance, store, stack)?,
-        Instr::I32Store16(memarg) => memory::i32_store_16(memarg, instance, store, stack)?,
-        Instr::I64Store8(memarg) => memory::i64_store_8(memarg, instance, store, stack)?,
-        Instr::I64Store16(memarg) => memory::i64_store_16(memarg, instance, store, stack)?,
-        Instr::I64Store32(memarg) => memory::i64_store_32(memarg, instance, store, stack)?,
-        Instr::MemorySize => memory::memory_size(instance, store, stack),
-        Instr::MemoryGrow => memory::memory_grow(instance, store, stack),
+        Instr::I32Load(memarg) => memory::i32_load(memarg, instance, store, stack, hooks)?,
+        Instr::I64Load(memarg) => memory::i64_load(memarg, instance, store, stack, hooks)?,
+        Instr::F32Load(memarg) => memory::f32_load(memarg, instance, store, stack, hooks)?,
+        Instr::F64Load(memarg) => memory::f64_load(memarg, instance, store, stack, hooks)?,
+        Instr::I32Load8S(memarg) => memory::i32_load_8s(memarg, instance, store, stack, hooks)?,
+        Instr::I32Load8U(memarg) => memory::i32_load_8u(memarg, instance, store, stack, hooks)?,
+        Instr::I32Load16S(memarg) => memory::i32_load_16s(memarg, instance, store, stack, hooks)?,
+        Instr::I32Load16U(memarg) => memory::i32_load_16u(memarg, instance, store, stack, hooks)?,
+        Instr::I64Load8S(memarg) => memory::i64_load_8s(memarg, instance, store, stack, hooks)?,
+        Instr::I64Load8U(memarg) => memory::i64_load_8u(memarg, instance, store, stack, hooks)?,
+        Instr::I64Load16S(memarg) => memory::i64_load_16s(memarg, instance, store, stack, hooks)?,
+        Instr::I64Load16U(memarg) => memory::i64_load_16u(memarg, instance, store, stack, hooks)?,
+        Instr::I64Load32S(memarg) => memory::i64_load_32s(memarg, instance, store, stack, hooks)?,
+        Instr::I64Load32U(memarg) => memory::i64_load_32u(memarg, instance, store, stack, hooks)?,
+        Instr::I32Store(memarg) => memory::i32_store(memarg, instance, store, stack, hooks)?,
+        Instr::I64Store(memarg) => memory::i64_store(memarg, instance, store, stack, hooks)?,
+        Instr::F32Store(memarg) => memory::f32_store(memarg, instance, store, stack, hooks)?,
+        Instr::F64Store(memarg) => memory::f64_store(memarg, instance, store, stack, hooks)?,
+        Instr::I32Store8(memarg) => memory::i32_store_8(memarg, instance, store, stack, hooks)?,
+        Instr::I32Store16(memarg) => memory::i32_store_16(memarg, instance, store, stack, hooks)?,
+        Instr::I64Store8(memarg) => memory::i64_store_8(memarg, instance, store, stack, hooks)?,
+        Instr::I64Store16(memarg) => memory::i64_store_16(memarg, instance, store, stack, hooks)?,
+        Instr::I64Store32(memarg) => memory::i64_store_32(memarg, instance, store, stack, hooks)?,
+        Instr::MemorySize(_) => memory::memory_size(instance, store, stack),
+        Instr::MemoryGrow(_) => memory::memory_grow(instance, store, stack)?,
         Instr::MemoryInit(x) => memory::memory_init(x, instance, store, stack)?,
         Instr::DataDrop(x) => memory::data_drop(x, instance, store),
         Instr::MemoryCopy => memory::memory_copy(instance, store, stack)?,
@@ -259,14 +234,14 @@ pub fn step(
         Instr::F32Const(a) => stack.push_value(*a),
         Instr::F64Const(a) => stack.push_value(*a),
         // iadd_N
-        Instr::I32Add => stack.binop(i32::wrapping_add),
-        Instr::I64Add => stack.binop(i64::wrapping_add),
+        Instr::I32Add => stack.binop(i32::wrapping_add)?,
+        Instr::I64Add => stack.binop(i64::wrapping_add)?,
         // isub_N
-        Instr::I32Sub => stack.binop(i32::wrapping_sub),
-        Instr::I64Sub => stack.binop(i64::wrapping_sub),
+        Instr::I32Sub => stack.binop(i32::wrapping_sub)?,
+        Instr::I64Sub => stack.binop(i64::wrapping_sub)?,
         // imul_N
-        Instr::I32Mul => stack.binop(i32::wrapping_mul),
-        Instr::I64Mul => stack.binop(i64::wrapping_mul),
+        Instr::I32Mul => stack.binop(i32::wrapping_mul)?,
+        Instr::I64Mul => stack.binop(i64::wrapping_mul)?,
         // idiv_u_N
         Instr::I32DivU => stack.binop_trap(|a: i32, b| {
             (a as u32)
@@ -326,83 +301,83 @@ pub fn step(
             }
         })?,
         // iand_N
-        Instr::I32And => stack.binop(|a: i32, b| a & b),
-        Instr::I64And => stack.binop(|a: i64, b| a & b),
+        Instr::I32And => stack.binop(|a: i32, b| a & b)?,
+        Instr::I64And => stack.binop(|a: i64, b| a & b)?,
         // ior_N
-        Instr::I32Or => stack.binop(|a: i32, b| a | b),
-        Instr::I64Or => stack.binop(|a: i64, b| a | b),
+        Instr::I32Or => stack.binop(|a: i32, b| a | b)?,
+        Instr::I64Or => stack.binop(|a: i64, b| a | b)?,
         // ixor_N
-        Instr::I32Xor => stack.binop(|a: i32, b| a ^ b),
-        Instr::I64Xor => stack.binop(|a: i64, b| a ^ b),
+        Instr::I32Xor => stack.binop(|a: i32, b| a ^ b)?,
+        Instr::I64Xor => stack.binop(|a: i64, b| a ^ b)?,
         // ishl_N
-        Instr::I32Shl => stack.binop(|a: i32, b| a.wrapping_shl(b as u32)),
-        Instr::I64Shl => stack.binop(|a: i64, b| a.wrapping_shl(b as u32)),
+        Instr::I32Shl => stack.binop(|a: i32, b| a.wrapping_shl(b as u32))?,
+        Instr::I64Shl => stack.binop(|a: i64, b| a.wrapping_shl(b as u32))?,
         // ishr_u_N
-        Instr::I32ShrU => stack.binop(|a: i32, b| (a as u32).wrapping_shr(b as u32) as i32),
-        Instr::I64ShrU => stack.binop(|a: i64, b| (a as u64).wrapping_shr(b as u32) as i64),
+        Instr::I32ShrU => stack.binop(|a: i32, b| (a as u32).wrapping_shr(b as u32) as i32)?,
+        Instr::I64ShrU => stack.binop(|a: i64, b| (a as u64).wrapping_shr(b as u32) as i64)?,
         // ishr_s_N
-        Instr::I32ShrS => stack.binop(|a: i32, b| a.wrapping_shr(b as u32)),
-        Instr::I64ShrS => stack.binop(|a: i64, b| a.wrapping_shr(b as u32)),
+        Instr::I32ShrS => stack.binop(|a: i32, b| a.wrapping_shr(b as u32))?,
+        Instr::I64ShrS => stack.binop(|a: i64, b| a.wrapping_shr(b as u32))?,
         // irotl_N
-        Instr::I32RotL => stack.binop(|a: i32, b| a.rotate_left(b as u32)),
-        Instr::I64RotL => stack.binop(|a: i64, b| a.rotate_left(b as u32)),
+        Instr::I32RotL => stack.binop(|a: i32, b| a.rotate_left(b as u32))?,
+        Instr::I64RotL => stack.binop(|a: i64, b| a.rotate_left(b as u32))?,
         // irotr_N
-        Instr::I32RotR => stack.binop(|a: i32, b| a.rotate_right(b as u32)),
-        Instr::I64RotR => stack.binop(|a: i64, b| a.rotate_right(b as u32)),
+        Instr::I32RotR => stack.binop(|a: i32, b| a.rotate_right(b as u32))?,
+        Instr::I64RotR => stack.binop(|a: i64, b| a.rotate_right(b as u32))?,
         // iclz_N
-        Instr::I32Clz => stack.unop(|v: i32| v.leading_zeros() as i32),
-        Instr::I64Clz => stack.unop(|v: i64| v.leading_zeros() as i64),
+        Instr::I32Clz => stack.unop(|v: i32| v.leading_zeros() as i32)?,
+        Instr::I64Clz => stack.unop(|v: i64| v.leading_zeros() as i64)?,
         // ictz_N
-        Instr::I32Ctz => stack.unop(|v: i32| v.trailing_zeros() as i32),
-        Instr::I64Ctz => stack.unop(|v: i64| v.trailing_zeros() as i64),
+        Instr::I32Ctz => stack.unop(|v: i32| v.trailing_zeros() as i32)?,
+        Instr::I64Ctz => stack.unop(|v: i64| v.trailing_zeros() as i64)?,
         // ipopcnt_N
-        Instr::I32Popcnt => stack.unop(|v: i32| v.count_ones() as i32),
-        Instr::I64Popcnt => stack.unop(|v: i64| v.count_ones() as i64),
+        Instr::I32Popcnt => stack.unop(|v: i32| v.count_ones() as i32)?,
+        Instr::I64Popcnt => stack.unop(|v: i64| v.count_ones() as i64)?,
         // ieqz_N
-        Instr::I32Eqz => stack.testop(|v: i32| if v == 0 { 1 } else { 0 }),
-        Instr::I64Eqz => stack.testop(|v: i64| if v == 0 { 1 } else { 0 }),
+        Instr::I32Eqz => stack.testop(|v: i32| if v == 0 { 1 } else { 0 })?,
+        Instr::I64Eqz => stack.testop(|v: i64| if v == 0 { 1 } else { 0 })?,
         // ieq_N
-        Instr::I32Eq => stack.relop(|a: i32, b| if a == b { 1 } else { 0 }),
-        Instr::I64Eq => stack.relop(|a: i64, b| if a == b { 1 } else { 0 }),
+        Instr::I32Eq => stack.relop(|a: i32, b| if a == b { 1 } else { 0 })?,
+        Instr::I64Eq => stack.relop(|a: i64, b| if a == b { 1 } else { 0 })?,
         // ine_N
-        Instr::I32Ne => stack.relop(|a: i32, b| if a != b { 1 } else { 0 }),
-        Instr::I64Ne => stack.relop(|a: i64, b| if a != b { 1 } else { 0 }),
+        Instr::I32Ne => stack.relop(|a: i32, b| if a != b { 1 } else { 0 })?,
+        Instr::I64Ne => stack.relop(|a: i64, b| if a != b { 1 } else { 0 })?,
         // ilt_u_N
-        Instr::I32LtU => stack.relop(|a: i32, b| if (a as u32) < b as u32 { 1 } else { 0 }),
-        Instr::I64LtU => stack.relop(|a: i64, b| if (a as u64) < b as u64 { 1 } else { 0 }),
+        Instr::I32LtU => stack.relop(|a: i32, b| if (a as u32) < b as u32 { 1 } else { 0 })?,
+        Instr::I64LtU => stack.relop(|a: i64, b| if (a as u64) < b as u64 { 1 } else { 0 })?,
         // ilt_s_N
-        Instr::I32LtS => stack.relop(|a: i32, b| if a < b { 1 } else { 0 }),
-        Instr::I64LtS => stack.relop(|a: i64, b| if a < b { 1 } else { 0 }),
+        Instr::I32LtS => stack.relop(|a: i32, b| if a < b { 1 } else { 0 })?,
+        Instr::I64LtS => stack.relop(|a: i64, b| if a < b { 1 } else { 0 })?,
         // igt_u_N
-        Instr::I32GtU => stack.relop(|a: i32, b| if a as u32 > b as u32 { 1 } else { 0 }),
-        Instr::I64GtU => stack.relop(|a: i64, b| if a as u64 > b as u64 { 1 } else { 0 }),
+        Instr::I32GtU => stack.relop(|a: i32, b| if a as u32 > b as u32 { 1 } else { 0 })?,
+        Instr::I64GtU => stack.relop(|a: i64, b| if a as u64 > b as u64 { 1 } else { 0 })?,
         // igt_s_N
-        Instr::I32GtS => stack.relop(|a: i32, b| if a > b { 1 } else { 0 }),
-        Instr::I64GtS => stack.relop(|a: i64, b| if a > b { 1 } else { 0 }),
+        Instr::I32GtS => stack.relop(|a: i32, b| if a > b { 1 } else { 0 })?,
+        Instr::I64GtS => stack.relop(|a: i64, b| if a > b { 1 } else { 0 })?,
         // ile_u_N
-        Instr::I32LeU => stack.relop(|a: i32, b| if a as u32 <= b as u32 { 1 } else { 0 }),
-        Instr::I64LeU => stack.relop(|a: i64, b| if a as u64 <= b as u64 { 1 } else { 0 }),
+        Instr::I32LeU => stack.relop(|a: i32, b| if a as u32 <= b as u32 { 1 } else { 0 })?,
+        Instr::I64LeU => stack.relop(|a: i64, b| if a as u64 <= b as u64 { 1 } else { 0 })?,
         // ile_s_N
-        Instr::I32LeS => stack.relop(|a: i32, b| if a <= b { 1 } else { 0 }),
-        Instr::I64LeS => stack.relop(|a: i64, b| if a <= b { 1 } else { 0 }),
+        Instr::I32LeS => stack.relop(|a: i32, b| if a <= b { 1 } else { 0 })?,
+        Instr::I64LeS => stack.relop(|a: i64, b| if a <= b { 1 } else { 0 })?,
         // ige_u_N
-        Instr::I32GeU => stack.relop(|a: i32, b| if a as u32 >= b as u32 { 1 } else { 0 }),
-        Instr::I64GeU => stack.relop(|a: i64, b| if a as u64 >= b as u64 { 1 } else { 0 }),
+        Instr::I32GeU => stack.relop(|a: i32, b| if a as u32 >= b as u32 { 1 } else { 0 })?,
+        Instr::I64GeU => stack.relop(|a: i64, b| if a as u64 >= b as u64 { 1 } else { 0 })?,
         // ige_s_N
-        Instr::I32GeS => stack.relop(|a: i32, b| if a >= b { 1 } else { 0 }),
-        Instr::I64GeS => stack.relop(|a: i64, b| if a >= b { 1 } else { 0 }),
+        Instr::I32GeS => stack.relop(|a: i32, b| if a >= b { 1 } else { 0 })?,
+        Instr::I64GeS => stack.relop(|a: i64, b| if a >= b { 1 } else { 0 })?,
         // fadd_N
-        Instr::F32Add => stack.binop(|a: f32, b| a + b),
-        Instr::F64Add => stack.binop(|a: f64, b| a + b),
+        Instr::F32Add => stack.binop(|a: f32, b| a + b)?,
+        Instr::F64Add => stack.binop(|a: f64, b| a + b)?,
         // fsub_N
-        Instr::F32Sub => stack.binop(|a: f32, b| a - b),
-        Instr::F64Sub => stack.binop(|a: f64, b| a - b),
+        Instr::F32Sub => stack.binop(|a: f32, b| a - b)?,
+        Instr::F64Sub => stack.binop(|a: f64, b| a - b)?,
         // fmul_N
-        Instr::F32Mul => stack.binop(|a: f32, b| a * b),
-        Instr::F64Mul => stack.binop(|a: f64, b| a * b),
+        Instr::F32Mul => stack.binop(|a: f32, b| a * b)?,
+        Instr::F64Mul => stack.binop(|a: f64, b| a * b)?,
         // fdiv_N
-        Instr::F32Div => stack.binop(|a: f32, b| a / b),
-        Instr::F64Div => stack.binop(|a: f64, b| a / b),
+        Instr::F32Div => stack.binop(|a: f32, b| canonical_nan(a / b))?,
+        Instr::F64Div => stack.binop(|a: f64, b| canonical_nan(a / b))?,
         // fmin_N
         Instr::F32Min => stack.binop(|a: f32, b| {
             if a.is_nan() || b.is_nan() {
@@ -410,14 +385,14 @@ pub fn step(
             } else {
                 a.min(b)
             }
-        }),
+        })?,
         Instr::F64Min => stack.binop(|a: f64, b| {
             if a.is_nan() || b.is_nan() {
                 f64::NAN
             } else {
                 a.min(b)
             }
-        }),
+        })?,
         // fmax_N
         Instr::F32Max => stack.binop(|a: f32, b| {
             if a.is_nan() || b.is_nan() {
@@ -425,35 +400,35 @@ pub fn step(
             } else {
                 a.max(b)
             }
-        }),
+        })?,
         Instr::F64Max => stack.binop(|a: f64, b| {
             if a.is_nan() || b.is_nan() {
                 f64::NAN
             } else {
                 a.max(b)
             }
-        }),
+        })?,
         // fcopysign_N
-        Instr::F32Copysign => stack.binop(|a: f32, b: f32| Float::copysign(a, b)),
-        Instr::F64Copysign => stack.binop(|a: f64, b: f64| Float::copysign(a, b)),
+        Instr::F32Copysign => stack.binop(|a: f32, b: f32| Float::copysign(a, b))?,
+        Instr::F64Copysign => stack.binop(|a: f64, b: f64| Float::copysign(a, b))?,
         // fabs_N
-        Instr::F32Abs => stack.unop(|f: f32| Float::abs(f)),
-        Instr::F64Abs => stack.unop(|f: f64| Float::abs(f)),
+        Instr::F32Abs => stack.unop(|f: f32| Float::abs(f))?,
+        Instr::F64Abs => stack.unop(|f: f64| Float::abs(f))?,
         // fneg_N
-        Instr::F32Neg => stack.unop(f32::neg),
-        Instr::F64Neg => stack.unop(f64::neg),
+        Instr::F32Neg => stack.unop(f32::neg)?,
+        Instr::F64Neg => stack.unop(f64::neg)?,
         // fsqrt_N
-        Instr::F32Sqrt => stack.unop(|f: f32| Float::sqrt(f)),
-        Instr::F64Sqrt => stack.unop(|f: f64| Float::sqrt(f)),
+        Instr::F32Sqrt => stack.unop(|f: f32| canonical_nan(Float::sqrt(f)))?,
+        Instr::F64Sqrt => stack.unop(|f: f64| canonical_nan(Float::sqrt(f)))?,
         // fceil_N
-        Instr::F32Ceil => stack.unop(|f: f32| Float::ceil(f)),
-        Instr::F64Ceil => stack.unop(|f: f64| Float::ceil(f)),
+        Instr::F32Ceil => stack.unop(|f: f32| Float::ceil(f))?,
+        Instr::F64Ceil => stack.unop(|f: f64| Float::ceil(f))?,
         // ffloor_N
-        Instr::F32Floor => stack.unop(|f: f32| Float::floor(f)),
-        Instr::F64Floor => stack.unop(|f: f64| Float::floor(f)),
+        Instr::F32Floor => stack.unop(|f: f32| Float::floor(f))?,
+        Instr::F64Floor => stack.unop(|f: f64| Float::floor(f))?,
         // ftrunc_N
-        Instr::F32Trunc => stack.unop(|f: f32| Float::trunc(f)),
-        Instr::F64Trunc => stack.unop(|f: f64| Float::trunc(f)),
+        Instr::F32Trunc => stack.unop(|f: f32| Float::trunc(f))?,
+        Instr::F64Trunc => stack.unop(|f: f64| Float::trunc(f))?,
         // fnearest_N
         Instr::F32Nearest => stack.unop(|v: f32| {
             let fround = Float::round(v);
@@ -462,7 +437,7 @@ pub fn step(
             } else {
                 fround
             }
-        }),
+        })?,
         Instr::F64Nearest => stack.unop(|v: f64| {
             let fround = Float::round(v);
             if Float::abs(v - fround) == 0.5 && fround % 2.0 != 0.0 {
@@ -470,30 +445,30 @@ pub fn step(
             } else {
                 fround
             }
-        }),
+        })?,
         // feq_N
-        Instr::F32Eq => stack.relop(|a: f32, b| if a == b { 1 } else { 0 }),
-        Instr::F64Eq => stack.relop(|a: f64, b| if a == b { 1 } else { 0 }),
+        Instr::F32Eq => stack.relop(|a: f32, b| if a == b { 1 } else { 0 })?,
+        Instr::F64Eq => stack.relop(|a: f64, b| if a == b { 1 } else { 0 })?,
         // fne_N
-        Instr::F32Ne => stack.relop(|a: f32, b| if a != b { 1 } else { 0 }),
-        Instr::F64Ne => stack.relop(|a: f64, b| if a != b { 1 } else { 0 }),
+        Instr::F32Ne => stack.relop(|a: f32, b| if a != b { 1 } else { 0 })?,
+        Instr::F64Ne => stack.relop(|a: f64, b| if a != b { 1 } else { 0 })?,
         // flt_N
-        Instr::F32Lt => stack.relop(|a: f32, b| if a < b { 1 } else { 0 }),
-        Instr::F64Lt => stack.relop(|a: f64, b| if a < b { 1 } else { 0 }),
+        Instr::F32Lt => stack.relop(|a: f32, b| if a < b { 1 } else { 0 })?,
+        Instr::F64Lt => stack.relop(|a: f64, b| if a < b { 1 } else { 0 })?,
         // fgt_N
-        Instr::F32Gt => stack.relop(|a: f32, b| if a > b { 1 } else { 0 }),
-        Instr::F64Gt => stack.relop(|a: f64, b| if a > b { 1 } else { 0 }),
+        Instr::F32Gt => stack.relop(|a: f32, b| if a > b { 1 } else { 0 })?,
+        Instr::F64Gt => stack.relop(|a: f64, b| if a > b { 1 } else { 0 })?,
         // fle_N
-        Instr::F32Le => stack.relop(|a: f32, b| if a <= b { 1 } else { 0 }),
-        Instr::F64Le => stack.relop(|a: f64, b| if a <= b { 1 } else { 0 }),
+        Instr::F32Le => stack.relop(|a: f32, b| if a <= b { 1 } else { 0 })?,
+        Instr::F64Le => stack.relop(|a: f64, b| if a <= b { 1 } else { 0 })?,
         // fge_N
-        Instr::F32Ge => stack.relop(|a: f32, b| if a >= b { 1 } else { 0 }),
-        Instr::F64Ge => stack.relop(|a: f64, b| if a >= b { 1 } else { 0 }),
+        Instr::F32Ge => stack.relop(|a: f32, b| if a >= b { 1 } else { 0 })?,
+        Instr::F64Ge => stack.relop(|a: f64, b| if a >= b { 1 } else { 0 })?,
 
         // conversion, shrink or expand
-        Instr::I64ExtendI32U => stack.cvtop(|v: i32| v as u32 as i64),
-        Instr::I64ExtendI32S => stack.cvtop(|v: i32| v as i64),
-        Instr::I32WrapI64 => stack.cvtop(|v: i64| v as i32),
+        Instr::I64ExtendI32U => stack.cvtop(|v: i32| v as u32 as i64)?,
+        Instr::I64ExtendI32S => stack.cvtop(|v: i32| v as i64)?,
+        Instr::I32WrapI64 => stack.cvtop(|v: i64| v as i32)?,
         Instr::I32TruncF32U => stack.cvtop_trap(|v: f32| cast::f32_to_u32(v).map(|v| v as i32))?,
         Instr::I32TruncF64U => stack.cvtop_trap(|v: f64| cast::f64_to_u32(v).map(|v| v as i32))?,
         Instr::I64TruncF32U => stack.cvtop_trap(|v: f32| cast::f32_to_u64(v).map(|v| v as i64))?,
@@ -502,77 +477,271 @@ pub fn step(
         Instr::I32TruncF64S => stack.cvtop_trap(cast::f64_to_i32)?,
         Instr::I64TruncF32S => stack.cvtop_trap(cast::f32_to_i64)?,
         Instr::I64TruncF64S => stack.cvtop_trap(cast::f64_to_i64)?,
-        Instr::F64PromoteF32 => stack.cvtop(|v: f32| v as f64),
-        Instr::F32DemoteF64 => stack.cvtop(|v: f64| v as f32),
-        Instr::F32ConvertI32U => stack.cvtop(|v: i32| v as u32 as f32),
-        Instr::F32ConvertI64U => stack.cvtop(|v: i64| v as u64 as f32),
-        Instr::F64ConvertI32U => stack.cvtop(|v: i32| v as u32 as f64),
-        Instr::F64ConvertI64U => stack.cvtop(|v: i64| v as u64 as f64),
-        Instr::F32ConvertI32S => stack.cvtop(|v: i32| v as f32),
-        Instr::F32ConvertI64S => stack.cvtop(|v: i64| v as f32),
-        Instr::F64ConvertI32S => stack.cvtop(|v: i32| v as f64),
-        Instr::F64ConvertI64S => stack.cvtop(|v: i64| v as f64),
-        Instr::I32ReinterpretF32 => {
-            stack.cvtop(|v: f32| unsafe { core::mem::transmute::<f32, i32>(v) })
-        }
-        Instr::I64ReinterpretF64 => {
-            stack.cvtop(|v: f64| unsafe { core::mem::transmute::<f64, i64>(v) })
-        }
-        Instr::F32ReinterpretI32 => {
-            stack.cvtop(|v: i32| unsafe { core::mem::transmute::<i32, f32>(v) })
-        }
-        Instr::F64ReinterpretI64 => {
-            stack.cvtop(|v: i64| unsafe { core::mem::transmute::<i64, f64>(v) })
-        }
-        Instr::I32Extend8S => stack.unop(|v: i32| (v as i8) as i32),
-        Instr::I32Extend16S => stack.unop(|v: i32| (v as i16) as i32),
-        Instr::I64Extend8S => stack.unop(|v: i64| (v as i8) as i64),
-        Instr::I64Extend16S => stack.unop(|v: i64| (v as i16) as i64),
-        Instr::I64Extend32S => stack.unop(|v: i64| (v as i32) as i64),
-        Instr::I32TruncSatF32S => stack.cvtop(|v: f32| cast::f32_to_i32_sat(v)),
-        Instr::I32TruncSatF32U => stack.cvtop(|v: f32| cast::f32_to_u32_sat(v) as i32),
-        Instr::I32TruncSatF64S => stack.cvtop(|v: f64| cast::f64_to_i32_sat(v)),
-        Instr::I32TruncSatF64U => stack.cvtop(|v: f64| cast::f64_to_u32_sat(v) as i32),
-        Instr::I64TruncSatF32S => stack.cvtop(|v: f32| cast::f32_to_i64_sat(v)),
-        Instr::I64TruncSatF32U => stack.cvtop(|v: f32| cast::f32_to_u64_sat(v) as i64),
-        Instr::I64TruncSatF64S => stack.cvtop(|v: f64| cast::f64_to_i64_sat(v)),
-        Instr::I64TruncSatF64U => stack.cvtop(|v: f64| cast::f64_to_u64_sat(v) as i64),
+        Instr::F64PromoteF32 => stack.cvtop(|v: f32| v as f64)?,
+        Instr::F32DemoteF64 => stack.cvtop(|v: f64| v as f32)?,
+        Instr::F32ConvertI32U => stack.cvtop(|v: i32| v as u32 as f32)?,
+        Instr::F32ConvertI64U => stack.cvtop(|v: i64| v as u64 as f32)?,
+        Instr::F64ConvertI32U => stack.cvtop(|v: i32| v as u32 as f64)?,
+        Instr::F64ConvertI64U => stack.cvtop(|v: i64| v as u64 as f64)?,
+        Instr::F32ConvertI32S => stack.cvtop(|v: i32| v as f32)?,
+        Instr::F32ConvertI64S => stack.cvtop(|v: i64| v as f32)?,
+        Instr::F64ConvertI32S => stack.cvtop(|v: i32| v as f64)?,
+        Instr::F64ConvertI64S => stack.cvtop(|v: i64| v as f64)?,
+        Instr::I32ReinterpretF32 => stack.cvtop(|v: f32| v.to_bits() as i32)?,
+        Instr::I64ReinterpretF64 => stack.cvtop(|v: f64| v.to_bits() as i64)?,
+        Instr::F32ReinterpretI32 => stack.cvtop(|v: i32| f32::from_bits(v as u32))?,
+        Instr::F64ReinterpretI64 => stack.cvtop(|v: i64| f64::from_bits(v as u64))?,
+        Instr::I32Extend8S => stack.unop(|v: i32| (v as i8) as i32)?,
+        Instr::I32Extend16S => stack.unop(|v: i32| (v as i16) as i32)?,
+        Instr::I64Extend8S => stack.unop(|v: i64| (v as i8) as i64)?,
+        Instr::I64Extend16S => stack.unop(|v: i64| (v as i16) as i64)?,
+        Instr::I64Extend32S => stack.unop(|v: i64| (v as i32) as i64)?,
+        Instr::I32TruncSatF32S => stack.cvtop(|v: f32| cast::f32_to_i32_sat(v))?,
+        Instr::I32TruncSatF32U => stack.cvtop(|v: f32| cast::f32_to_u32_sat(v) as i32)?,
+        Instr::I32TruncSatF64S => stack.cvtop(|v: f64| cast::f64_to_i32_sat(v))?,
+        Instr::I32TruncSatF64U => stack.cvtop(|v: f64| cast::f64_to_u32_sat(v) as i32)?,
+        Instr::I64TruncSatF32S => stack.cvtop(|v: f32| cast::f32_to_i64_sat(v))?,
+        Instr::I64TruncSatF32U => stack.cvtop(|v: f32| cast::f32_to_u64_sat(v) as i64)?,
+        Instr::I64TruncSatF64S => stack.cvtop(|v: f64| cast::f64_to_i64_sat(v))?,
+        Instr::I64TruncSatF64U => stack.cvtop(|v: f64| cast::f64_to_u64_sat(v) as i64)?,
+
+        /////////////////////////
+        // Vector Instructions //
+        /////////////////////////
+        Instr::V128Load(memarg) => memory::v128_load(memarg, instance, store, stack, hooks)?,
+        Instr::V128Store(memarg) => memory::v128_store(memarg, instance, store, stack, hooks)?,
+        Instr::V128Const(v) => stack.push_value(*v),
+        Instr::I8x16Splat => stack.cvtop(|v: i32| simd::from_i8x16([v as i8; 16]))?,
+        Instr::I16x8Splat => stack.cvtop(|v: i32| simd::from_i16x8([v as i16; 8]))?,
+        Instr::I32x4Splat => stack.cvtop(|v: i32| simd::from_i32x4([v; 4]))?,
+        Instr::I64x2Splat => stack.cvtop(|v: i64| simd::from_i64x2([v; 2]))?,
+        Instr::F32x4Splat => stack.cvtop(|v: f32| simd::from_f32x4([v; 4]))?,
+        Instr::F64x2Splat => stack.cvtop(|v: f64| simd::from_f64x2([v; 2]))?,
+        Instr::I8x16ExtractLaneS(l) => {
+            stack.cvtop(|v: simd::V128| simd::i8x16(v)[*l as usize] as i32)?
+        }
+        Instr::I8x16ExtractLaneU(l) => {
+            stack.cvtop(|v: simd::V128| simd::i8x16(v)[*l as usize] as u8 as i32)?
+        }
+        Instr::I16x8ExtractLaneS(l) => {
+            stack.cvtop(|v: simd::V128| simd::i16x8(v)[*l as usize] as i32)?
+        }
+        Instr::I16x8ExtractLaneU(l) => {
+            stack.cvtop(|v: simd::V128| simd::i16x8(v)[*l as usize] as u16 as i32)?
+        }
+        Instr::I32x4ExtractLane(l) => stack.cvtop(|v: simd::V128| simd::i32x4(v)[*l as usize])?,
+        Instr::I64x2ExtractLane(l) => stack.cvtop(|v: simd::V128| simd::i64x2(v)[*l as usize])?,
+        Instr::F32x4ExtractLane(l) => stack.cvtop(|v: simd::V128| simd::f32x4(v)[*l as usize])?,
+        Instr::F64x2ExtractLane(l) => stack.cvtop(|v: simd::V128| simd::f64x2(v)[*l as usize])?,
+        Instr::I32x4Add => stack.binop(|a, b| {
+            simd::from_i32x4(simd::lanewise(
+                simd::i32x4(a),
+                simd::i32x4(b),
+                i32::wrapping_add,
+            ))
+        })?,
+        Instr::I32x4Sub => stack.binop(|a, b| {
+            simd::from_i32x4(simd::lanewise(
+                simd::i32x4(a),
+                simd::i32x4(b),
+                i32::wrapping_sub,
+            ))
+        })?,
+        Instr::I32x4Mul => stack.binop(|a, b| {
+            simd::from_i32x4(simd::lanewise(
+                simd::i32x4(a),
+                simd::i32x4(b),
+                i32::wrapping_mul,
+            ))
+        })?,
+        Instr::F32x4Add => stack.binop(|a, b| {
+            simd::from_f32x4(simd::lanewise(simd::f32x4(a), simd::f32x4(b), |x, y| x + y))
+        })?,
+        Instr::F32x4Sub => stack.binop(|a, b| {
+            simd::from_f32x4(simd::lanewise(simd::f32x4(a), simd::f32x4(b), |x, y| x - y))
+        })?,
+        Instr::F32x4Mul => stack.binop(|a, b| {
+            simd::from_f32x4(simd::lanewise(simd::f32x4(a), simd::f32x4(b), |x, y| x * y))
+        })?,
+        Instr::F32x4Div => stack.binop(|a, b| {
+            simd::from_f32x4(simd::lanewise(simd::f32x4(a), simd::f32x4(b), |x, y| x / y))
+        })?,
+
+        /////////////////////////
+        // Atomic Instructions //
+        /////////////////////////
+        Instr::MemoryAtomicNotify(memarg) => {
+            memory::memory_atomic_notify(memarg, instance, store, stack)?
+        }
+        Instr::MemoryAtomicWait32(memarg) => {
+            memory::memory_atomic_wait::<i32>(memarg, instance, store, stack)?
+        }
+        Instr::MemoryAtomicWait64(memarg) => {
+            memory::memory_atomic_wait::<i64>(memarg, instance, store, stack)?
+        }
+        Instr::AtomicFence => {}
 
         //////////////////////////
         // Pseudo Instructions ///
         //////////////////////////
         Instr::RJump(r) => return Ok(ExecState::Continue(*r + pc)),
         Instr::PopLabel => {
-            stack.pop_label();
+            stack.pop_label()?;
+        }
+    }
+    if hooks.trap_on_nan && is_float_arith(&instrs[pc]) {
+        let nan = match stack.values().last() {
+            Some(Value::F32(v)) => v.is_nan(),
+            Some(Value::F64(v)) => v.is_nan(),
+            Some(Value::V128(v)) => simd::f32x4(*v).iter().any(|f| f.is_nan()),
+            _ => false,
+        };
+        if nan {
+            return Err(Trap::UnexpectedNaN);
         }
     }
     Ok(ExecState::Continue(pc + 1))
 }
 
-pub fn unwind_stack(frame: &Frame, stack: &mut Stack) -> Option<usize> {
+/// Whether `instr` is float arithmetic, which can produce a NaN. `abs`,
+/// `neg` and `copysign` only operate on the sign bit and pass NaNs through.
+fn is_float_arith(instr: &Instr) -> bool {
+    use Instr::*;
+    matches!(
+        instr,
+        F32Ceil
+            | F32Floor
+            | F32Trunc
+            | F32Nearest
+            | F32Sqrt
+            | F32Add
+            | F32Sub
+            | F32Mul
+            | F32Div
+            | F32Min
+            | F32Max
+            | F64Ceil
+            | F64Floor
+            | F64Trunc
+            | F64Nearest
+            | F64Sqrt
+            | F64Add
+            | F64Sub
+            | F64Mul
+            | F64Div
+            | F64Min
+            | F64Max
+            | F32DemoteF64
+            | F64PromoteF32
+            | F32x4Add
+            | F32x4Sub
+            | F32x4Mul
+            | F32x4Div
+    )
+}
+
+/// Branches to label `l`, returning from the function when it names the
+/// function's own label rather than one of its blocks.
+fn branch(l: LabelIdx, frame: &Frame, stack: &mut Stack) -> Result<ExecState, Trap> {
+    if l as usize >= stack.labels_len().saturating_sub(frame.label_offset) {
+        return match unwind_stack(frame, stack)? {
+            Some(new_pc) => Ok(ExecState::Continue(new_pc)),
+            None => Ok(ExecState::Return),
+        };
+    }
+    let new_pc = stack.jump(l as usize)?;
+    Ok(ExecState::Continue(new_pc))
+}
+
+/// Where the values of a block with `params` parameters start. An
+/// unvalidated module may not have pushed them.
+fn block_offset(params: usize, frame: &Frame, stack: &Stack) -> Result<usize, Trap> {
+    stack
+        .values_len()
+        .checked_sub(params)
+        .filter(|&offset| offset >= frame.stack_offset)
+        .ok_or(Trap::StackUnderflow)
+}
+
+/// Replaces any NaN with the positive canonical NaN, so that results do not
+/// depend on the payload the host FPU happens to produce.
+fn canonical_nan<F: Float>(f: F) -> F {
+    if f.is_nan() {
+        F::nan()
+    } else {
+        f
+    }
+}
+
+pub fn unwind_stack(frame: &Frame, stack: &mut Stack) -> Result<Option<usize>, Trap> {
     let n = frame.n;
     let mut results: Vec<Value> = vec![];
     for _ in 0..n {
-        results.push(stack.pop_value());
+        results.push(stack.pop_value()?);
     }
     stack.values_unwind(frame.stack_offset);
     for _ in 0..n {
         stack.push_value(results.pop().unwrap());
     }
-    stack.pop_frame();
+    stack.labels_unwind(frame.label_offset);
+    stack.pop_frame()?;
     if stack.frames_len() == 0 {
-        None
+        Ok(None)
     } else {
-        Some(frame.pc)
+        Ok(Some(frame.pc))
+    }
+}
+
+/// Number of values `instr` pops, for the instructions whose operands are
+/// checked before executing them; others report zero.
+fn operand_count(instr: &Instr) -> usize {
+    use Instr::*;
+    match instr {
+        Select | MemoryAtomicWait32(_) | MemoryAtomicWait64(_) => 3,
+        I32Eq | I32Ne | I32LtS | I32LtU | I32GtS | I32GtU | I32LeS | I32LeU | I32GeS | I32GeU
+        | I64Eq | I64Ne | I64LtS | I64LtU | I64GtS | I64GtU | I64LeS | I64LeU | I64GeS | I64GeU
+        | F32Eq | F32Ne | F32Lt | F32Gt | F32Le | F32Ge | F64Eq | F64Ne | F64Lt | F64Gt | F64Le
+        | F64Ge | I32Add | I32Sub | I32Mul | I32DivS | I32DivU | I32RemS | I32RemU | I32And
+        | I32Or | I32Xor | I32Shl | I32ShrS | I32ShrU | I32RotL | I32RotR | I64Add | I64Sub
+        | I64Mul | I64DivS | I64DivU | I64RemS | I64RemU | I64And | I64Or | I64Xor | I64Shl
+        | I64ShrS | I64ShrU | I64RotL | I64RotR | F32Add | F32Sub | F32Mul | F32Div | F32Min
+        | F32Max | F32Copysign | F64Add | F64Sub | F64Mul | F64Div | F64Min | F64Max
+        | F64Copysign | I32x4Add | I32x4Sub | I32x4Mul | F32x4Add | F32x4Sub | F32x4Mul
+        | F32x4Div => 2,
+        MemoryAtomicNotify(_) => 2,
+        Drop | RefIsNull | I32Eqz | I64Eqz | I32Clz | I32Ctz | I32Popcnt | I64Clz | I64Ctz
+        | I64Popcnt | F32Abs | F32Neg | F32Ceil | F32Floor | F32Trunc | F32Nearest | F32Sqrt
+        | F64Abs | F64Neg | F64Ceil | F64Floor | F64Trunc | F64Nearest | F64Sqrt | I32WrapI64
+        | I32TruncF32S | I32TruncF32U | I32TruncF64S | I32TruncF64U | I64ExtendI32S
+        | I64ExtendI32U | I64TruncF32S | I64TruncF32U | I64TruncF64S | I64TruncF64U
+        | F32ConvertI32S | F32ConvertI32U | F32ConvertI64S | F32ConvertI64U | F32DemoteF64
+        | F64ConvertI32S | F64ConvertI32U | F64ConvertI64S | F64ConvertI64U | F64PromoteF32
+        | I32ReinterpretF32 | I64ReinterpretF64 | F32ReinterpretI32 | F64ReinterpretI64
+        | I32Extend8S | I32Extend16S | I64Extend8S | I64Extend16S | I64Extend32S
+        | I32TruncSatF32S | I32TruncSatF32U | I32TruncSatF64S | I32TruncSatF64U
+        | I64TruncSatF32S | I64TruncSatF32U | I64TruncSatF64S | I64TruncSatF64U | I8x16Splat
+        | I16x8Splat | I32x4Splat | I64x2Splat | F32x4Splat | F64x2Splat | I8x16ExtractLaneS(_)
+        | I8x16ExtractLaneU(_) | I16x8ExtractLaneS(_) | I16x8ExtractLaneU(_)
+        | I32x4ExtractLane(_) | I64x2ExtractLane(_) | F32x4ExtractLane(_) | F64x2ExtractLane(_) => {
+            1
+        }
+        _ => 0,
     }
 }
 
 pub fn attach(func: &FuncInst, stack: &mut Stack, pc: usize) -> Result<ExecState, Trap> {
     match func {
-        FuncInst::HostFunc { name, functype } => {
+        FuncInst::HostFunc {
+            name,
+            functype,
+            stub,
+        } => {
+            if *stub {
+                return Err(Trap::UnresolvedImport(name.clone()));
+            }
             let mut local = vec![];
             for _ in 0..functype.0 .0.len() {
-                local.push(stack.pop_value());
+                local.push(stack.pop_value()?);
             }
             local.reverse();
 
@@ -587,9 +756,12 @@ pub fn attach(func: &FuncInst, stack: &mut Stack, pc: usize) -> Result<ExecState
             locals,
             start,
         } => {
+            if stack.frames_len() >= MAX_CALL_DEPTH {
+                return Err(Trap::CallStackExhausted);
+            }
             let mut local = vec![];
             for _ in 0..functype.0 .0.len() {
-                local.push(stack.pop_value());
+                local.push(stack.pop_value()?);
             }
             local.reverse();
             for val in locals.iter() {
@@ -598,7 +770,9 @@ pub fn attach(func: &FuncInst, stack: &mut Stack, pc: usize) -> Result<ExecState
                     ValType::I64 => local.push(Value::I64(0)),
                     ValType::F32 => local.push(Value::F32(0.0)),
                     ValType::F64 => local.push(Value::F64(0.0)),
-                    _ => todo!(),
+                    ValType::V128 => local.push(Value::V128([0; 16])),
+                    ValType::FuncRef => local.push(Value::Ref(Ref::Null(RefType::FuncRef))),
+                    ValType::ExternRef => local.push(Value::Ref(Ref::Null(RefType::ExternRef))),
                 }
             }
             let new_frame = Frame {
@@ -606,6 +780,7 @@ pub fn attach(func: &FuncInst, stack: &mut Stack, pc: usize) -> Result<ExecState
                 instance_addr: *instance_addr,
                 local,
                 stack_offset: stack.values_len(),
+                label_offset: stack.labels_len(),
                 pc: pc + 1,
             };
             stack.push_frame(new_frame);
@@ -618,8 +793,9 @@ pub fn attach(func: &FuncInst, stack: &mut Stack, pc: usize) -> Result<ExecState
 mod tests {
     use super::step;
     use crate::{
-        binary::Instr,
+        binary::{Block, Expr, FuncType, Global, GlobalType, Instr, Mut, ResultType, ValType},
         exec::{
+            hook::Hooks,
             runtime::Instance,
             stack::{Frame, Stack},
             store::Store,
@@ -627,15 +803,16 @@ mod tests {
             value::Value,
         },
     };
+    use std::sync::Arc;
 
     fn test_instr(
-        instrs: &Vec<Instr>,
+        instrs: &[Instr],
         stack: &mut Stack,
         store: &mut Store,
-        instances: &mut Vec<Instance>,
+        instances: &mut [Instance],
     ) -> Result<(), Trap> {
         for pc in 0..instrs.len() {
-            step(instances, instrs, pc, store, stack).map(|_| ())?;
+            step(instances, instrs, pc, store, stack, &mut Hooks::default()).map(|_| ())?;
         }
         Ok(())
     }
@@ -654,11 +831,335 @@ mod tests {
         assert_eq!(stack.values(), &vec![Value::I32(0b11111)]);
     }
 
+    #[test]
+    fn extend_i32() {
+        let (mut stack, mut store, mut instances) = default();
+        let instrs = vec![Instr::I32Const(-1), Instr::I64ExtendI32U];
+        test_instr(&instrs, &mut stack, &mut store, &mut instances).unwrap();
+        assert_eq!(stack.values(), &vec![Value::I64(0x0000_0000_FFFF_FFFF)]);
+
+        let (mut stack, mut store, mut instances) = default();
+        let instrs = vec![Instr::I32Const(-1), Instr::I64ExtendI32S];
+        test_instr(&instrs, &mut stack, &mut store, &mut instances).unwrap();
+        assert_eq!(stack.values(), &vec![Value::I64(-1)]);
+    }
+
+    #[test]
+    fn wrap_i64() {
+        let (mut stack, mut store, mut instances) = default();
+        let instrs = vec![Instr::I64Const(0x1_0000_0001), Instr::I32WrapI64];
+        test_instr(&instrs, &mut stack, &mut store, &mut instances).unwrap();
+        assert_eq!(stack.values(), &vec![Value::I32(1)]);
+    }
+
     #[test]
     fn reinterpret() {
         let (mut stack, mut store, mut instances) = default();
         let instrs = vec![Instr::F32Const(-0.0), Instr::I32ReinterpretF32];
         test_instr(&instrs, &mut stack, &mut store, &mut instances).unwrap();
         assert_eq!(stack.values(), &vec![Value::I32(-2147483648)]);
+
+        let (mut stack, mut store, mut instances) = default();
+        let instrs = vec![Instr::F64Const(-0.0), Instr::I64ReinterpretF64];
+        test_instr(&instrs, &mut stack, &mut store, &mut instances).unwrap();
+        assert_eq!(stack.values(), &vec![Value::I64(i64::MIN)]);
+
+        // `Value` compares finite floats loosely, so check the bits.
+        let (mut stack, mut store, mut instances) = default();
+        let instrs = vec![Instr::I32Const(i32::MIN), Instr::F32ReinterpretI32];
+        test_instr(&instrs, &mut stack, &mut store, &mut instances).unwrap();
+        assert!(matches!(
+            stack.values()[..],
+            [Value::F32(v)] if v.to_bits() == (-0.0f32).to_bits()
+        ));
+
+        let (mut stack, mut store, mut instances) = default();
+        let instrs = vec![Instr::I64Const(0x3ff8 << 48), Instr::F64ReinterpretI64];
+        test_instr(&instrs, &mut stack, &mut store, &mut instances).unwrap();
+        assert!(matches!(stack.values()[..], [Value::F64(v)] if v == 1.5));
+    }
+
+    #[test]
+    fn bit_count() {
+        let cases = [
+            (Instr::I32Const(0), Instr::I32Clz, Value::I32(32)),
+            (Instr::I32Const(0), Instr::I32Ctz, Value::I32(32)),
+            (Instr::I32Const(-1), Instr::I32Popcnt, Value::I32(32)),
+            (Instr::I64Const(0), Instr::I64Clz, Value::I64(64)),
+            (Instr::I64Const(0), Instr::I64Ctz, Value::I64(64)),
+            (Instr::I64Const(-1), Instr::I64Popcnt, Value::I64(64)),
+            (Instr::I64Const(1), Instr::I64Clz, Value::I64(63)),
+            (Instr::I64Const(1 << 40), Instr::I64Ctz, Value::I64(40)),
+        ];
+        for (value, op, expected) in cases {
+            let (mut stack, mut store, mut instances) = default();
+            let instrs = vec![value, op];
+            test_instr(&instrs, &mut stack, &mut store, &mut instances).unwrap();
+            assert_eq!(stack.values(), &vec![expected]);
+        }
+    }
+
+    #[test]
+    fn rem_div_overflow() {
+        let cases = [
+            (
+                vec![
+                    Instr::I32Const(i32::MIN),
+                    Instr::I32Const(-1),
+                    Instr::I32RemS,
+                ],
+                Ok(Value::I32(0)),
+            ),
+            (
+                vec![Instr::I32Const(7), Instr::I32Const(0), Instr::I32RemS],
+                Err(Trap::DivideByZeroInt),
+            ),
+            (
+                vec![
+                    Instr::I32Const(i32::MIN),
+                    Instr::I32Const(-1),
+                    Instr::I32DivS,
+                ],
+                Err(Trap::IntegerOverflow),
+            ),
+            (
+                vec![
+                    Instr::I64Const(i64::MIN),
+                    Instr::I64Const(-1),
+                    Instr::I64RemS,
+                ],
+                Ok(Value::I64(0)),
+            ),
+            (
+                vec![Instr::I64Const(7), Instr::I64Const(0), Instr::I64RemS],
+                Err(Trap::DivideByZeroInt),
+            ),
+            (
+                vec![
+                    Instr::I64Const(i64::MIN),
+                    Instr::I64Const(-1),
+                    Instr::I64DivS,
+                ],
+                Err(Trap::IntegerOverflow),
+            ),
+        ];
+        for (instrs, expected) in cases {
+            let (mut stack, mut store, mut instances) = default();
+            let result = test_instr(&instrs, &mut stack, &mut store, &mut instances)
+                .map(|_| stack.values()[0]);
+            assert_eq!(result, expected);
+        }
+    }
+
+    #[test]
+    fn undefined_global() {
+        let (mut stack, mut store, mut instances) = default();
+        let global = Global {
+            type_: GlobalType {
+                valtype: ValType::I32,
+                mut_: Mut::Var,
+            },
+            value: Expr(vec![Instr::I32Const(5)]),
+        };
+        instances[0]
+            .globaladdrs
+            .push(store.allocate_global(global, &[]).unwrap());
+
+        let instrs = vec![Instr::GlobalGet(0)];
+        test_instr(&instrs, &mut stack, &mut store, &mut instances).unwrap();
+        assert_eq!(stack.values(), &vec![Value::I32(5)]);
+
+        let instrs = vec![Instr::GlobalGet(99)];
+        assert_eq!(
+            test_instr(&instrs, &mut stack, &mut store, &mut instances),
+            Err(Trap::UndefinedGlobal)
+        );
+        let instrs = vec![Instr::I32Const(1), Instr::GlobalSet(99)];
+        assert_eq!(
+            test_instr(&instrs, &mut stack, &mut store, &mut instances),
+            Err(Trap::UndefinedGlobal)
+        );
+    }
+
+    #[test]
+    fn stack_underflow() {
+        let cases = [
+            vec![Instr::Drop],
+            vec![Instr::I32Const(0), Instr::I32Const(1), Instr::Select],
+            vec![Instr::I32Const(1), Instr::I32Add],
+            vec![Instr::F64Neg],
+        ];
+        for instrs in cases {
+            let (mut stack, mut store, mut instances) = default();
+            assert_eq!(
+                test_instr(&instrs, &mut stack, &mut store, &mut instances),
+                Err(Trap::StackUnderflow)
+            );
+        }
+
+        // Values below the current frame belong to the caller.
+        let (mut stack, mut store, mut instances) = default();
+        stack.push_value(Value::I32(1));
+        stack.push_frame(Frame {
+            stack_offset: 1,
+            ..Frame::default()
+        });
+        assert_eq!(
+            test_instr(&[Instr::Drop], &mut stack, &mut store, &mut instances),
+            Err(Trap::StackUnderflow)
+        );
+    }
+
+    #[test]
+    fn copysign() {
+        // Compare bits: the sign of zero and NaN is the point.
+        let cases = [
+            (3.0, -0.0, (-3.0f32).to_bits()),
+            (-3.0, 0.0, 3.0f32.to_bits()),
+            (f32::NAN, -1.0, f32::NAN.to_bits() | 1 << 31),
+        ];
+        for (z1, z2, expected) in cases {
+            let (mut stack, mut store, mut instances) = default();
+            let instrs = vec![Instr::F32Const(z1), Instr::F32Const(z2), Instr::F32Copysign];
+            test_instr(&instrs, &mut stack, &mut store, &mut instances).unwrap();
+            assert!(matches!(stack.values()[..], [Value::F32(v)] if v.to_bits() == expected));
+        }
+
+        let cases = [
+            (3.0, -0.0, (-3.0f64).to_bits()),
+            (-3.0, 0.0, 3.0f64.to_bits()),
+            (f64::NAN, -1.0, f64::NAN.to_bits() | 1 << 63),
+        ];
+        for (z1, z2, expected) in cases {
+            let (mut stack, mut store, mut instances) = default();
+            let instrs = vec![Instr::F64Const(z1), Instr::F64Const(z2), Instr::F64Copysign];
+            test_instr(&instrs, &mut stack, &mut store, &mut instances).unwrap();
+            assert!(matches!(stack.values()[..], [Value::F64(v)] if v.to_bits() == expected));
+        }
+    }
+
+    #[test]
+    fn sqrt_nan() {
+        let (mut stack, mut store, mut instances) = default();
+        let instrs = vec![Instr::F32Const(-1.0), Instr::F32Sqrt];
+        test_instr(&instrs, &mut stack, &mut store, &mut instances).unwrap();
+        assert!(matches!(stack.values()[..], [Value::F32(v)] if v.to_bits() == 0x7FC0_0000));
+
+        let (mut stack, mut store, mut instances) = default();
+        let instrs = vec![Instr::F64Const(-1.0), Instr::F64Sqrt];
+        test_instr(&instrs, &mut stack, &mut store, &mut instances).unwrap();
+        assert!(
+            matches!(stack.values()[..], [Value::F64(v)] if v.to_bits() == 0x7FF8_0000_0000_0000)
+        );
+    }
+
+    #[test]
+    fn div_nan() {
+        let (mut stack, mut store, mut instances) = default();
+        let instrs = vec![Instr::F32Const(0.0), Instr::F32Const(0.0), Instr::F32Div];
+        test_instr(&instrs, &mut stack, &mut store, &mut instances).unwrap();
+        assert!(matches!(stack.values()[..], [Value::F32(v)] if v.to_bits() == 0x7FC0_0000));
+
+        let (mut stack, mut store, mut instances) = default();
+        let instrs = vec![Instr::F64Const(0.0), Instr::F64Const(0.0), Instr::F64Div];
+        test_instr(&instrs, &mut stack, &mut store, &mut instances).unwrap();
+        assert!(
+            matches!(stack.values()[..], [Value::F64(v)] if v.to_bits() == 0x7FF8_0000_0000_0000)
+        );
+    }
+
+    #[test]
+    fn eqz_result_type() {
+        for (v, expected) in [(0, 1), (5, 0)] {
+            let (mut stack, mut store, mut instances) = default();
+            let instrs = vec![Instr::I64Const(v), Instr::I64Eqz];
+            test_instr(&instrs, &mut stack, &mut store, &mut instances).unwrap();
+            assert_eq!(stack.values(), &[Value::I32(expected)]);
+            assert_eq!(stack.values()[0].type_of(), ValType::I32);
+        }
+
+        // The result feeds i32 consumers directly.
+        let (mut stack, mut store, mut instances) = default();
+        let instrs = vec![
+            Instr::I64Const(0),
+            Instr::I64Eqz,
+            Instr::I32Const(41),
+            Instr::I32Add,
+        ];
+        test_instr(&instrs, &mut stack, &mut store, &mut instances).unwrap();
+        assert_eq!(stack.values(), &[Value::I32(42)]);
+    }
+
+    #[test]
+    fn block_params_underflow() {
+        let types = vec![FuncType(ResultType(vec![ValType::I32]), ResultType(vec![]))];
+        for instrs in [
+            vec![Instr::Block {
+                bt: Block::TypeIdx(0),
+                end_offset: 1,
+            }],
+            vec![Instr::Loop {
+                bt: Block::TypeIdx(0),
+            }],
+            vec![
+                Instr::I32Const(1),
+                Instr::If {
+                    bt: Block::TypeIdx(0),
+                    else_offset: None,
+                    end_offset: 1,
+                },
+            ],
+        ] {
+            let (mut stack, mut store, mut instances) = default();
+            instances[0].types = Arc::new(types.clone());
+            assert_eq!(
+                test_instr(&instrs, &mut stack, &mut store, &mut instances),
+                Err(Trap::StackUnderflow)
+            );
+        }
+    }
+
+    #[test]
+    fn branch_with_block_params() {
+        let types = vec![
+            FuncType(
+                ResultType(vec![ValType::I32]),
+                ResultType(vec![ValType::I32, ValType::I64]),
+            ),
+            FuncType(ResultType(vec![ValType::I32]), ResultType(vec![])),
+        ];
+
+        // The parameter is consumed by the block, so a branch out leaves
+        // only the results.
+        let (mut stack, mut store, mut instances) = default();
+        instances[0].types = Arc::new(types.clone());
+        let instrs = vec![
+            Instr::I32Const(5),
+            Instr::Block {
+                bt: Block::TypeIdx(0),
+                end_offset: 5,
+            },
+            Instr::I32Const(0),
+            Instr::I32Const(1),
+            Instr::I64Const(2),
+            Instr::Br(0),
+        ];
+        test_instr(&instrs, &mut stack, &mut store, &mut instances).unwrap();
+        assert_eq!(stack.values(), &vec![Value::I32(1), Value::I64(2)]);
+
+        // A branch back to a loop carries its parameters and drops the rest.
+        let (mut stack, mut store, mut instances) = default();
+        instances[0].types = Arc::new(types);
+        let instrs = vec![
+            Instr::I32Const(5),
+            Instr::Loop {
+                bt: Block::TypeIdx(1),
+            },
+            Instr::I32Const(9),
+            Instr::I32Const(7),
+            Instr::Br(0),
+        ];
+        test_instr(&instrs, &mut stack, &mut store, &mut instances).unwrap();
+        assert_eq!(stack.values(), &vec![Value::I32(7)]);
     }
 }
@@ -1,4 +1,5 @@
 use super::{
+    hook::Hooks,
     runtime::{Addr, Instance},
     stack::Stack,
     store::{DataInst, MemInst, Store},
@@ -8,10 +9,25 @@ use super::{
 use crate::lib::*;
 use crate::{
     binary::{Data, MemArg},
-    exec::{runtime::PAGE_SIZE, value::LittleEndian},
+    exec::{
+        runtime::PAGE_SIZE,
+        value::{LittleEndian, Value},
+    },
 };
 use opt_vec::OptVec;
 
+/// Address of an access of `size` bytes at `i + offset`, or
+/// `MemoryOutOfBounds` if any of it lies past `mem_len`.
+pub fn effective_addr(i: usize, offset: u32, size: usize, mem_len: usize) -> Result<usize, Trap> {
+    let ea = i
+        .checked_add(offset as usize)
+        .ok_or(Trap::MemoryOutOfBounds)?;
+    match ea.checked_add(size) {
+        Some(end) if end <= mem_len => Ok(ea),
+        _ => Err(Trap::MemoryOutOfBounds),
+    }
+}
+
 macro_rules! impl_load {
     ($fnname: ident, $t:ty, $sx:ty) => {
         pub fn $fnname(
@@ -19,17 +35,21 @@ macro_rules! impl_load {
             instance: &mut Instance,
             store: &mut Store,
             stack: &mut Stack,
+            hooks: &mut Hooks,
         ) -> Result<(), Trap> {
             let a = instance.memaddr.unwrap();
             let mem = &store.mems[a];
-            let i = stack.pop_value::<i32>() as usize;
-            let ea = i
-                .checked_add(memarg.offset as usize)
-                .ok_or(Trap::MemoryOutOfBounds)?;
+            let i = stack.pop_value::<i32>()? as u32 as usize;
             const SIZE: usize = core::mem::size_of::<$sx>();
-            if ea.checked_add(SIZE).ok_or(Trap::MemoryOutOfBounds)? > mem.data.len() {
-                return Err(Trap::MemoryOutOfBounds);
-            }
+            let ea = effective_addr(i, memarg.offset, SIZE, mem.data.len()).map_err(|trap| {
+                hooks.mem_violation(
+                    i.saturating_add(memarg.offset as usize),
+                    SIZE,
+                    mem.data.len(),
+                );
+                trap
+            })?;
+            hooks.check_align(memarg, SIZE, ea);
             let c: $sx = LittleEndian::read(&mem.data, ea);
             stack.push_value(c as $t);
             Ok(())
@@ -51,6 +71,7 @@ impl_load!(i64_load_16s, i64, i16);
 impl_load!(i64_load_16u, i64, u16);
 impl_load!(i64_load_32s, i64, i32);
 impl_load!(i64_load_32u, i64, u32);
+impl_load!(v128_load, [u8; 16], [u8; 16]);
 
 macro_rules! impl_store {
     ($fnname: ident, $t:ty, $sx:ty) => {
@@ -59,18 +80,22 @@ macro_rules! impl_store {
             instance: &mut Instance,
             store: &mut Store,
             stack: &mut Stack,
+            hooks: &mut Hooks,
         ) -> Result<(), Trap> {
             let a = instance.memaddr.unwrap();
             let mem = &mut store.mems[a];
-            let c = stack.pop_value::<$t>();
-            let i = stack.pop_value::<i32>() as usize;
-            let ea = i
-                .checked_add(memarg.offset as usize)
-                .ok_or(Trap::MemoryOutOfBounds)?;
+            let c = stack.pop_value::<$t>()?;
+            let i = stack.pop_value::<i32>()? as u32 as usize;
             const SIZE: usize = core::mem::size_of::<$sx>();
-            if ea.checked_add(SIZE).ok_or(Trap::MemoryOutOfBounds)? > mem.data.len() {
-                return Err(Trap::MemoryOutOfBounds);
-            }
+            let ea = effective_addr(i, memarg.offset, SIZE, mem.data.len()).map_err(|trap| {
+                hooks.mem_violation(
+                    i.saturating_add(memarg.offset as usize),
+                    SIZE,
+                    mem.data.len(),
+                );
+                trap
+            })?;
+            hooks.check_align(memarg, SIZE, ea);
             LittleEndian::write(&mut mem.data, ea, c as $sx);
             Ok(())
         }
@@ -86,6 +111,65 @@ impl_store!(i32_store_16, i32, u16);
 impl_store!(i64_store_8, i64, u8);
 impl_store!(i64_store_16, i64, u16);
 impl_store!(i64_store_32, i64, u32);
+impl_store!(v128_store, [u8; 16], [u8; 16]);
+
+/// Like `effective_addr`, but atomic accesses must also be aligned to
+/// their size.
+fn atomic_addr(i: usize, offset: u32, size: usize, mem_len: usize) -> Result<usize, Trap> {
+    let ea = effective_addr(i, offset, size, mem_len)?;
+    if ea % size != 0 {
+        return Err(Trap::UnalignedAtomic);
+    }
+    Ok(ea)
+}
+
+/// There are no other threads, so there are never waiters to wake.
+pub fn memory_atomic_notify(
+    memarg: &MemArg,
+    instance: &Instance,
+    store: &Store,
+    stack: &mut Stack,
+) -> Result<(), Trap> {
+    let mem = &store.mems[instance.memaddr.unwrap()];
+    stack.pop_value::<i32>()?;
+    let i = stack.pop_value::<i32>()? as u32 as usize;
+    atomic_addr(i, memarg.offset, 4, mem.data.len())?;
+    stack.push_value(0);
+    Ok(())
+}
+
+/// `memory.atomic.wait32` and `wait64`, which trap on a memory that is not
+/// shared. Without other threads nothing can change the value or notify,
+/// so a matching wait with a timeout times out at once, and one without a
+/// timeout traps instead of hanging.
+pub fn memory_atomic_wait<T>(
+    memarg: &MemArg,
+    instance: &Instance,
+    store: &Store,
+    stack: &mut Stack,
+) -> Result<(), Trap>
+where
+    T: LittleEndian + PartialEq + From<Value>,
+{
+    let mem = &store.mems[instance.memaddr.unwrap()];
+    if !mem.shared {
+        return Err(Trap::UnsharedAtomicWait);
+    }
+    let timeout = stack.pop_value::<i64>()?;
+    let expected = stack.pop_value::<T>()?;
+    let i = stack.pop_value::<i32>()? as u32 as usize;
+    let ea = atomic_addr(i, memarg.offset, core::mem::size_of::<T>(), mem.data.len())?;
+    if T::read(&mem.data, ea) != expected {
+        // "not-equal"
+        stack.push_value(1);
+    } else if timeout >= 0 {
+        // "timed-out"
+        stack.push_value(2);
+    } else {
+        return Err(Trap::AtomicWait);
+    }
+    Ok(())
+}
 
 pub fn memory_size(instance: &Instance, store: &Store, stack: &mut Stack) {
     let a = instance.memaddr.unwrap();
@@ -93,57 +177,57 @@ pub fn memory_size(instance: &Instance, store: &Store, stack: &mut Stack) {
     stack.push_value(mem.limits.min() as i32);
 }
 
-pub fn memory_grow(instance: &Instance, store: &mut Store, stack: &mut Stack) {
+pub fn memory_grow(instance: &Instance, store: &mut Store, stack: &mut Stack) -> Result<(), Trap> {
     let a = instance.memaddr.unwrap();
+    let n = stack.pop_value::<i32>()? as u32;
+    stack.push_value(grow(&mut store.mems[a], n));
+    Ok(())
+}
+
+/// Grows `mem` by `n` pages, returning the previous size in pages or -1 if
+/// the memory cannot grow that far.
+pub fn grow(mem: &mut MemInst, n: u32) -> i32 {
     const ERR: i32 = -1;
-    let mem = &mut store.mems[a];
     let sz = mem.limits.min();
-    let n = stack.pop_value::<i32>() as u32;
-    let len = sz + n;
-    if len > u16::MAX as u32 + 1 {
-        stack.push_value(ERR);
-        return;
-    }
+    let len = match sz.checked_add(n) {
+        Some(len) if len <= u16::MAX as u32 + 1 => len,
+        _ => return ERR,
+    };
     let limits_ = mem.limits.set_min(len);
     if !limits_.valid() {
-        stack.push_value(ERR);
-        return;
-    }
-    for _ in 0..(n * PAGE_SIZE as u32) {
-        mem.data.push(0);
+        return ERR;
     }
+    mem.data.resize(len as usize * PAGE_SIZE, 0);
     mem.limits = limits_;
-    stack.push_value(sz as i32);
+    // The page count is unsigned; a 32-bit memory never exceeds 2^16 pages,
+    // so it always fits in the i32 result.
+    sz as i32
 }
 
 pub fn memory_fill(instance: &Instance, store: &mut Store, stack: &mut Stack) -> Result<(), Trap> {
     let ma = instance.memaddr.unwrap();
     let mem = &mut store.mems[ma];
-    let n = stack.pop_value::<i32>() as usize;
-    let val = stack.pop_value::<i32>();
-    let d = stack.pop_value::<i32>() as usize;
-    if d + n > mem.data.len() {
-        return Err(Trap::MemoryOutOfBounds);
-    }
+    let n = stack.pop_value::<i32>()? as u32 as usize;
+    let val = stack.pop_value::<i32>()?;
+    let d = stack.pop_value::<i32>()? as u32 as usize;
+    // Even a zero-length fill traps past the end of memory.
+    effective_addr(d, 0, n, mem.data.len())?;
     if n == 0 {
         return Ok(());
     }
-    for i in 0..n {
-        LittleEndian::write(&mut mem.data, d + i, val);
-    }
+    mem.data[d..d + n].fill((val & 0xFF) as u8);
     Ok(())
 }
 
 pub fn memory_copy(instance: &Instance, store: &mut Store, stack: &mut Stack) -> Result<(), Trap> {
     let ma = instance.memaddr.unwrap();
     let mem = &mut store.mems[ma];
-    let n = stack.pop_value::<i32>() as usize;
-    let s = stack.pop_value::<i32>() as usize;
-    let d = stack.pop_value::<i32>() as usize;
+    let n = stack.pop_value::<i32>()? as u32 as usize;
+    let s = stack.pop_value::<i32>()? as u32 as usize;
+    let d = stack.pop_value::<i32>()? as u32 as usize;
 
-    if s + n > mem.data.len() || d + n > mem.data.len() {
-        return Err(Trap::MemoryOutOfBounds);
-    }
+    effective_addr(s, 0, n, mem.data.len())?;
+    effective_addr(d, 0, n, mem.data.len())?;
     if n == 0 {
         return Ok(());
     }
@@ -167,26 +251,26 @@ pub fn memory_init(
 ) -> Result<(), Trap> {
     let ma = instance.memaddr.unwrap();
     let mem = &mut store.mems[ma];
-    let da = instance.dataaddrs[*x as usize];
-    let data = &store.datas[da];
-    let n = stack.pop_value::<i32>() as usize;
-    let s = stack.pop_value::<i32>() as usize;
-    let d = stack.pop_value::<i32>() as usize;
-    if s + n > data.data.len() || d + n > mem.data.len() {
-        return Err(Trap::MemoryOutOfBounds);
-    }
+    let data: &[u8] = match instance.dataaddrs[*x as usize] {
+        Some(da) => &store.datas[da].data,
+        None => &[],
+    };
+    let n = stack.pop_value::<i32>()? as u32 as usize;
+    let s = stack.pop_value::<i32>()? as u32 as usize;
+    let d = stack.pop_value::<i32>()? as u32 as usize;
+    effective_addr(s, 0, n, data.len())?;
+    effective_addr(d, 0, n, mem.data.len())?;
     if n == 0 {
         return Ok(());
     }
-    for i in 0..n {
-        mem.data[d + i] = data.data[s + i];
-    }
+    mem.data[d..d + n].copy_from_slice(&data[s..s + n]);
     Ok(())
 }
 
 pub fn data_drop(x: &u32, instance: &mut Instance, store: &mut Store) {
-    let a = instance.dataaddrs[*x as usize];
-    store.datas.remove(a);
+    if let Some(a) = instance.dataaddrs[*x as usize].take() {
+        store.datas.remove(a);
+    }
 }
 
 pub fn data_passiv(datas: &mut OptVec<DataInst>, data: Data) -> Addr {
@@ -198,3 +282,24 @@ pub fn data_active(mem: &mut MemInst, data: Data, offset: usize) {
         mem.data[offset + i] = data.init[i];
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::effective_addr;
+    use crate::exec::trap::Trap;
+
+    #[test]
+    fn effective_addr_bounds() {
+        assert_eq!(effective_addr(4, 2, 4, 10), Ok(6));
+        assert_eq!(effective_addr(4, 3, 4, 10), Err(Trap::MemoryOutOfBounds));
+        assert_eq!(effective_addr(0, 0, 0, 0), Ok(0));
+        assert_eq!(
+            effective_addr(usize::MAX, 1, 1, 10),
+            Err(Trap::MemoryOutOfBounds)
+        );
+        assert_eq!(
+            effective_addr(usize::MAX - 2, 0, 4, usize::MAX),
+            Err(Trap::MemoryOutOfBounds)
+        );
+    }
+}
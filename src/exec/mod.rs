@@ -1,11 +1,16 @@
 pub mod cast;
+pub mod compiled;
 pub mod env;
+pub mod hook;
 pub mod importer;
 pub mod instr;
 pub mod memory;
 pub mod runtime;
+pub mod simd;
 pub mod stack;
 pub mod store;
 pub mod table;
 pub mod trap;
 pub mod value;
+#[cfg(feature = "wasi")]
+pub mod wasi;
@@ -1,21 +1,28 @@
 #[cfg(not(feature = "std"))]
 use crate::lib::*;
+use alloc::sync::Arc;
 
+use super::compiled::CompiledModule;
 use super::env::Env;
+use super::hook::{Hooks, LogHook, MemViolationHook};
 use super::importer::Importer;
 use super::instr::{attach, step};
+use super::memory;
 use super::stack::Stack;
-use super::store::{FuncInst, Store};
+use super::store::{FuncInst, OptVecExt, Store};
 use super::trap::Trap;
 use super::value::{Ref, Value};
 use crate::binary::{Block, Export, Import};
-use crate::binary::{ExportDesc, FuncType, ImportDesc, Instr, Module};
-use crate::binary::{Expr, ValType};
+use crate::binary::{ExportDesc, FuncIdx, FuncType, ImportDesc, Instr, Module};
+use crate::binary::{Expr, Mut, ValType};
 use core::fmt::Debug;
 
 pub type Addr = usize;
 pub const PAGE_SIZE: usize = 65536;
 
+// The spec fixes the page size at 64 KiB; memory code assumes it.
+const _: [(); 1] = [(); (PAGE_SIZE == 65536) as usize];
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum ExecState {
     Continue(usize),
@@ -31,12 +38,18 @@ pub struct Instance {
     // implicitly operate on memory index 0. This restriction may be
     // lifted in future versions.
     pub memaddr: Option<Addr>,
-    pub types: Vec<FuncType>,
-    pub dataaddrs: Vec<Addr>,
+    pub types: Arc<Vec<FuncType>>,
+    /// Bodies of the functions the instance defines, shared by every
+    /// instance of the same `CompiledModule`.
+    pub code: Arc<Vec<Instr>>,
+    // Indexed by data index. `None` marks a segment that is active or has
+    // been dropped, which behaves as an empty segment.
+    pub dataaddrs: Vec<Option<Addr>>,
     pub funcaddrs: Vec<Addr>,
     pub elemaddrs: Vec<Addr>,
-    pub start: Option<usize>,
+    pub start: Option<Addr>,
     pub exports: Vec<Export>,
+    pub func_names: Vec<(FuncIdx, String)>,
 }
 
 impl Instance {
@@ -47,26 +60,92 @@ impl Instance {
             Block::TypeIdx(idx) => self.types[*idx as usize].1 .0.len(),
         }
     }
+
+    /// Number of values a block of type `bt` takes from the stack.
+    pub fn block_params(&self, bt: &Block) -> usize {
+        match bt {
+            Block::Empty | Block::ValType(_) => 0,
+            Block::TypeIdx(idx) => self.types[*idx as usize].0 .0.len(),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Runtime {
-    pub instrs: Vec<Instr>,
     pub instances: Vec<Instance>,
     pub root: usize,
     pub stack: Stack,
     pub pc: usize,
     pub env_name: &'static str,
+    pub hooks: Hooks,
+    /// Instances created to satisfy imports, by module name.
+    imported: Vec<(String, usize)>,
+    /// Steps each call to `exec` may take, unlimited if `None`.
+    fuel: Option<u64>,
 }
 
+/// Errors from instantiating or running a module. Like `Trap`, this may
+/// gain variants, so matches outside this crate need a wildcard arm:
+///
+/// ```
+/// use wasper::exec::runtime::RuntimeError;
+///
+/// fn is_trap(err: &RuntimeError) -> bool {
+///     match err {
+///         RuntimeError::Trap { .. } => true,
+///         _ => false,
+///     }
+/// }
+/// assert!(!is_trap(&RuntimeError::InvalidParams));
+/// ```
 #[derive(Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum RuntimeError {
     ModuleNotFound(String),
     NotFound(ImportType),
     Env(&'static str),
     ConstantExpression,
     NoStartFunction,
-    Trap(Trap),
+    InvalidParams,
+    SharedMemoryWithoutMax,
+    InvalidStartSignature,
+    /// The export an import resolved to has a different type than the
+    /// import declares.
+    ImportTypeMismatch(ImportType),
+    /// `Runtime::set_global` on a global declared immutable.
+    ImmutableGlobal(String),
+    /// The export is a host function, which has no instructions to step.
+    HostExport(String),
+    /// The invocation used up the budget given to `Runtime::set_fuel`.
+    OutOfFuel,
+    /// `pc` is the failing instruction in its instance's code. `func` is
+    /// the index of the function containing it within its instance, and the
+    /// offset from its start.
+    Trap {
+        trap: Trap,
+        pc: usize,
+        func: Option<(FuncIdx, usize)>,
+    },
+}
+
+/// A trap raised outside any instruction, so without a position. Traps
+/// from executing code are annotated with where they happened instead.
+impl From<Trap> for RuntimeError {
+    fn from(trap: Trap) -> Self {
+        RuntimeError::Trap {
+            trap,
+            pc: 0,
+            func: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ExportKind {
+    Func(FuncType),
+    Table,
+    Memory,
+    Global(ValType),
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -74,41 +153,141 @@ pub enum ImportType {
     Func(String),
     Table(String),
     Global(String),
-    Mem,
+    Mem(String),
 }
 
-pub fn eval_const(expr: &Expr) -> Result<Value, RuntimeError> {
+/// Evaluates a constant expression. `ref.func` is resolved through
+/// `funcaddrs`, the function addresses of the instance being built.
+pub fn eval_const(expr: &Expr, funcaddrs: &[Addr]) -> Result<Value, RuntimeError> {
+    if !expr.is_valid_const() {
+        return Err(RuntimeError::ConstantExpression);
+    }
     Ok(match expr.0[0] {
         Instr::I32Const(value) => Value::I32(value),
         Instr::I64Const(value) => Value::I64(value),
         Instr::F32Const(value) => Value::F32(value),
         Instr::F64Const(value) => Value::F64(value),
-        Instr::RefNull(_) => Value::Ref(Ref::Null),
-        Instr::RefFunc(idx) => Value::I32(idx as i32),
+        Instr::V128Const(value) => Value::V128(value),
+        Instr::RefNull(t) => Value::Ref(Ref::Null(t)),
+        Instr::RefFunc(idx) => Value::Ref(Ref::Func(funcaddrs[idx as usize])),
         _ => return Err(RuntimeError::ConstantExpression),
     })
 }
 
-impl Runtime {
-    pub fn allocate_func(
-        &mut self,
-        functype: FuncType,
-        locals: Vec<ValType>,
-        instrs: Vec<Instr>,
-        instance_addr: Addr,
-        store: &mut Store,
-    ) -> Addr {
-        let start = self.instrs.len();
-        self.instrs.extend(instrs);
-        self.instrs.extend(vec![Instr::Return]);
-        store.funcs.push(FuncInst::InnerFunc {
-            instance_addr,
-            start,
-            functype,
-            locals,
-        })
+/// Checks that the control flow flattened by the loader is consistent:
+/// every `Block`/`Loop`/`If` is closed by its own `PopLabel`, the offsets
+/// stored in `Block`/`If` point at it, and every `RJump` lands inside
+/// `instrs`. Describes the first violation found.
+pub(crate) fn check_flattened(instrs: &[Instr]) -> Result<(), String> {
+    // An open control instruction and the `PopLabel` that should close it.
+    enum Open {
+        Block {
+            at: usize,
+            end: usize,
+        },
+        Loop {
+            at: usize,
+        },
+        Then {
+            at: usize,
+            else_at: usize,
+            end: usize,
+        },
+        Else {
+            at: usize,
+            end: usize,
+        },
+    }
+
+    let mut open: Vec<Open> = vec![];
+    let mut pc = 0;
+    while pc < instrs.len() {
+        match &instrs[pc] {
+            Instr::PopLabel => match open.pop() {
+                None => return Err(format!("PopLabel at {} does not close any block", pc)),
+                Some(Open::Loop { .. }) => {}
+                Some(Open::Block { at, end }) if pc + 1 != end => {
+                    let kind = match instrs[at] {
+                        Instr::If { .. } => "if",
+                        _ => "block",
+                    };
+                    return Err(format!(
+                        "{} at {}: end_offset does not point past its PopLabel",
+                        kind, at
+                    ));
+                }
+                Some(Open::Block { .. }) => {}
+                Some(Open::Then { at, else_at, end }) => {
+                    if pc + 2 != else_at {
+                        return Err(format!(
+                            "if at {}: else_offset does not point past its RJump",
+                            at
+                        ));
+                    }
+                    if instrs.get(pc + 1) != end.checked_sub(pc + 1).map(Instr::RJump).as_ref() {
+                        return Err(format!(
+                            "if at {}: then branch does not jump to the end",
+                            at
+                        ));
+                    }
+                    open.push(Open::Else { at, end });
+                    pc = else_at;
+                    continue;
+                }
+                Some(Open::Else { at, end }) if pc + 1 != end => {
+                    return Err(format!(
+                        "if at {}: end_offset does not point past its PopLabel",
+                        at
+                    ));
+                }
+                Some(Open::Else { .. }) => {}
+            },
+            Instr::Block { end_offset, .. } => open.push(Open::Block {
+                at: pc,
+                end: pc + end_offset,
+            }),
+            Instr::Loop { .. } => open.push(Open::Loop { at: pc }),
+            Instr::If {
+                else_offset: Some(else_offset),
+                end_offset,
+                ..
+            } => open.push(Open::Then {
+                at: pc,
+                else_at: pc + else_offset,
+                end: pc + end_offset,
+            }),
+            // Without an else branch an `if` closes like a block.
+            Instr::If {
+                else_offset: None,
+                end_offset,
+                ..
+            } => open.push(Open::Block {
+                at: pc,
+                end: pc + end_offset,
+            }),
+            Instr::RJump(r) if pc + r >= instrs.len() => {
+                return Err(format!(
+                    "rjump at {}: target {} is outside the function",
+                    pc,
+                    pc + r
+                ));
+            }
+            _ => {}
+        }
+        pc += 1;
+    }
+    match open.last() {
+        Some(
+            Open::Block { at, .. }
+            | Open::Loop { at }
+            | Open::Then { at, .. }
+            | Open::Else { at, .. },
+        ) => Err(format!("control instruction at {} has no PopLabel", at)),
+        None => Ok(()),
     }
+}
 
+impl Runtime {
     pub fn instances(self) -> Vec<Instance> {
         self.instances
     }
@@ -116,31 +295,63 @@ impl Runtime {
     pub fn new(env_name: &'static str) -> Self {
         Runtime {
             root: 0,
-            instrs: vec![],
             instances: vec![],
             stack: Stack::new(),
             pc: 0,
             env_name,
+            hooks: Hooks::default(),
+            imported: vec![],
+            fuel: None,
         }
     }
 
     pub fn add_module(&mut self, store: &mut Store, module: Module) -> Result<(), RuntimeError> {
-        struct EmptyImporter {}
-        impl Importer for EmptyImporter {
-            fn import(&mut self, _: &str) -> Option<Module> {
-                panic!()
-            }
-        }
-
-        let mut importer = EmptyImporter {};
-        let instance = self.new_instance(store, module, &mut importer)?;
+        self.instantiate(store, &module.into())
+    }
 
+    /// Like `import_module`, but a function import that cannot be resolved
+    /// becomes a stub trapping with `Trap::UnresolvedImport` when called.
+    /// Other kinds of imports must still resolve.
+    pub fn instantiate_partial<I: Importer>(
+        &mut self,
+        store: &mut Store,
+        importer: &mut I,
+        module: Module,
+    ) -> Result<(), RuntimeError> {
+        let instance = self.new_instance(store, &module.into(), importer, true)?;
         self.instances.push(instance);
 
         self.root = self.instances.len() - 1;
         Ok(())
     }
 
+    pub fn set_mem_violation_hook(&mut self, hook: MemViolationHook) {
+        self.hooks.mem_violation = Some(hook);
+    }
+
+    pub fn set_log_hook(&mut self, hook: LogHook) {
+        self.hooks.log = Some(hook);
+    }
+
+    /// Report loads and stores whose alignment hint differs from the
+    /// natural alignment through the log hook.
+    pub fn set_warn_misaligned(&mut self, warn: bool) {
+        self.hooks.warn_misaligned = warn;
+    }
+
+    /// Trap as soon as a float arithmetic instruction produces a NaN, to
+    /// find where one first appears. Off by default.
+    pub fn set_trap_on_nan(&mut self, trap: bool) {
+        self.hooks.trap_on_nan = trap;
+    }
+
+    /// Limits every `invoke`, and the start function, to `fuel`
+    /// instructions, after which it fails with `RuntimeError::OutOfFuel`.
+    /// Each instruction costs one. `None`, the default, is unlimited.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
     pub fn set_pc(&mut self, pc: usize) {
         self.pc = pc;
     }
@@ -158,7 +369,7 @@ impl Runtime {
         let module = importer
             .import(modname)
             .ok_or(RuntimeError::ModuleNotFound(modname.into()))?;
-        let instance = self.new_instance(store, module, importer)?;
+        let instance = self.new_instance(store, &module.into(), importer, false)?;
 
         self.instances.push(instance);
 
@@ -166,24 +377,28 @@ impl Runtime {
         Ok(())
     }
 
-    fn new_instance<I: Importer>(
+    /// With `partial`, function imports of this module that do not resolve
+    /// become stubs; the modules it imports from must resolve completely.
+    pub(crate) fn new_instance<I: Importer>(
         &mut self,
         store: &mut Store,
-        module: Module,
+        compiled: &CompiledModule,
         importer: &mut I,
+        partial: bool,
     ) -> Result<Instance, RuntimeError> {
+        let module = compiled.module();
         let mut funcaddrs = vec![];
         let mut globaladdrs = vec![];
         let mut tableaddrs = vec![];
         let mut memaddr = None;
 
-        for import in module.imports {
+        for import in &module.imports {
             if import.module == self.env_name {
                 match import.desc {
                     ImportDesc::Func(ty) => funcaddrs.push(self.import_env_func(
                         store,
                         module.types[ty as usize].clone(),
-                        import.name,
+                        import.name.clone(),
                     )),
                     ImportDesc::Table(_) => {}
                     ImportDesc::Mem(_) => {}
@@ -191,104 +406,152 @@ impl Runtime {
                 }
             } else {
                 match import.desc {
-                    ImportDesc::Func(_) => {
-                        funcaddrs.push(self.import_func(store, &import, importer)?)
+                    ImportDesc::Func(ty) => {
+                        match self.import_func(store, import, &module.types[ty as usize], importer)
+                        {
+                            Ok(addr) => funcaddrs.push(addr),
+                            Err(RuntimeError::ModuleNotFound(_) | RuntimeError::NotFound(_))
+                                if partial =>
+                            {
+                                funcaddrs.push(store.funcs.push(FuncInst::HostFunc {
+                                    functype: module.types[ty as usize].clone(),
+                                    name: format!("{}.{}", import.module, import.name),
+                                    stub: true,
+                                }))
+                            }
+                            Err(err) => return Err(err),
+                        }
                     }
                     ImportDesc::Mem(_) => {
-                        memaddr = Some(self.import_memory(store, &import, importer)?)
+                        memaddr = Some(self.import_memory(store, import, importer)?)
                     }
                     ImportDesc::Table(_) => {
-                        tableaddrs.push(self.import_table(store, &import, importer)?)
+                        tableaddrs.push(self.import_table(store, import, importer)?)
                     }
                     ImportDesc::Global(_) => {
-                        globaladdrs.push(self.import_global(store, &import, importer)?)
+                        globaladdrs.push(self.import_global(store, import, importer)?)
                     }
                 }
             }
         }
 
-        for global in module.globals {
-            globaladdrs.push(store.allocate_global(global)?);
+        let mut inner_funcaddr = vec![];
+        for func in compiled.funcs.iter() {
+            let addr = store.funcs.push(FuncInst::InnerFunc {
+                instance_addr: self.instances.len(),
+                start: func.start,
+                functype: compiled.types[func.typeidx as usize].clone(),
+                locals: func.locals.clone(),
+            });
+            inner_funcaddr.push(addr);
+            funcaddrs.push(addr);
+        }
+        let instance_addr = self.instances.len();
+        store.update_func_inst(&inner_funcaddr, instance_addr);
+
+        for global in &module.globals {
+            globaladdrs.push(store.allocate_global(global.clone(), &funcaddrs)?);
         }
 
-        for table in module.tables {
-            tableaddrs.push(store.allocate_table(table));
+        for table in &module.tables {
+            tableaddrs.push(store.allocate_table(table.clone(), &funcaddrs)?);
         }
 
         let mut elemaddrs = vec![];
-        for elem in module.elems {
-            if let Some(addr) = store.allocate_elem(elem)? {
+        for elem in &module.elems {
+            if let Some(addr) = store.allocate_elem(elem.clone(), &funcaddrs)? {
                 elemaddrs.push(addr);
             }
         }
 
-        let mut inner_funcaddr = vec![];
-        for func in module.funcs {
-            let functype = module.types[func.typeidx as usize].clone();
-            let addr = self.allocate_func(
-                functype,
-                func.locals,
-                func.body.0,
-                self.instances.len(),
-                store,
-            );
-            inner_funcaddr.push(addr);
-            funcaddrs.push(addr);
-        }
-        let instance_addr = self.instances.len();
-        store.update_func_inst(&inner_funcaddr, instance_addr);
-
-        if module.mems.len() > 0 {
-            memaddr = Some(store.allocate_mem(&module.mems[0]))
+        if let Some(mem) = module.mems.first() {
+            if mem.shared && mem.limits.max().is_none() {
+                return Err(RuntimeError::SharedMemoryWithoutMax);
+            }
+            memaddr = Some(store.allocate_mem(mem))
         }
 
         let mut dataaddrs = vec![];
-        for data in module.datas {
+        for data in &module.datas {
             let memidx = memaddr.unwrap();
-            if let Some(addr) = store.allocate_data(memidx, data)? {
-                dataaddrs.push(addr);
+            dataaddrs.push(store.allocate_data(memidx, data.clone(), &funcaddrs)?);
+        }
+
+        let start = module.start.map(|idx| funcaddrs[idx as usize]);
+        if let Some(addr) = start {
+            let functype = store.funcs[addr].functype();
+            if !functype.0 .0.is_empty() || !functype.1 .0.is_empty() {
+                return Err(RuntimeError::InvalidStartSignature);
             }
         }
 
         Ok(Instance {
             funcaddrs,
-            types: module.types,
+            types: Arc::clone(&compiled.types),
+            code: Arc::clone(&compiled.code),
             globaladdrs,
             tableaddrs,
             elemaddrs,
             memaddr,
             dataaddrs,
-            start: module.start.map(|idx| idx as usize),
-            exports: module.exports,
+            start,
+            exports: module.exports.clone(),
+            func_names: module.func_names.clone(),
         })
     }
 
     pub fn import_env_func(&mut self, store: &mut Store, functype: FuncType, name: String) -> Addr {
-        store.funcs.push(FuncInst::HostFunc { functype, name })
+        store.funcs.push(FuncInst::HostFunc {
+            functype,
+            name,
+            stub: false,
+        })
+    }
+
+    /// Index in `instances` of the module `modname`, instantiated on first
+    /// use so that every import from it resolves to the same instance.
+    fn import_instance<I: Importer>(
+        &mut self,
+        store: &mut Store,
+        modname: &str,
+        importer: &mut I,
+    ) -> Result<usize, RuntimeError> {
+        if let Some(&(_, idx)) = self.imported.iter().find(|(name, _)| name == modname) {
+            return Ok(idx);
+        }
+        let module = importer
+            .import(modname)
+            .ok_or_else(|| RuntimeError::ModuleNotFound(modname.into()))?;
+        let instance = self.new_instance(store, &module.into(), importer, false)?;
+        self.instances.push(instance);
+        let idx = self.instances.len() - 1;
+        self.imported.push((modname.into(), idx));
+        Ok(idx)
     }
 
     pub fn import_func<I: Importer>(
         &mut self,
         store: &mut Store,
         import: &Import,
+        functype: &FuncType,
         importer: &mut I,
     ) -> Result<usize, RuntimeError> {
-        let module = importer
-            .import(&import.module)
-            .ok_or_else(|| RuntimeError::ModuleNotFound(import.module.clone()))?;
-        let instance = self.new_instance(store, module, importer)?;
-        if let Some(desc) = instance
+        let idx = self.import_instance(store, &import.module, importer)?;
+        let instance = &self.instances[idx];
+        if let Some(ExportDesc::Func(index)) = instance
             .exports
             .iter()
             .filter(|export| export.name == import.name)
             .map(|export| &export.desc)
             .next()
         {
-            if let ExportDesc::Func(index) = desc {
-                let ret = Ok(instance.funcaddrs[*index as usize]);
-                self.instances.push(instance);
-                return ret;
+            let addr = instance.funcaddrs[*index as usize];
+            if store.funcs[addr].functype() != functype {
+                return Err(RuntimeError::ImportTypeMismatch(ImportType::Func(
+                    import.name.clone(),
+                )));
             }
+            return Ok(addr);
         }
         Err(RuntimeError::NotFound(ImportType::Func(
             import.name.clone(),
@@ -301,25 +564,20 @@ impl Runtime {
         import: &Import,
         importer: &mut I,
     ) -> Result<Addr, RuntimeError> {
-        let module = importer
-            .import(&import.module)
-            .ok_or_else(|| RuntimeError::ModuleNotFound(import.module.clone()))?;
-        let instance = self.new_instance(store, module, importer)?;
-        if let Some(desc) = instance
+        let idx = self.import_instance(store, &import.module, importer)?;
+        let instance = &self.instances[idx];
+        if let Some(ExportDesc::Mem(_)) = instance
             .exports
             .iter()
             .filter(|e| e.name == import.name)
             .map(|export| &export.desc)
             .next()
         {
-            if let ExportDesc::Mem(_) = desc {
-                if let Some(addr) = instance.memaddr {
-                    self.instances.push(instance);
-                    return Ok(addr);
-                }
+            if let Some(addr) = instance.memaddr {
+                return Ok(addr);
             }
         }
-        Err(RuntimeError::NotFound(ImportType::Mem))
+        Err(RuntimeError::NotFound(ImportType::Mem(import.name.clone())))
     }
 
     pub fn import_table<I: Importer>(
@@ -328,22 +586,16 @@ impl Runtime {
         import: &Import,
         importer: &mut I,
     ) -> Result<Addr, RuntimeError> {
-        let module = importer
-            .import(&import.module)
-            .ok_or_else(|| RuntimeError::ModuleNotFound(import.module.clone()))?;
-        let instance = self.new_instance(store, module, importer)?;
-        if let Some(desc) = instance
+        let idx = self.import_instance(store, &import.module, importer)?;
+        let instance = &self.instances[idx];
+        if let Some(ExportDesc::Table(addr)) = instance
             .exports
             .iter()
             .filter(|export| export.name == import.name)
             .map(|export| &export.desc)
             .next()
         {
-            if let ExportDesc::Table(addr) = desc {
-                let ret = Ok(instance.tableaddrs[*addr as usize]);
-                self.instances.push(instance);
-                return ret;
-            }
+            return Ok(instance.tableaddrs[*addr as usize]);
         }
         Err(RuntimeError::NotFound(ImportType::Table(
             import.name.clone(),
@@ -356,22 +608,16 @@ impl Runtime {
         import: &Import,
         importer: &mut I,
     ) -> Result<Addr, RuntimeError> {
-        let module = importer
-            .import(&import.module)
-            .ok_or_else(|| RuntimeError::ModuleNotFound(import.module.clone()))?;
-        let instance = self.new_instance(store, module, importer)?;
-        if let Some(desc) = instance
+        let idx = self.import_instance(store, &import.module, importer)?;
+        let instance = &self.instances[idx];
+        if let Some(ExportDesc::Global(addr)) = instance
             .exports
             .iter()
             .filter(|export| export.name == import.name)
             .map(|export| &export.desc)
             .next()
         {
-            if let ExportDesc::Global(addr) = desc {
-                let ret = Ok(instance.globaladdrs[*addr as usize]);
-                self.instances.push(instance);
-                return ret;
-            }
+            return Ok(instance.globaladdrs[*addr as usize]);
         }
         Err(RuntimeError::NotFound(ImportType::Global(
             import.name.clone(),
@@ -382,20 +628,52 @@ impl Runtime {
         match self.attach_start(store)? {
             ExecState::Continue(pc) => {
                 self.pc = pc;
-                self.exec(store, env)
-                    .map_err(|trap| RuntimeError::Trap(trap))?;
+                self.exec(store, env, 0)?;
             }
             ExecState::EnvFunc { name, params } => {
                 let instance = &self.instances[self.root];
                 let memory = instance.memaddr.map(|a| &mut store.mems[a]);
-                env.call(&name, params, memory)
-                    .map_err(|err| RuntimeError::Env(err))?;
+                env.call(&name, params, memory).map_err(RuntimeError::Env)?;
             }
             _ => {}
         }
         Ok(())
     }
 
+    /// Like `start`, but a module without a start function is not an error.
+    pub fn call_start_if_present<E: Env>(
+        &mut self,
+        store: &mut Store,
+        env: &mut E,
+    ) -> Result<(), RuntimeError> {
+        match self.start(store, env) {
+            Err(RuntimeError::NoStartFunction) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Runs the declared start function, if any, then the exported
+    /// `_initialize`, if any, as a WASI reactor expects.
+    pub fn run_initializer<E: Env>(
+        &mut self,
+        store: &mut Store,
+        env: &mut E,
+    ) -> Result<(), RuntimeError> {
+        self.call_start_if_present(store, env)?;
+        let instance = &self.instances[self.root];
+        if instance.exports.iter().any(|export| {
+            export.name == "_initialize" && matches!(export.desc, ExportDesc::Func(_))
+        }) {
+            self.invoke(store, env, "_initialize", vec![])?;
+        }
+        Ok(())
+    }
+
+    /// Calls the exported function `name` on a fresh stack.
+    ///
+    /// A host function cannot re-enter the same runtime: `invoke` holds
+    /// `&mut self` for the whole call, so a host sharing the runtime through
+    /// a `RefCell` finds it already borrowed rather than clobbering the stack.
     pub fn invoke<E: Env>(
         &mut self,
         store: &mut Store,
@@ -403,19 +681,250 @@ impl Runtime {
         name: &str,
         params: Vec<Value>,
     ) -> Result<Vec<Value>, RuntimeError> {
+        let arity = self
+            .exported_func(name)
+            .map_or(0, |addr| store.funcs[addr].functype().1 .0.len());
         match self.attach_invoke(store, name, params)? {
             ExecState::Continue(pc) => {
                 self.pc = pc;
-                self.exec(store, env)
-                    .map_err(|trap| RuntimeError::Trap(trap))
+                self.exec(store, env, arity)
             }
             ExecState::Return => unreachable!(),
             ExecState::EnvFunc { name, params } => {
                 let instance = &self.instances[self.root];
                 let memory = instance.memaddr.map(|a| &mut store.mems[a]);
-                env.call(&name, params, memory)
-                    .map_err(|err| RuntimeError::Env(err))
+                env.call(&name, params, memory).map_err(RuntimeError::Env)
+            }
+        }
+    }
+
+    /// Name and kind of every export of the root instance.
+    pub fn exports<'a>(
+        &'a self,
+        store: &'a Store,
+    ) -> impl Iterator<Item = (&'a str, ExportKind)> + 'a {
+        let instance = &self.instances[self.root];
+        instance.exports.iter().map(move |export| {
+            let kind = match export.desc {
+                ExportDesc::Func(idx) => ExportKind::Func(
+                    store.funcs[instance.funcaddrs[idx as usize]]
+                        .functype()
+                        .clone(),
+                ),
+                ExportDesc::Table(_) => ExportKind::Table,
+                ExportDesc::Mem(_) => ExportKind::Memory,
+                ExportDesc::Global(idx) => ExportKind::Global(
+                    store.globals[instance.globaladdrs[idx as usize]]
+                        .globaltype
+                        .valtype,
+                ),
+            };
+            (export.name.as_str(), kind)
+        })
+    }
+
+    /// Name of function `idx` of the root instance from its `name` section.
+    pub fn func_name(&self, idx: FuncIdx) -> Option<&str> {
+        let names = &self.instances[self.root].func_names;
+        let i = names.binary_search_by_key(&idx, |&(idx, _)| idx).ok()?;
+        Some(&names[i].1)
+    }
+
+    /// Reference to the exported function `name` of the root instance.
+    pub fn func_ref(&self, name: &str) -> Option<Ref> {
+        self.exported_func(name).map(Ref::Func)
+    }
+
+    /// Current size in pages of the exported memory `name`.
+    pub fn memory_pages(&self, store: &Store, name: &str) -> Result<u32, RuntimeError> {
+        let addr = self
+            .exported_mem(name)
+            .ok_or_else(|| RuntimeError::NotFound(ImportType::Mem(name.into())))?;
+        Ok(store.mems[addr].limits.min())
+    }
+
+    /// Grows the exported memory `name` by `pages` as `memory.grow` would,
+    /// returning the previous size in pages or -1.
+    pub fn grow_memory(
+        &self,
+        store: &mut Store,
+        name: &str,
+        pages: u32,
+    ) -> Result<i32, RuntimeError> {
+        let addr = self
+            .exported_mem(name)
+            .ok_or_else(|| RuntimeError::NotFound(ImportType::Mem(name.into())))?;
+        Ok(memory::grow(&mut store.mems[addr], pages))
+    }
+
+    /// Current value of the exported global `name`.
+    pub fn get_global(&self, store: &Store, name: &str) -> Result<Value, RuntimeError> {
+        let addr = self
+            .exported_global(name)
+            .ok_or_else(|| RuntimeError::NotFound(ImportType::Global(name.into())))?;
+        Ok(store.globals[addr].value)
+    }
+
+    /// Replaces the value of the exported global `name`, which must be
+    /// mutable and of the same type as `value`.
+    pub fn set_global(
+        &self,
+        store: &mut Store,
+        name: &str,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        let addr = self
+            .exported_global(name)
+            .ok_or_else(|| RuntimeError::NotFound(ImportType::Global(name.into())))?;
+        let global = &mut store.globals[addr];
+        if global.globaltype.mut_ == Mut::Const {
+            return Err(RuntimeError::ImmutableGlobal(name.into()));
+        }
+        if !value.is_type(&global.globaltype.valtype) {
+            return Err(RuntimeError::InvalidParams);
+        }
+        global.value = value;
+        Ok(())
+    }
+
+    /// Bytes of linear memory held by the root instance.
+    pub fn total_memory_bytes(&self, store: &Store) -> usize {
+        let instance = &self.instances[self.root];
+        instance
+            .memaddr
+            .map_or(0, |addr| store.mems[addr].data.len())
+    }
+
+    /// Entries across all tables of the root instance.
+    pub fn table_entry_count(&self, store: &Store) -> usize {
+        let instance = &self.instances[self.root];
+        instance
+            .tableaddrs
+            .iter()
+            .map(|&addr| store.tables[addr].elem.len())
+            .sum()
+    }
+
+    /// Functions of the root instance transitively referenced from the
+    /// exported function `name`, in index order. `call_indirect` counts as a
+    /// call to every function held by a table or element segment.
+    pub fn reachable_funcs(&self, store: &Store, name: &str) -> Vec<FuncIdx> {
+        let instance = &self.instances[self.root];
+        let entry = match instance.exports.iter().find(|export| export.name == name) {
+            Some(Export {
+                desc: ExportDesc::Func(idx),
+                ..
+            }) => *idx,
+            _ => return vec![],
+        };
+        let index_of = |addr: Addr| {
+            instance
+                .funcaddrs
+                .iter()
+                .position(|&a| a == addr)
+                .map(|idx| idx as FuncIdx)
+        };
+        let indirect = instance
+            .tableaddrs
+            .iter()
+            .flat_map(|&addr| store.tables[addr].elem.iter())
+            .chain(
+                instance
+                    .elemaddrs
+                    .iter()
+                    .flat_map(|&addr| store.elems[addr].elem.iter()),
+            )
+            .filter_map(|r| match r {
+                Ref::Func(addr) => index_of(*addr),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        // Bodies are laid out back to back, so each one ends where the next
+        // function in the instance's code starts.
+        let code = &self.instances[self.root].code;
+        let mut starts = store
+            .funcs
+            .iter()
+            .filter_map(|(_, func)| match func {
+                FuncInst::InnerFunc {
+                    instance_addr,
+                    start,
+                    ..
+                } if *instance_addr == self.root => Some(*start),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        starts.sort_unstable();
+
+        let mut reached = vec![false; instance.funcaddrs.len()];
+        let mut pending = vec![entry];
+        while let Some(idx) = pending.pop() {
+            if reached[idx as usize] {
+                continue;
             }
+            reached[idx as usize] = true;
+            let start = match store.funcs[instance.funcaddrs[idx as usize]] {
+                FuncInst::InnerFunc {
+                    instance_addr,
+                    start,
+                    ..
+                } if instance_addr == self.root => start,
+                // Imported functions are reachable but not ours to walk.
+                _ => continue,
+            };
+            let end = starts
+                .iter()
+                .find(|&&s| s > start)
+                .map_or(code.len(), |&s| s);
+            for instr in &code[start..end] {
+                match instr {
+                    Instr::Call(callee) | Instr::RefFunc(callee) => pending.push(*callee),
+                    Instr::CallIndirect(..) => pending.extend(&indirect),
+                    _ => {}
+                }
+            }
+        }
+        (0..reached.len() as FuncIdx)
+            .filter(|&idx| reached[idx as usize])
+            .collect()
+    }
+
+    fn exported_mem(&self, name: &str) -> Option<Addr> {
+        let instance = &self.instances[self.root];
+        match instance
+            .exports
+            .iter()
+            .find(|export| export.name == name)?
+            .desc
+        {
+            ExportDesc::Mem(_) => instance.memaddr,
+            _ => None,
+        }
+    }
+
+    fn exported_global(&self, name: &str) -> Option<Addr> {
+        let instance = &self.instances[self.root];
+        match instance
+            .exports
+            .iter()
+            .find(|export| export.name == name)?
+            .desc
+        {
+            ExportDesc::Global(index) => Some(instance.globaladdrs[index as usize]),
+            _ => None,
+        }
+    }
+
+    fn exported_func(&self, name: &str) -> Option<Addr> {
+        let instance = &self.instances[self.root];
+        match instance
+            .exports
+            .iter()
+            .find(|export| export.name == name)?
+            .desc
+        {
+            ExportDesc::Func(index) => Some(instance.funcaddrs[index as usize]),
+            _ => None,
         }
     }
 
@@ -424,17 +933,15 @@ impl Runtime {
         stack: &mut Stack,
         pc: &mut usize,
     ) -> Result<ExecState, RuntimeError> {
-        match attach(func, stack, *pc).map_err(|trap| RuntimeError::Trap(trap)) {
-            Ok(state) => {
-                if let ExecState::Continue(start) = state {
-                    *pc = start;
-                    Ok(state)
-                } else {
-                    Ok(state)
-                }
-            }
-            Err(err) => Err(err),
+        let state = attach(func, stack, *pc).map_err(|trap| RuntimeError::Trap {
+            trap,
+            pc: *pc,
+            func: None,
+        })?;
+        if let ExecState::Continue(start) = state {
+            *pc = start;
         }
+        Ok(state)
     }
 
     pub fn attach_start(&mut self, store: &mut Store) -> Result<ExecState, RuntimeError> {
@@ -454,38 +961,71 @@ impl Runtime {
         name: &str,
         params: Vec<Value>,
     ) -> Result<ExecState, RuntimeError> {
-        let instance = &self.instances[self.root];
         self.stack = Stack::new();
-        if let Some(export) = instance
-            .exports
-            .iter()
-            .filter(|export| &export.name == name)
-            .next()
-        {
-            match export.desc {
-                ExportDesc::Func(index) => {
-                    let func = &store.funcs[instance.funcaddrs[index as usize]];
-                    self.stack.extend_values(params);
-                    Self::attach(func, &mut self.stack, &mut self.pc)
-                }
-                _ => Err(RuntimeError::NotFound(ImportType::Func(name.into()))),
+        let addr = self
+            .exported_func(name)
+            .ok_or_else(|| RuntimeError::NotFound(ImportType::Func(name.into())))?;
+        let func = &store.funcs[addr];
+        let types = &func.functype().0 .0;
+        if params.len() != types.len() || !params.iter().zip(types).all(|(p, t)| p.is_type(t)) {
+            return Err(RuntimeError::InvalidParams);
+        }
+        self.stack.extend_values(params);
+        Self::attach(func, &mut self.stack, &mut self.pc)
+    }
+
+    /// Sets up a call to the exported function `name` without running it.
+    /// The caller then drives it with `step` until `ExecState::Return`,
+    /// after which the results are on `stack`.
+    pub fn begin_invoke(
+        &mut self,
+        store: &mut Store,
+        name: &str,
+        params: Vec<Value>,
+    ) -> Result<(), RuntimeError> {
+        match self.attach_invoke(store, name, params)? {
+            ExecState::Continue(_) => Ok(()),
+            ExecState::Return => unreachable!(),
+            ExecState::EnvFunc { .. } => {
+                self.stack = Stack::new();
+                Err(RuntimeError::HostExport(name.into()))
             }
-        } else {
-            Err(RuntimeError::NotFound(ImportType::Func(name.into())))
         }
     }
 
-    fn exec<E: Env>(&mut self, store: &mut Store, env: &mut E) -> Result<Vec<Value>, Trap> {
+    /// Runs until the root frame returns and yields its `arity` results.
+    fn exec<E: Env>(
+        &mut self,
+        store: &mut Store,
+        env: &mut E,
+        arity: usize,
+    ) -> Result<Vec<Value>, RuntimeError> {
+        let mut fuel = self.fuel;
+        // Calls and returns can cross into another instance, whose code the
+        // pc then indexes.
+        let mut code_addr = self.stack.frames().last().map(|f| f.instance_addr);
+        let mut code = self.current_code();
         loop {
-            match step(
+            if let Some(fuel) = &mut fuel {
+                *fuel = fuel.checked_sub(1).ok_or(RuntimeError::OutOfFuel)?;
+            }
+            let state = step(
                 &mut self.instances,
-                &self.instrs,
+                &code,
                 self.pc,
                 store,
                 &mut self.stack,
-            )? {
+                &mut self.hooks,
+            )
+            .map_err(|trap| self.trap_at(store, trap))?;
+            match state {
                 ExecState::Continue(pc) => {
                     self.pc = pc;
+                    let addr = self.stack.frames().last().map(|f| f.instance_addr);
+                    if addr != code_addr {
+                        code_addr = addr;
+                        code = self.current_code();
+                    }
                 }
                 ExecState::Return => break,
                 ExecState::EnvFunc { params, name } => {
@@ -493,7 +1033,7 @@ impl Runtime {
                     let memory = instance.memaddr.map(|a| &mut store.mems[a]);
                     let results = env
                         .call(&name, params, memory)
-                        .map_err(|err| Trap::Env(err))?;
+                        .map_err(|err| self.trap_at(store, Trap::Env(err)))?;
                     for result in results {
                         self.stack.push_value(result);
                     }
@@ -501,16 +1041,57 @@ impl Runtime {
                 }
             }
         }
-        Ok(self.stack.get_returns())
+        let results = self.stack.get_returns(arity);
+        if cfg!(debug_assertions) && !self.stack.values().is_empty() {
+            return Err(self.trap_at(store, Trap::StackNotEmpty));
+        }
+        Ok(results)
+    }
+
+    fn trap_at(&self, store: &Store, trap: Trap) -> RuntimeError {
+        let instance_addr = self.stack.frames().last().map(|frame| frame.instance_addr);
+        let func = instance_addr.and_then(|instance_addr| {
+            self.instances[instance_addr]
+                .funcaddrs
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, &addr)| match store.funcs[addr] {
+                    FuncInst::InnerFunc {
+                        instance_addr: addr,
+                        start,
+                        ..
+                    } if addr == instance_addr && start <= self.pc => Some((idx, start)),
+                    _ => None,
+                })
+                .max_by_key(|&(_, start)| start)
+                .map(|(idx, start)| (idx as FuncIdx, self.pc - start))
+        });
+        RuntimeError::Trap {
+            trap,
+            pc: self.pc,
+            func,
+        }
+    }
+
+    /// The code of the instance the top frame runs in.
+    fn current_code(&self) -> Arc<Vec<Instr>> {
+        self.stack
+            .frames()
+            .last()
+            .map_or_else(Arc::default, |frame| {
+                Arc::clone(&self.instances[frame.instance_addr].code)
+            })
     }
 
     pub fn step(&mut self, store: &mut Store) -> Result<ExecState, Trap> {
+        let code = self.current_code();
         match step(
             &mut self.instances,
-            &self.instrs,
+            &code,
             self.pc,
             store,
             &mut self.stack,
+            &mut self.hooks,
         ) {
             Ok(state) => {
                 if let ExecState::Continue(next) = state {
@@ -534,12 +1115,16 @@ impl Runtime {
 
 #[cfg(test)]
 mod tests {
-    use super::Runtime;
+    use super::{
+        check_flattened, ExecState, ExportKind, ImportType, Runtime, RuntimeError, PAGE_SIZE,
+    };
     use crate::binary::Module;
-    use crate::exec::env::DebugEnv;
+    use crate::binary::{Block, FuncType, Instr, RefType, ResultType, ValType};
+    use crate::exec::env::{DebugEnv, Env};
     use crate::exec::importer::Importer;
-    use crate::exec::store::Store;
-    use crate::exec::value::Value;
+    use crate::exec::store::{MemInst, Store};
+    use crate::exec::trap::Trap;
+    use crate::exec::value::{Ref, Value};
     use crate::loader::parser::Parser;
     use crate::tests::wat2wasm;
 
@@ -549,7 +1134,7 @@ mod tests {
             r#"(module
                   (memory 1)
                   (global $x (mut i32) (i32.const -12))
-                  (table 2 anyfunc)
+                  (table 2 funcref)
                   (func $f1 (result i32) i32.const 42)
                   (func $f2 (result i32) i32.const 13)
                   (elem (i32.const 0) $f1 $f2)
@@ -597,4 +1182,1617 @@ mod tests {
         assert_eq!(store.mems.to_vec().len(), 0);
         assert_eq!(store.tables.to_vec().len(), 0);
     }
+
+    #[test]
+    fn trap() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func (result i32) i32.const 1)
+                  (func (export "main") nop nop unreachable))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let mut env = DebugEnv {};
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "main", vec![]),
+            Err(RuntimeError::Trap {
+                trap: Trap::Unreachable,
+                pc: 4,
+                func: Some((1, 2)),
+            })
+        );
+    }
+
+    #[test]
+    fn flattened() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func (param i32) (result i32)
+                    (block
+                      (loop
+                        (if (local.get 0)
+                          (then (block (br 2)))
+                          (else (if (local.get 0) (then (br 1)))))))
+                    i32.const 0))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        assert_eq!(check_flattened(&module.funcs[0].body.0), Ok(()));
+
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+    }
+
+    #[test]
+    fn flattened_violation() {
+        assert_eq!(
+            check_flattened(&[
+                Instr::Block {
+                    bt: Block::Empty,
+                    end_offset: 3,
+                },
+                Instr::PopLabel,
+                Instr::Nop,
+            ]),
+            Err("block at 0: end_offset does not point past its PopLabel".to_string())
+        );
+        assert_eq!(
+            check_flattened(&[Instr::Loop { bt: Block::Empty }, Instr::Nop]),
+            Err("control instruction at 0 has no PopLabel".to_string())
+        );
+        assert_eq!(
+            check_flattened(&[Instr::Nop, Instr::PopLabel]),
+            Err("PopLabel at 1 does not close any block".to_string())
+        );
+
+        // Deep nesting is checked without recursing.
+        let mut deep = vec![Instr::Loop { bt: Block::Empty }; 100_000];
+        deep.extend(vec![Instr::PopLabel; 100_000]);
+        assert_eq!(check_flattened(&deep), Ok(()));
+    }
+
+    #[test]
+    fn memory_init() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (memory 1)
+                  (data (i32.const 0) "ab")
+                  (data "cd")
+                  (func (export "init") (param i32) (result i32)
+                    (memory.init 1 (i32.const 4) (i32.const 0) (i32.const 2))
+                    (memory.init 0 (i32.const 8) (i32.const 0) (local.get 0))
+                    (i32.load16_u (i32.const 4))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let mut env = DebugEnv {};
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "init", vec![Value::I32(0)]),
+            Ok(vec![Value::I32(0x6463)])
+        );
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "init", vec![Value::I32(1)]),
+            Err(RuntimeError::Trap {
+                trap: Trap::MemoryOutOfBounds,
+                pc: 7,
+                func: Some((0, 7)),
+            })
+        );
+    }
+
+    #[test]
+    fn ref_params() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func (export "is_null") (param funcref) (result i32)
+                    (ref.is_null (local.get 0))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let mut env = DebugEnv {};
+        assert_eq!(
+            runtime.invoke(
+                &mut store,
+                &mut env,
+                "is_null",
+                vec![Value::Ref(Ref::Null(RefType::FuncRef))]
+            ),
+            Ok(vec![Value::I32(1)])
+        );
+        let func_ref = runtime.func_ref("is_null").unwrap();
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "is_null", vec![Value::Ref(func_ref)]),
+            Ok(vec![Value::I32(0)])
+        );
+        assert_eq!(
+            runtime.invoke(
+                &mut store,
+                &mut env,
+                "is_null",
+                vec![Value::Ref(Ref::Extern(0))]
+            ),
+            Err(RuntimeError::InvalidParams)
+        );
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "is_null", vec![]),
+            Err(RuntimeError::InvalidParams)
+        );
+    }
+
+    #[test]
+    fn global_ref_func() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (table 1 funcref)
+                  (global $g funcref (ref.func $f))
+                  (func $f (result i32) i32.const 42)
+                  (elem declare func $f)
+                  (func (export "main") (result i32)
+                    (table.fill 0 (i32.const 0) (global.get $g) (i32.const 1))
+                    (call_indirect (result i32) (i32.const 0))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        // Instantiate twice so that function indices and addresses differ.
+        Runtime::new("env")
+            .add_module(&mut store, module.clone())
+            .unwrap();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let mut env = DebugEnv {};
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "main", vec![]),
+            Ok(vec![Value::I32(42)])
+        );
+    }
+
+    #[test]
+    fn initializer() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (global $g (mut i32) (i32.const 0))
+                  (func (export "_initialize") (global.set $g (i32.const 7)))
+                  (func (export "get") (result i32) (global.get $g)))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let mut env = DebugEnv {};
+        runtime.run_initializer(&mut store, &mut env).unwrap();
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "get", vec![]),
+            Ok(vec![Value::I32(7)])
+        );
+    }
+
+    #[test]
+    fn shared_memory() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (memory 1 2 shared)
+                  (func (export "grow") (param i32) (result i32)
+                    (memory.grow (local.get 0))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let mut env = DebugEnv {};
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "grow", vec![Value::I32(1)]),
+            Ok(vec![Value::I32(1)])
+        );
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "grow", vec![Value::I32(1)]),
+            Ok(vec![Value::I32(-1)])
+        );
+
+        // (memory 1 shared)
+        let wasm = [
+            0x00, 0x61, 0x73, 0x6D, 0x01, 0x00, 0x00, 0x00, // header
+            0x05, 0x03, 0x01, 0x02, 0x01, // memory section
+        ];
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        assert_eq!(
+            runtime.add_module(&mut store, module),
+            Err(RuntimeError::SharedMemoryWithoutMax)
+        );
+    }
+
+    #[test]
+    fn start_signature() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func $f (param i32))
+                  (start $f))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        assert_eq!(
+            runtime.add_module(&mut store, module),
+            Err(RuntimeError::InvalidStartSignature)
+        );
+    }
+
+    #[test]
+    fn memory_grow() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (memory 3)
+                  (func (export "grow") (param i32) (result i32 i32)
+                    (memory.size)
+                    (memory.grow (local.get 0))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let mut env = DebugEnv {};
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "grow", vec![Value::I32(2)]),
+            Ok(vec![Value::I32(3), Value::I32(3)])
+        );
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "grow", vec![Value::I32(0)]),
+            Ok(vec![Value::I32(5), Value::I32(5)])
+        );
+        // 0xFFFF_FFFF pages must fail instead of overflowing.
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "grow", vec![Value::I32(-1)]),
+            Ok(vec![Value::I32(5), Value::I32(-1)])
+        );
+    }
+
+    #[test]
+    fn mem_violation_hook() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let wasm = wat2wasm(
+            r#"(module
+                  (memory 1)
+                  (func (export "load") (result i32)
+                    (i32.load offset=2 (i32.const 65532)))
+                  (func (export "load_high") (result i32)
+                    (i32.load offset=2 (i32.const 0x80000000)))
+                  (func (export "store_high")
+                    (i32.store offset=2 (i32.const 0xFFFFFFF0) (i32.const 1))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+
+        let violation = Rc::new(RefCell::new(None));
+        let captured = violation.clone();
+        runtime.set_mem_violation_hook(Box::new(move |ea, size, mem_len| {
+            *captured.borrow_mut() = Some((ea, size, mem_len));
+        }));
+        let mut env = DebugEnv {};
+        assert!(runtime
+            .invoke(&mut store, &mut env, "load", vec![])
+            .is_err());
+        assert_eq!(*violation.borrow(), Some((65534, 4, 65536)));
+
+        // Addresses are unsigned, however high the pointer.
+        assert!(runtime
+            .invoke(&mut store, &mut env, "load_high", vec![])
+            .is_err());
+        assert_eq!(*violation.borrow(), Some((0x8000_0002, 4, 65536)));
+        assert!(runtime
+            .invoke(&mut store, &mut env, "store_high", vec![])
+            .is_err());
+        assert_eq!(*violation.borrow(), Some((0xFFFF_FFF2, 4, 65536)));
+    }
+
+    #[test]
+    fn result_count() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func $inner (result i32 i32) (i32.const 1) (i32.const 2))
+                  (func (export "main") (result i32 i32 i32)
+                    (block (result i32) (i32.const 0))
+                    (call $inner)))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let mut env = DebugEnv {};
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "main", vec![]),
+            Ok(vec![Value::I32(0), Value::I32(1), Value::I32(2)])
+        );
+        assert!(runtime.stack.values().is_empty());
+    }
+
+    #[test]
+    fn exports() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (memory (export "mem") 1)
+                  (global (export "g") i64 (i64.const 0))
+                  (func (export "f") (param i32) (result f32) (f32.const 0)))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        assert_eq!(
+            runtime.exports(&store).collect::<Vec<_>>(),
+            vec![
+                ("mem", ExportKind::Memory),
+                ("g", ExportKind::Global(ValType::I64)),
+                (
+                    "f",
+                    ExportKind::Func(FuncType(
+                        ResultType(vec![ValType::I32]),
+                        ResultType(vec![ValType::F32])
+                    ))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn trailing_return() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func (export "explicit") (result i32)
+                    i32.const 1
+                    return)
+                  (func (export "implicit") (result i32)
+                    i32.const 2))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        assert_eq!(
+            *runtime.instances[0].code,
+            vec![
+                Instr::I32Const(1),
+                Instr::Return,
+                Instr::I32Const(2),
+                Instr::Return,
+            ]
+        );
+        assert_eq!(
+            runtime.invoke(&mut store, &mut DebugEnv {}, "explicit", vec![]),
+            Ok(vec![Value::I32(1)])
+        );
+        assert_eq!(
+            runtime.invoke(&mut store, &mut DebugEnv {}, "implicit", vec![]),
+            Ok(vec![Value::I32(2)])
+        );
+    }
+
+    #[test]
+    fn table_init() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (type $t (func (result i32)))
+                  (func $f (result i32) (i32.const 7))
+                  (table 3 funcref (ref.func $f))
+                  (func (export "call") (param i32) (result i32)
+                    (call_indirect (type $t) (local.get 0))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let f = runtime.instances[runtime.root].funcaddrs[0];
+        let table = runtime.instances[runtime.root].tableaddrs[0];
+        assert_eq!(store.tables[table].elem, vec![Ref::Func(f); 3]);
+        for i in 0..3 {
+            assert_eq!(
+                runtime.invoke(&mut store, &mut DebugEnv {}, "call", vec![Value::I32(i)]),
+                Ok(vec![Value::I32(7)])
+            );
+        }
+    }
+
+    #[test]
+    fn call_stack_exhausted() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func $even (export "even") (param i32) (result i32)
+                    (if (result i32) (i32.eqz (local.get 0))
+                      (then (i32.const 1))
+                      (else (call $odd (i32.sub (local.get 0) (i32.const 1))))))
+                  (func $odd (param i32) (result i32)
+                    (if (result i32) (i32.eqz (local.get 0))
+                      (then (i32.const 0))
+                      (else (call $even (i32.sub (local.get 0) (i32.const 1))))))
+                  (func $runaway (export "runaway") (call $runaway)))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        assert_eq!(
+            runtime.invoke(&mut store, &mut DebugEnv {}, "even", vec![Value::I32(100)]),
+            Ok(vec![Value::I32(1)])
+        );
+        assert!(matches!(
+            runtime.invoke(&mut store, &mut DebugEnv {}, "runaway", vec![]),
+            Err(RuntimeError::Trap {
+                trap: Trap::CallStackExhausted,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn instantiate_partial() {
+        struct NoModules {}
+        impl Importer for NoModules {
+            fn import(&mut self, _: &str) -> Option<Module> {
+                None
+            }
+        }
+        struct Lib {}
+        impl Importer for Lib {
+            fn import(&mut self, modname: &str) -> Option<Module> {
+                if modname != "lib" {
+                    return None;
+                }
+                let wasm = wat2wasm(
+                    r#"(module
+                          (import "missing" "h" (func))
+                          (func (export "f") (result i32) (i32.const 7)))"#,
+                )
+                .unwrap();
+                Some(Parser::new(&wasm).module().unwrap())
+            }
+        }
+
+        let wasm = wat2wasm(
+            r#"(module
+                  (import "missing" "f" (func $f (result i32)))
+                  (func (export "g") (result i32) (call $f))
+                  (func (export "one") (result i32) (i32.const 1)))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime
+            .instantiate_partial(&mut store, &mut NoModules {}, module)
+            .unwrap();
+        assert_eq!(
+            runtime
+                .exports(&store)
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>(),
+            vec!["g", "one"]
+        );
+        assert_eq!(
+            runtime.invoke(&mut store, &mut DebugEnv {}, "one", vec![]),
+            Ok(vec![Value::I32(1)])
+        );
+        assert!(matches!(
+            runtime.invoke(&mut store, &mut DebugEnv {}, "g", vec![]),
+            Err(RuntimeError::Trap {
+                trap: Trap::UnresolvedImport(name),
+                ..
+            }) if name == "missing.f"
+        ));
+
+        // Only the module itself is partial: a library with an unresolved
+        // import of its own cannot be instantiated, so the import of it is
+        // the one stubbed.
+        let wasm = wat2wasm(
+            r#"(module
+                  (import "lib" "f" (func $f (result i32)))
+                  (func (export "g") (result i32) (call $f)))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime
+            .instantiate_partial(&mut store, &mut Lib {}, module)
+            .unwrap();
+        assert!(matches!(
+            runtime.invoke(&mut store, &mut DebugEnv {}, "g", vec![]),
+            Err(RuntimeError::Trap {
+                trap: Trap::UnresolvedImport(name),
+                ..
+            }) if name == "lib.f"
+        ));
+    }
+
+    #[test]
+    fn memory_fill() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (memory 1)
+                  (func (export "fill")
+                    (memory.fill (i32.const 0) (i32.const 0x12345678) (i32.const 3))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        assert_eq!(
+            runtime.invoke(&mut store, &mut DebugEnv {}, "fill", vec![]),
+            Ok(vec![])
+        );
+        let memaddr = runtime.instances[runtime.root].memaddr.unwrap();
+        assert_eq!(&store.mems[memaddr].data[..4], &[0x78, 0x78, 0x78, 0x00]);
+    }
+
+    #[test]
+    fn typed_null() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (table $f 1 funcref)
+                  (table $e 1 externref)
+                  (func (export "get_func") (result funcref)
+                    (table.get $f (i32.const 0)))
+                  (func (export "get_extern") (result externref)
+                    (table.get $e (i32.const 0)))
+                  (func (export "is_null") (result i32)
+                    (ref.is_null (table.get $e (i32.const 0)))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let mut env = DebugEnv {};
+
+        let func = runtime.invoke(&mut store, &mut env, "get_func", vec![]);
+        assert_eq!(func, Ok(vec![Value::Ref(Ref::Null(RefType::FuncRef))]));
+        assert!(func.unwrap()[0].is_type(&ValType::FuncRef));
+
+        let extern_ = runtime.invoke(&mut store, &mut env, "get_extern", vec![]);
+        assert_eq!(extern_, Ok(vec![Value::Ref(Ref::Null(RefType::ExternRef))]));
+        let extern_ = extern_.unwrap();
+        assert!(extern_[0].is_type(&ValType::ExternRef));
+        assert!(!extern_[0].is_type(&ValType::FuncRef));
+
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "is_null", vec![]),
+            Ok(vec![Value::I32(1)])
+        );
+    }
+
+    #[test]
+    fn ref_locals() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func (export "locals") (result funcref externref i32)
+                    (local funcref externref)
+                    local.get 0
+                    local.get 1
+                    (ref.is_null (local.get 1))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        assert_eq!(
+            runtime.invoke(&mut store, &mut DebugEnv {}, "locals", vec![]),
+            Ok(vec![
+                Value::Ref(Ref::Null(RefType::FuncRef)),
+                Value::Ref(Ref::Null(RefType::ExternRef)),
+                Value::I32(1),
+            ])
+        );
+    }
+
+    #[test]
+    fn declarative_elem() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func $f (result i32) (i32.const 42))
+                  (elem declare func $f)
+                  (func (export "get") (result funcref) (ref.func $f)))"#,
+        )
+        .unwrap();
+        let module = crate::loader::parse(&wasm).unwrap();
+        assert!(module.tables.is_empty());
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let f = runtime.instances[runtime.root].funcaddrs[0];
+        assert_eq!(
+            runtime.invoke(&mut store, &mut DebugEnv {}, "get", vec![]),
+            Ok(vec![Value::Ref(Ref::Func(f))])
+        );
+    }
+
+    #[test]
+    fn grow_memory() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (memory (export "mem") 1 4)
+                  (func (export "size") (result i32) (memory.size)))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        assert_eq!(runtime.grow_memory(&mut store, "mem", 2), Ok(1));
+        assert_eq!(runtime.memory_pages(&store, "mem"), Ok(3));
+        assert_eq!(store.mems[0].data.len(), 3 * PAGE_SIZE);
+        assert_eq!(
+            runtime.invoke(&mut store, &mut DebugEnv {}, "size", vec![]),
+            Ok(vec![Value::I32(3)])
+        );
+        assert_eq!(runtime.grow_memory(&mut store, "mem", 2), Ok(-1));
+        assert_eq!(
+            runtime.grow_memory(&mut store, "size", 1),
+            Err(RuntimeError::NotFound(ImportType::Mem("size".into())))
+        );
+    }
+
+    #[test]
+    fn reentrant_invoke() {
+        use crate::exec::env::Env;
+        use crate::exec::store::MemInst;
+        use std::{cell::RefCell, rc::Rc};
+
+        struct Reenter {
+            runtime: Rc<RefCell<Runtime>>,
+        }
+        impl Env for Reenter {
+            fn call(
+                &mut self,
+                _: &str,
+                _: Vec<Value>,
+                _: Option<&mut MemInst>,
+            ) -> Result<Vec<Value>, &'static str> {
+                match self.runtime.try_borrow_mut() {
+                    Ok(_) => Err("runtime was not borrowed"),
+                    Err(_) => Ok(vec![Value::I32(10)]),
+                }
+            }
+        }
+
+        let wasm = wat2wasm(
+            r#"(module
+                  (import "env" "host" (func $host (result i32)))
+                  (func (export "f") (result i32)
+                    (i32.add (i32.const 1) (call $host))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let runtime = Rc::new(RefCell::new(Runtime::new("env")));
+        runtime.borrow_mut().add_module(&mut store, module).unwrap();
+        let mut env = Reenter {
+            runtime: runtime.clone(),
+        };
+        let result = runtime
+            .borrow_mut()
+            .invoke(&mut store, &mut env, "f", vec![]);
+        assert_eq!(result, Ok(vec![Value::I32(11)]));
+        assert!(runtime.borrow().stack.is_empty());
+    }
+
+    #[test]
+    fn call_indirect_traps() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (type $r (func (result i32)))
+                  (func $f (param i32))
+                  (table 3 funcref)
+                  (elem (i32.const 0) $f)
+                  (func (export "call") (param i32) (result i32)
+                    (call_indirect (type $r) (local.get 0))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        for (i, expected) in [
+            (3, Trap::UndefinedElement),
+            (1, Trap::UninitializedElement),
+            (0, Trap::IndirectCallTypeMismatch),
+        ] {
+            match runtime.invoke(&mut store, &mut DebugEnv {}, "call", vec![Value::I32(i)]) {
+                Err(RuntimeError::Trap { trap, .. }) => assert_eq!(trap, expected),
+                ret => panic!("expected {:?}, found {:?}", expected, ret),
+            }
+        }
+    }
+
+    #[test]
+    fn constant_expression() {
+        for init in ["unreachable", "i32.const 1 i32.const 2 drop"] {
+            let wasm = wat2wasm(format!(
+                "(module (global i32 {}) (func (export \"f\")))",
+                init
+            ))
+            .unwrap();
+            let module = Parser::new(&wasm).module().unwrap();
+            let mut store = Store::new();
+            let mut runtime = Runtime::new("env");
+            assert_eq!(
+                runtime.add_module(&mut store, module),
+                Err(RuntimeError::ConstantExpression),
+                "{}",
+                init
+            );
+        }
+    }
+
+    #[test]
+    fn resource_usage() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (memory 2)
+                  (table 3 funcref)
+                  (table 4 externref))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        assert_eq!(runtime.total_memory_bytes(&store), 2 * 65536);
+        assert_eq!(runtime.table_entry_count(&store), 7);
+    }
+
+    #[test]
+    fn param_order() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func (export "first") (param i32 i32 i32) (result i32) local.get 0)
+                  (func (export "last") (param i32 i32 i32) (result i32) local.get 2))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let args = vec![Value::I32(10), Value::I32(20), Value::I32(30)];
+        for (name, expected) in [("first", 10), ("last", 30)] {
+            assert_eq!(
+                runtime.invoke(&mut store, &mut DebugEnv {}, name, args.clone()),
+                Ok(vec![Value::I32(expected)])
+            );
+        }
+    }
+
+    #[test]
+    fn reachable_funcs() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (type $t (func))
+                  (table 1 funcref)
+                  (elem (i32.const 0) $indirect)
+                  (func $entry (export "entry") call $direct)
+                  (func $direct i32.const 0 call_indirect (type $t))
+                  (func $dead call $direct)
+                  (func $indirect)
+                  (func (export "other")))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        assert_eq!(runtime.reachable_funcs(&store, "entry"), vec![0, 1, 3]);
+        assert_eq!(runtime.reachable_funcs(&store, "other"), vec![4]);
+        assert!(runtime.reachable_funcs(&store, "missing").is_empty());
+    }
+
+    #[test]
+    fn warn_misaligned() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let wasm = wat2wasm(
+            r#"(module
+                  (memory 1)
+                  (data (i32.const 1) "\2a\00\00\00")
+                  (func (export "load") (result i32)
+                    (i32.load align=1 (i32.const 1)))
+                  (func (export "aligned") (result i32)
+                    (i32.load (i32.const 4))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+
+        let messages = Rc::new(RefCell::new(vec![]));
+        let captured = messages.clone();
+        runtime.set_log_hook(Box::new(move |message| {
+            captured.borrow_mut().push(message.to_string());
+        }));
+        let mut env = DebugEnv {};
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "load", vec![]),
+            Ok(vec![Value::I32(42)])
+        );
+        assert!(messages.borrow().is_empty());
+
+        runtime.set_warn_misaligned(true);
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "load", vec![]),
+            Ok(vec![Value::I32(42)])
+        );
+        runtime
+            .invoke(&mut store, &mut env, "aligned", vec![])
+            .unwrap();
+        assert_eq!(
+            *messages.borrow(),
+            vec!["4-byte access at 0x1 has alignment 2^0, expected 2^2".to_string()]
+        );
+    }
+
+    #[test]
+    fn begin_invoke() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func $square (param i32) (result i32)
+                    (i32.mul (local.get 0) (local.get 0)))
+                  (func (export "main") (param i32) (result i32)
+                    (block (result i32)
+                      (br 0 (call $square (local.get 0))))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+
+        runtime
+            .begin_invoke(&mut store, "main", vec![Value::I32(7)])
+            .unwrap();
+        let mut steps = 0;
+        loop {
+            match runtime.step(&mut store).unwrap() {
+                ExecState::Continue(_) => steps += 1,
+                ExecState::Return => break,
+                ExecState::EnvFunc { .. } => unreachable!(),
+            }
+        }
+        assert!(steps > 1);
+        assert_eq!(runtime.stack.values(), &[Value::I32(49)]);
+
+        assert_eq!(
+            runtime.begin_invoke(&mut store, "missing", vec![]),
+            Err(RuntimeError::NotFound(ImportType::Func("missing".into())))
+        );
+    }
+
+    #[test]
+    fn zero_length_bulk_memory() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (memory 1)
+                  (data $d "abc")
+                  (func (export "fill") (param i32)
+                    (memory.fill (local.get 0) (i32.const 0) (i32.const 0)))
+                  (func (export "copy") (param i32 i32)
+                    (memory.copy (local.get 0) (local.get 1) (i32.const 0)))
+                  (func (export "init") (param i32 i32)
+                    (memory.init $d (local.get 0) (local.get 1) (i32.const 0))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let mut env = DebugEnv {};
+
+        let cases = [
+            ("fill", vec![65536], true),
+            ("fill", vec![65537], false),
+            ("fill", vec![-1], false),
+            ("copy", vec![65536, 0], true),
+            ("copy", vec![65537, 0], false),
+            ("copy", vec![0, 65537], false),
+            ("init", vec![65536, 3], true),
+            ("init", vec![65537, 0], false),
+            ("init", vec![0, 4], false),
+        ];
+        for (name, args, ok) in cases {
+            let args = args.into_iter().map(Value::I32).collect::<Vec<_>>();
+            let result = runtime.invoke(&mut store, &mut env, name, args.clone());
+            if ok {
+                assert_eq!(result, Ok(vec![]), "{} {:?}", name, args);
+            } else {
+                assert!(
+                    matches!(
+                        result,
+                        Err(RuntimeError::Trap {
+                            trap: Trap::MemoryOutOfBounds,
+                            ..
+                        })
+                    ),
+                    "{} {:?}",
+                    name,
+                    args
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn func_name() {
+        // wat keeps `$` identifiers in a name section, as a debug build
+        // would.
+        let wasm = wat2wasm(
+            r#"(module
+                  (import "env" "log" (func $log (param i32)))
+                  (func $main (export "main"))
+                  (func))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        assert_eq!(runtime.func_name(0), Some("log"));
+        assert_eq!(runtime.func_name(1), Some("main"));
+        assert_eq!(runtime.func_name(2), None);
+    }
+
+    #[test]
+    fn simd() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (memory 1)
+                  (func (export "lane") (result i32)
+                    (i32x4.extract_lane 2 (v128.const i32x4 1 2 3 4)))
+                  (func (export "store") (result i32)
+                    (v128.store (i32.const 0) (v128.const i32x4 1 2 3 4))
+                    (i32.load (i32.const 4)))
+                  (func (export "add") (result i32)
+                    (i32x4.extract_lane 3
+                      (i32x4.add (i32x4.splat (i32.const 10)) (v128.load (i32.const 0)))))
+                  (func (export "mul") (result f32)
+                    (f32x4.extract_lane 1
+                      (f32x4.mul (v128.const f32x4 1 2 3 4) (f32x4.splat (f32.const 1.5)))))
+                  (func (export "signed") (result i32 i32)
+                    (i8x16.extract_lane_s 15 (i8x16.splat (i32.const 255)))
+                    (i8x16.extract_lane_u 15 (i8x16.splat (i32.const 255)))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        crate::loader::validate::validate(&module).unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let mut env = DebugEnv {};
+
+        let mut invoke = |name| runtime.invoke(&mut store, &mut env, name, vec![]).unwrap();
+        assert_eq!(invoke("lane"), vec![Value::I32(3)]);
+        assert_eq!(invoke("store"), vec![Value::I32(2)]);
+        assert_eq!(invoke("add"), vec![Value::I32(14)]);
+        assert!(matches!(invoke("mul")[..], [Value::F32(v)] if v == 3.0));
+        assert_eq!(invoke("signed"), vec![Value::I32(-1), Value::I32(255)]);
+    }
+
+    #[test]
+    fn br_table_default_returns() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func $pick (export "pick") (param i32) (result i32)
+                    (i32.add
+                      (block (result i32)
+                        (br_table 0 1 (i32.const 10) (local.get 0)))
+                      (i32.const 1)))
+                  (func (export "outer") (result i32)
+                    (i32.add (call $pick (i32.const 7)) (i32.const 100))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let mut env = DebugEnv {};
+
+        for (arg, expected) in [(0, 11), (1, 10), (-1, 10)] {
+            assert_eq!(
+                runtime.invoke(&mut store, &mut env, "pick", vec![Value::I32(arg)]),
+                Ok(vec![Value::I32(expected)]),
+                "{}",
+                arg
+            );
+        }
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "outer", vec![]),
+            Ok(vec![Value::I32(110)])
+        );
+    }
+
+    #[test]
+    fn function_branch_inside_caller_block() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func $early (param i32) (result i32)
+                    (br_if 0 (i32.const 5) (local.get 0))
+                    drop
+                    (i32.const 6))
+                  (func $br (result i32)
+                    (br 0 (i32.const 7)))
+                  (func $table (param i32) (result i32)
+                    (br_table 0 0 (i32.const 8) (local.get 0)))
+                  (func $ret (result i32)
+                    (block (block (return (i32.const 9)))))
+                  (func (export "early") (param i32) (result i32)
+                    (block (result i32)
+                      (br 0 (i32.add (call $early (local.get 0)) (i32.const 100)))))
+                  (func (export "br") (result i32)
+                    (block (result i32) (call $br)))
+                  (func (export "table") (result i32)
+                    (block (result i32) (block (result i32) (call $table (i32.const 5)))))
+                  (func (export "ret") (result i32)
+                    (block (result i32)
+                      (br 0 (i32.add (call $ret) (i32.const 100))))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let mut env = DebugEnv {};
+
+        let cases = [
+            ("early", vec![Value::I32(1)], 105),
+            ("early", vec![Value::I32(0)], 106),
+            ("br", vec![], 7),
+            ("table", vec![], 8),
+            ("ret", vec![], 109),
+        ];
+        for (name, params, expected) in cases {
+            assert_eq!(
+                runtime.invoke(&mut store, &mut env, name, params),
+                Ok(vec![Value::I32(expected)]),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn trap_into_runtime_error() {
+        fn fails() -> Result<(), RuntimeError> {
+            Err(Trap::Unreachable)?
+        }
+        assert_eq!(
+            fails(),
+            Err(RuntimeError::Trap {
+                trap: Trap::Unreachable,
+                pc: 0,
+                func: None
+            })
+        );
+    }
+
+    #[test]
+    fn multiple_exports() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func (export "x") (export "y") (param i32) (result i32)
+                    (i32.mul (local.get 0) (i32.const 2))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let mut env = DebugEnv {};
+
+        for name in ["x", "y"] {
+            assert_eq!(
+                runtime.invoke(&mut store, &mut env, name, vec![Value::I32(21)]),
+                Ok(vec![Value::I32(42)])
+            );
+            assert_eq!(runtime.reachable_funcs(&store, name), vec![0]);
+        }
+    }
+
+    #[test]
+    fn table_get_set_bounds() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (table 2 externref)
+                  (func (export "get") (param i32) (result i32)
+                    (ref.is_null (table.get (local.get 0))))
+                  (func (export "set") (param i32)
+                    (table.set (local.get 0) (ref.null extern))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let mut env = DebugEnv {};
+
+        for i in [0, 1] {
+            assert_eq!(
+                runtime.invoke(&mut store, &mut env, "get", vec![Value::I32(i)]),
+                Ok(vec![Value::I32(1)])
+            );
+            assert_eq!(
+                runtime.invoke(&mut store, &mut env, "set", vec![Value::I32(i)]),
+                Ok(vec![])
+            );
+        }
+        for name in ["get", "set"] {
+            for i in [2, -1] {
+                let result = runtime.invoke(&mut store, &mut env, name, vec![Value::I32(i)]);
+                assert!(
+                    matches!(
+                        result,
+                        Err(RuntimeError::Trap {
+                            trap: Trap::TableOutOfRange,
+                            ..
+                        })
+                    ),
+                    "{} {}: {:?}",
+                    name,
+                    i,
+                    result
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn trap_on_nan() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func (export "div") (param f32 f32) (result f32)
+                    (f32.div (local.get 0) (local.get 1)))
+                  (func (export "neg") (param f32) (result f32)
+                    (f32.neg (local.get 0)))
+                  (func (export "demote") (result f32)
+                    (f32.demote_f64 (f64.const nan)))
+                  (func (export "div4") (result i32)
+                    (i32x4.extract_lane 0
+                      (f32x4.div (v128.const f32x4 1 0 1 1) (v128.const f32x4 1 0 1 1)))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let mut env = DebugEnv {};
+        let zeros = vec![Value::F32(0.0), Value::F32(0.0)];
+
+        let result = runtime.invoke(&mut store, &mut env, "div", zeros.clone());
+        assert!(matches!(result.as_deref(), Ok([Value::F32(v)]) if v.is_nan()));
+
+        runtime.set_trap_on_nan(true);
+        let result = runtime.invoke(&mut store, &mut env, "div", zeros);
+        assert!(matches!(
+            result,
+            Err(RuntimeError::Trap {
+                trap: Trap::UnexpectedNaN,
+                ..
+            })
+        ));
+        let args = vec![Value::F32(1.0), Value::F32(4.0)];
+        let result = runtime.invoke(&mut store, &mut env, "div", args);
+        assert!(matches!(result.as_deref(), Ok([Value::F32(v)]) if *v == 0.25));
+
+        // Sign operations pass a NaN argument through without trapping.
+        let nan = vec![Value::F32(f32::NAN)];
+        let result = runtime.invoke(&mut store, &mut env, "neg", nan.clone());
+        assert!(matches!(result.as_deref(), Ok([Value::F32(v)]) if v.is_nan()));
+
+        for name in ["demote", "div4"] {
+            let result = runtime.invoke(&mut store, &mut env, name, vec![]);
+            assert!(
+                matches!(
+                    result,
+                    Err(RuntimeError::Trap {
+                        trap: Trap::UnexpectedNaN,
+                        ..
+                    })
+                ),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn atomics_single_threaded() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (memory 1 1 shared)
+                  (func (export "notify") (param i32) (result i32)
+                    atomic.fence
+                    (memory.atomic.notify (local.get 0) (i32.const 1)))
+                  (data (i32.const 16) "\07\00\00\00\00\00\00\00")
+                  (func (export "wait32") (param i32 i32 i64) (result i32)
+                    (memory.atomic.wait32 (local.get 0) (local.get 1) (local.get 2)))
+                  (func (export "wait64") (param i32 i64 i64) (result i32)
+                    (memory.atomic.wait64 (local.get 0) (local.get 1) (local.get 2))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        crate::loader::validate::validate(&module).unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let mut env = DebugEnv {};
+
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "notify", vec![Value::I32(8)]),
+            Ok(vec![Value::I32(0)])
+        );
+        assert!(matches!(
+            runtime.invoke(&mut store, &mut env, "notify", vec![Value::I32(65536)]),
+            Err(RuntimeError::Trap {
+                trap: Trap::MemoryOutOfBounds,
+                ..
+            })
+        ));
+        assert!(matches!(
+            runtime.invoke(&mut store, &mut env, "notify", vec![Value::I32(6)]),
+            Err(RuntimeError::Trap {
+                trap: Trap::UnalignedAtomic,
+                ..
+            })
+        ));
+
+        let wait32 = |addr, expected, timeout| {
+            vec![Value::I32(addr), Value::I32(expected), Value::I64(timeout)]
+        };
+        let wait64 = |addr, expected, timeout| {
+            vec![Value::I32(addr), Value::I64(expected), Value::I64(timeout)]
+        };
+        let cases = [
+            ("wait32", wait32(16, 0, -1), Ok(1)),
+            ("wait32", wait32(16, 7, 0), Ok(2)),
+            ("wait32", wait32(16, 7, 1_000), Ok(2)),
+            ("wait32", wait32(16, 7, -1), Err(Trap::AtomicWait)),
+            ("wait32", wait32(18, 0, 0), Err(Trap::UnalignedAtomic)),
+            ("wait32", wait32(65536, 0, 0), Err(Trap::MemoryOutOfBounds)),
+            ("wait64", wait64(16, 8, -1), Ok(1)),
+            ("wait64", wait64(16, 7, 5), Ok(2)),
+            ("wait64", wait64(16, 7, -1), Err(Trap::AtomicWait)),
+            ("wait64", wait64(20, 0, 0), Err(Trap::UnalignedAtomic)),
+        ];
+        for (name, params, expected) in cases {
+            let result = match runtime.invoke(&mut store, &mut env, name, params) {
+                Ok(results) => Ok(results),
+                Err(RuntimeError::Trap { trap, .. }) => Err(trap),
+                Err(err) => panic!("{:?}", err),
+            };
+            assert_eq!(result, expected.map(|v| vec![Value::I32(v)]), "{}", name);
+        }
+
+        let wasm = wat2wasm(
+            r#"(module
+                  (memory 1)
+                  (func (export "wait32") (param i32 i32 i64) (result i32)
+                    (memory.atomic.wait32 (local.get 0) (local.get 1) (local.get 2))))"#,
+        )
+        .unwrap();
+        let module = crate::loader::parse(&wasm).unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        assert!(matches!(
+            runtime.invoke(&mut store, &mut env, "wait32", wait32(16, 0, 0)),
+            Err(RuntimeError::Trap {
+                trap: Trap::UnsharedAtomicWait,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn fuel() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func (export "spin") (loop (br 0)))
+                  (func (export "three") (result i32)
+                    (i32.add (i32.const 1) (i32.const 2))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let mut env = DebugEnv {};
+
+        runtime.set_fuel(Some(1000));
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "spin", vec![]),
+            Err(RuntimeError::OutOfFuel)
+        );
+        // The budget applies afresh to every invocation. The body is three
+        // instructions plus the return appended when instantiating.
+        runtime.set_fuel(Some(4));
+        for _ in 0..2 {
+            assert_eq!(
+                runtime.invoke(&mut store, &mut env, "three", vec![]),
+                Ok(vec![Value::I32(3)])
+            );
+        }
+        runtime.set_fuel(Some(3));
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "three", vec![]),
+            Err(RuntimeError::OutOfFuel)
+        );
+        runtime.set_fuel(None);
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "three", vec![]),
+            Ok(vec![Value::I32(3)])
+        );
+    }
+
+    #[test]
+    fn import_type_mismatch() {
+        struct LibImporter {}
+        impl Importer for LibImporter {
+            fn import(&mut self, modname: &str) -> Option<Module> {
+                let wat = match modname {
+                    "main" => {
+                        r#"(module
+                              (import "lib" "f" (func (param i32) (result i32)))
+                              (func (export "g") (result i32) (call 0 (i32.const 1))))"#
+                    }
+                    "lib" => r#"(module (func (export "f") (result i32) (i32.const 1)))"#,
+                    _ => return None,
+                };
+                Some(Parser::new(&wat2wasm(wat).unwrap()).module().unwrap())
+            }
+        }
+
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        assert_eq!(
+            runtime.import_module(&mut store, &mut LibImporter {}, "main"),
+            Err(RuntimeError::ImportTypeMismatch(ImportType::Func(
+                "f".into()
+            )))
+        );
+    }
+
+    #[test]
+    fn get_set_global() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (global (export "counter") (mut i32) (i32.const 7))
+                  (global (export "base") i32 (i32.const 1024))
+                  (func (export "get") (result i32) (global.get 0)))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+
+        assert_eq!(runtime.get_global(&store, "base"), Ok(Value::I32(1024)));
+        assert_eq!(runtime.get_global(&store, "counter"), Ok(Value::I32(7)));
+        assert_eq!(
+            runtime.set_global(&mut store, "counter", Value::I32(9)),
+            Ok(())
+        );
+        assert_eq!(
+            runtime.invoke(&mut store, &mut DebugEnv {}, "get", vec![]),
+            Ok(vec![Value::I32(9)])
+        );
+
+        assert_eq!(
+            runtime.set_global(&mut store, "base", Value::I32(0)),
+            Err(RuntimeError::ImmutableGlobal("base".into()))
+        );
+        assert_eq!(
+            runtime.set_global(&mut store, "counter", Value::I64(0)),
+            Err(RuntimeError::InvalidParams)
+        );
+        assert_eq!(
+            runtime.get_global(&store, "get"),
+            Err(RuntimeError::NotFound(ImportType::Global("get".into())))
+        );
+    }
+
+    #[test]
+    fn shared_imported_global() {
+        struct Modules {}
+        impl Importer for Modules {
+            fn import(&mut self, modname: &str) -> Option<Module> {
+                let wat = match modname {
+                    "main" => {
+                        r#"(module
+                              (import "a" "bump_shared" (func $a_bump_shared))
+                              (import "a" "bump_own" (func $a_bump_own))
+                              (import "a" "shared" (func $a_shared (result i32)))
+                              (import "a" "own" (func $a_own (result i32)))
+                              (import "b" "shared" (func $b_shared (result i32)))
+                              (import "b" "own" (func $b_own (result i32)))
+                              (func (export "a_bump_shared") (call $a_bump_shared))
+                              (func (export "a_bump_own") (call $a_bump_own))
+                              (func (export "a_shared") (result i32) (call $a_shared))
+                              (func (export "a_own") (result i32) (call $a_own))
+                              (func (export "b_shared") (result i32) (call $b_shared))
+                              (func (export "b_own") (result i32) (call $b_own)))"#
+                    }
+                    // Both instances come from the same module.
+                    "a" | "b" => {
+                        r#"(module
+                              (import "globals" "g" (global $shared (mut i32)))
+                              (global $own (mut i32) (i32.const 0))
+                              (func (export "bump_shared")
+                                  (global.set $shared (i32.add (global.get $shared) (i32.const 1))))
+                              (func (export "bump_own")
+                                  (global.set $own (i32.add (global.get $own) (i32.const 1))))
+                              (func (export "shared") (result i32) (global.get $shared))
+                              (func (export "own") (result i32) (global.get $own)))"#
+                    }
+                    "globals" => r#"(module (global (export "g") (mut i32) (i32.const 10)))"#,
+                    _ => return None,
+                };
+                Some(Parser::new(&wat2wasm(wat).unwrap()).module().unwrap())
+            }
+        }
+
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime
+            .import_module(&mut store, &mut Modules {}, "main")
+            .unwrap();
+        let mut call = |name| {
+            runtime
+                .invoke(&mut store, &mut DebugEnv {}, name, vec![])
+                .unwrap()
+        };
+
+        call("a_bump_shared");
+        assert_eq!(call("a_shared"), vec![Value::I32(11)]);
+        assert_eq!(call("b_shared"), vec![Value::I32(11)]);
+
+        call("a_bump_own");
+        call("a_bump_own");
+        assert_eq!(call("a_own"), vec![Value::I32(2)]);
+        assert_eq!(call("b_own"), vec![Value::I32(0)]);
+    }
+
+    #[test]
+    fn call_start_if_present() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (global $g (export "g") (mut i32) (i32.const 0))
+                  (func $init (global.set $g (i32.const 1))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+
+        assert_eq!(
+            runtime.start(&mut store, &mut DebugEnv {}),
+            Err(RuntimeError::NoStartFunction)
+        );
+        assert_eq!(
+            runtime.call_start_if_present(&mut store, &mut DebugEnv {}),
+            Ok(())
+        );
+        assert_eq!(runtime.get_global(&store, "g"), Ok(Value::I32(0)));
+        assert!(runtime.stack.is_empty());
+    }
+
+    #[test]
+    fn multi_value() {
+        struct PairEnv {}
+        impl Env for PairEnv {
+            fn call(
+                &mut self,
+                name: &str,
+                params: Vec<Value>,
+                _: Option<&mut MemInst>,
+            ) -> Result<Vec<Value>, &'static str> {
+                assert_eq!(name, "pair");
+                Ok(vec![params[0], Value::I64(7)])
+            }
+        }
+
+        let wasm = wat2wasm(
+            r#"(module
+                  (import "env" "pair" (func $pair (param i32) (result i32 i64)))
+                  (func $typed (export "typed") (param i32) (result i32 i64)
+                      (local.get 0)
+                      (block (param i32) (result i32 i64)
+                          (i64.const 2))
+                      (block (param i32 i64) (result i32 i64)
+                          (i32.const 0) (i32.const 1) (i64.const 2)
+                          (br_if 0 (local.get 0))
+                          (drop) (drop) (drop)))
+                  (func (export "early") (param i32) (result i32 i64)
+                      (local i32 i64)
+                      (i32.const 9)
+                      (block (result i32 i64)
+                          (i32.const 3) (i64.const 4)
+                          (if (local.get 0)
+                              (then (i32.const 10) (i64.const 11) (return))))
+                      (local.set 2) (local.set 1) (drop)
+                      (local.get 1) (local.get 2))
+                  (func (export "swap") (result i64 i32)
+                      (local i32 i64)
+                      (call $typed (i32.const 0))
+                      (local.set 1) (local.set 0)
+                      (local.get 1) (local.get 0))
+                  (func (export "host") (param i32) (result i32 i64 i32)
+                      (call $pair (local.get 0))
+                      (i32.const 3)))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        let mut env = PairEnv {};
+
+        let cases = [
+            (
+                "typed",
+                vec![Value::I32(0)],
+                vec![Value::I32(0), Value::I64(2)],
+            ),
+            (
+                "typed",
+                vec![Value::I32(5)],
+                vec![Value::I32(1), Value::I64(2)],
+            ),
+            (
+                "early",
+                vec![Value::I32(0)],
+                vec![Value::I32(3), Value::I64(4)],
+            ),
+            (
+                "early",
+                vec![Value::I32(1)],
+                vec![Value::I32(10), Value::I64(11)],
+            ),
+            ("swap", vec![], vec![Value::I64(2), Value::I32(0)]),
+            (
+                "host",
+                vec![Value::I32(4)],
+                vec![Value::I32(4), Value::I64(7), Value::I32(3)],
+            ),
+        ];
+        for (name, params, results) in cases {
+            assert_eq!(
+                runtime.invoke(&mut store, &mut env, name, params),
+                Ok(results),
+                "{}",
+                name
+            );
+        }
+    }
 }
@@ -0,0 +1,59 @@
+// A v128 is kept as its 16 bytes in memory order, so lane 0 of every
+// interpretation occupies the lowest addressed bytes.
+
+pub type V128 = [u8; 16];
+
+macro_rules! impl_lanes {
+    ($t:ty, $n:expr, $to:ident, $from:ident) => {
+        pub fn $to(v: V128) -> [$t; $n] {
+            const SIZE: usize = core::mem::size_of::<$t>();
+            let mut lanes = [<$t>::default(); $n];
+            for (lane, bytes) in lanes.iter_mut().zip(v.chunks_exact(SIZE)) {
+                *lane = <$t>::from_le_bytes(bytes.try_into().unwrap());
+            }
+            lanes
+        }
+
+        pub fn $from(lanes: [$t; $n]) -> V128 {
+            const SIZE: usize = core::mem::size_of::<$t>();
+            let mut v = [0; 16];
+            for (bytes, lane) in v.chunks_exact_mut(SIZE).zip(lanes) {
+                bytes.copy_from_slice(&lane.to_le_bytes());
+            }
+            v
+        }
+    };
+}
+
+impl_lanes!(i8, 16, i8x16, from_i8x16);
+impl_lanes!(i16, 8, i16x8, from_i16x8);
+impl_lanes!(i32, 4, i32x4, from_i32x4);
+impl_lanes!(i64, 2, i64x2, from_i64x2);
+impl_lanes!(f32, 4, f32x4, from_f32x4);
+impl_lanes!(f64, 2, f64x2, from_f64x2);
+
+/// Applies `f` to each pair of corresponding lanes.
+pub fn lanewise<T: Copy, const N: usize>(
+    lhs: [T; N],
+    rhs: [T; N],
+    f: impl Fn(T, T) -> T,
+) -> [T; N] {
+    let mut out = lhs;
+    for (l, r) in out.iter_mut().zip(rhs) {
+        *l = f(*l, r);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lane_order() {
+        let v = from_i32x4([1, 2, 3, 4]);
+        assert_eq!(v[..8], [1, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(i16x8(v), [1, 0, 2, 0, 3, 0, 4, 0]);
+        assert_eq!(i64x2(v), [2 << 32 | 1, 4 << 32 | 3]);
+    }
+}
@@ -3,8 +3,13 @@ use crate::lib::*;
 
 use super::{runtime::Addr, trap::Trap, value::Value};
 
+/// Maximum number of nested calls before `Trap::CallStackExhausted`.
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Label {
+    /// Values a branch here carries: the block's results, or a loop's
+    /// parameters.
     pub n: usize,
     pub stack_offset: usize,
     pub pc: usize,
@@ -18,6 +23,8 @@ pub struct Frame {
     pub local: Vec<Value>,
     pub pc: usize,
     pub stack_offset: usize,
+    /// Labels below this belong to callers.
+    pub label_offset: usize,
 }
 
 #[derive(Debug, PartialEq, Default, Clone)]
@@ -53,9 +60,17 @@ impl Stack {
     }
 
     pub fn values_unwind(&mut self, offset: usize) {
-        while self.values_len() > offset {
-            self.pop_value::<Value>();
-        }
+        self.drop_values(self.values_len().saturating_sub(offset));
+    }
+
+    /// Discards every label above the first `offset`.
+    pub fn labels_unwind(&mut self, offset: usize) {
+        self.labels.truncate(offset);
+    }
+
+    /// Discards the top `n` values, clamping to an empty value stack.
+    pub fn drop_values(&mut self, n: usize) {
+        self.values.truncate(self.values_len().saturating_sub(n));
     }
 
     pub fn values_len(&self) -> usize {
@@ -86,97 +101,109 @@ impl Stack {
         self.frames.push(frame);
     }
 
-    pub fn pop_value<T: From<Value>>(&mut self) -> T {
-        self.values.pop().unwrap().into()
+    pub fn pop_value<T: From<Value>>(&mut self) -> Result<T, Trap> {
+        self.values
+            .pop()
+            .map(Into::into)
+            .ok_or(Trap::StackUnderflow)
     }
 
-    pub fn pop_label(&mut self) -> Label {
-        self.labels.pop().unwrap()
+    pub fn pop_label(&mut self) -> Result<Label, Trap> {
+        self.labels.pop().ok_or(Trap::UndefinedLabel)
     }
 
-    pub fn pop_frame(&mut self) -> Frame {
-        self.frames.pop().unwrap()
+    pub fn pop_frame(&mut self) -> Result<Frame, Trap> {
+        self.frames.pop().ok_or(Trap::NoFrame)
     }
 
     pub fn set_params(&mut self, params: Vec<Value>) {
         self.values = params;
     }
 
-    pub fn get_returns(&mut self) -> Vec<Value> {
-        self.values.drain(..).collect()
+    /// Takes the top `n` values, leaving anything below them in place.
+    pub fn get_returns(&mut self, n: usize) -> Vec<Value> {
+        let at = self.values_len().saturating_sub(n);
+        self.values.split_off(at)
     }
 
-    pub fn th_label(&self, th: usize) -> Label {
-        self.labels[self.labels.len() - 1 - th].clone()
+    /// The label `th` levels out from the innermost one, if there is one.
+    pub fn th_label(&self, th: usize) -> Option<Label> {
+        let i = self.labels.len().checked_sub(th + 1)?;
+        Some(self.labels[i].clone())
     }
 
-    pub fn top_frame(&mut self) -> &Frame {
-        self.frames.last().unwrap()
+    pub fn top_frame(&self) -> Result<&Frame, Trap> {
+        self.frames.last().ok_or(Trap::NoFrame)
     }
 
-    pub fn top_frame_mut(&mut self) -> &mut Frame {
-        self.frames.last_mut().unwrap()
+    pub fn top_frame_mut(&mut self) -> Result<&mut Frame, Trap> {
+        self.frames.last_mut().ok_or(Trap::NoFrame)
     }
 }
 
 impl Stack {
-    pub fn unop<T, F: Fn(T) -> T>(&mut self, func: F)
+    pub fn unop<T, F: Fn(T) -> T>(&mut self, func: F) -> Result<(), Trap>
     where
         T: From<Value> + Into<Value>,
     {
-        let v = self.pop_value::<T>();
+        let v = self.pop_value::<T>()?;
         let r = func(v);
         self.push_value(r);
+        Ok(())
     }
 
-    pub fn binop<T, F: Fn(T, T) -> T>(&mut self, func: F)
+    pub fn binop<T, F: Fn(T, T) -> T>(&mut self, func: F) -> Result<(), Trap>
     where
         T: From<Value> + Into<Value>,
     {
-        let rhs = self.pop_value::<T>();
-        let lhs = self.pop_value::<T>();
+        let rhs = self.pop_value::<T>()?;
+        let lhs = self.pop_value::<T>()?;
         let r = func(lhs, rhs);
         self.push_value(r);
+        Ok(())
     }
 
     pub fn binop_trap<F: Fn(T, T) -> Result<T, Trap>, T>(&mut self, func: F) -> Result<(), Trap>
     where
         T: From<Value> + Into<Value>,
     {
-        let rhs = self.pop_value::<T>();
-        let lhs = self.pop_value::<T>();
+        let rhs = self.pop_value::<T>()?;
+        let lhs = self.pop_value::<T>()?;
         let r = func(lhs, rhs)?;
         self.push_value(r);
         Ok(())
     }
 
-    pub fn relop<F: Fn(T, T) -> i32, T>(&mut self, func: F)
+    pub fn relop<F: Fn(T, T) -> i32, T>(&mut self, func: F) -> Result<(), Trap>
     where
         T: From<Value> + Into<Value>,
     {
-        let rhs = self.pop_value::<T>();
-        let lhs = self.pop_value::<T>();
+        let rhs = self.pop_value::<T>()?;
+        let lhs = self.pop_value::<T>()?;
         let r = func(lhs, rhs);
         self.push_value(r);
+        Ok(())
     }
 
-    pub fn testop<F: Fn(T) -> i32, T>(&mut self, func: F)
+    pub fn testop<F: Fn(T) -> i32, T>(&mut self, func: F) -> Result<(), Trap>
     where
         T: From<Value> + Into<Value>,
     {
-        let v = self.pop_value::<T>();
+        let v = self.pop_value::<T>()?;
         let r = func(v);
         self.push_value(r);
+        Ok(())
     }
 
-    pub fn cvtop<F: Fn(T) -> U, T, U>(&mut self, func: F)
+    pub fn cvtop<F: Fn(T) -> U, T, U>(&mut self, func: F) -> Result<(), Trap>
     where
         T: From<Value> + Into<Value>,
         U: From<Value> + Into<Value>,
     {
-        let t = self.pop_value::<T>();
+        let t = self.pop_value::<T>()?;
         let u = func(t);
         self.push_value(u);
+        Ok(())
     }
 
     pub fn cvtop_trap<F: Fn(T) -> Result<U, Trap>, T, U>(&mut self, func: F) -> Result<(), Trap>
@@ -184,39 +211,38 @@ impl Stack {
         T: From<Value> + Into<Value>,
         U: From<Value> + Into<Value>,
     {
-        let t = self.pop_value::<T>();
+        let t = self.pop_value::<T>()?;
         let u = func(t)?;
         self.push_value(u);
         Ok(())
     }
 
-    pub fn jump(&mut self, l: usize) -> usize {
-        let label = self.th_label(l);
-        if !label.cont {
-            let mut values: Vec<Value> = vec![];
-            for _ in 0..label.n {
-                let v = self.pop_value();
-                values.push(v);
-            }
+    pub fn jump(&mut self, l: usize) -> Result<usize, Trap> {
+        let label = self.th_label(l).ok_or(Trap::UndefinedLabel)?;
+        let mut values: Vec<Value> = vec![];
+        for _ in 0..label.n {
+            let v = self.pop_value()?;
+            values.push(v);
+        }
 
-            self.values_unwind(label.stack_offset);
+        self.values_unwind(label.stack_offset);
 
-            for value in values.into_iter().rev() {
-                self.push_value(value);
-            }
+        for value in values.into_iter().rev() {
+            self.push_value(value);
         }
 
         for _ in 0..(l + 1) {
-            self.pop_label();
+            self.pop_label()?;
         }
 
-        label.pc
+        Ok(label.pc)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::exec::stack::{Frame, Label, Value};
+    use crate::exec::trap::Trap;
 
     use super::Stack;
 
@@ -238,7 +264,7 @@ mod tests {
         stack.push_label(label1);
         stack.push_label(label2);
         assert_eq!(
-            stack.pop_label(),
+            stack.pop_label().unwrap(),
             Label {
                 n: 0,
                 stack_offset: 1,
@@ -247,7 +273,7 @@ mod tests {
             }
         );
         assert_eq!(
-            stack.pop_label(),
+            stack.pop_label().unwrap(),
             Label {
                 n: 0,
                 stack_offset: 0,
@@ -266,6 +292,7 @@ mod tests {
             instance_addr: 0,
             local: vec![],
             stack_offset: 0,
+            label_offset: 0,
             pc: 0,
         };
         let frame2 = Frame {
@@ -273,6 +300,7 @@ mod tests {
             instance_addr: 0,
             local: vec![Value::I32(1), Value::F32(3.0)],
             stack_offset: 0,
+            label_offset: 0,
             pc: 0,
         };
         let mut stack = Stack::new();
@@ -280,25 +308,73 @@ mod tests {
         stack.push_frame(frame2);
 
         assert_eq!(
-            stack.pop_frame(),
+            stack.pop_frame().unwrap(),
             Frame {
                 n: 0,
                 instance_addr: 0,
                 local: vec![Value::I32(1), Value::F32(3.0)],
                 stack_offset: 0,
+                label_offset: 0,
                 pc: 0
             }
         );
         assert_eq!(
-            stack.pop_frame(),
+            stack.pop_frame().unwrap(),
             Frame {
                 n: 0,
                 instance_addr: 0,
                 local: vec![],
                 stack_offset: 0,
+                label_offset: 0,
                 pc: 0
             }
         );
         assert!(stack.is_empty());
     }
+
+    #[test]
+    fn stack_drop_values() {
+        let mut stack = Stack::new();
+        stack.extend_values(vec![Value::I32(1), Value::I32(2), Value::I32(3)]);
+        stack.drop_values(2);
+        assert_eq!(stack.values(), &vec![Value::I32(1)]);
+        stack.drop_values(5);
+        assert!(stack.values().is_empty());
+    }
+
+    #[test]
+    fn jump_out_of_range() {
+        let mut stack = Stack::new();
+        assert_eq!(stack.jump(0), Err(Trap::UndefinedLabel));
+        stack.push_label(Label {
+            n: 0,
+            stack_offset: 0,
+            pc: 10,
+            cont: false,
+        });
+        assert_eq!(stack.jump(1), Err(Trap::UndefinedLabel));
+        assert_eq!(stack.labels_len(), 1);
+        assert_eq!(stack.jump(0), Ok(10));
+        assert_eq!(stack.labels_len(), 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "expected i32, found F64")]
+    fn pop_mismatch() {
+        let mut stack = Stack::new();
+        stack.push_value(Value::F64(1.0));
+        let _ = stack.pop_value::<i32>();
+    }
+
+    #[test]
+    fn pop_empty() {
+        let mut stack = Stack::new();
+        assert_eq!(stack.pop_value::<i32>(), Err(Trap::StackUnderflow));
+        assert_eq!(stack.pop_label(), Err(Trap::UndefinedLabel));
+        assert_eq!(stack.pop_frame(), Err(Trap::NoFrame));
+        assert_eq!(stack.top_frame(), Err(Trap::NoFrame));
+        assert_eq!(stack.top_frame_mut(), Err(Trap::NoFrame));
+        assert_eq!(stack.binop(i32::wrapping_add), Err(Trap::StackUnderflow));
+    }
 }
@@ -1,17 +1,49 @@
-use super::memory::{data_active, data_passiv};
+use super::memory::{data_active, data_passiv, effective_addr};
 use super::runtime::{eval_const, Addr, Runtime, RuntimeError, PAGE_SIZE};
 use super::table::{elem_active, elem_passiv};
-use super::value::{Ref, Value};
+use super::trap::Trap;
+use super::value::{LittleEndian, Ref, Value};
 use crate::binary::FuncType;
 use crate::binary::ValType;
 use crate::binary::{Data, DataMode, Elem, Limits, Memory, Table};
 use crate::binary::{ElemMode, RefType};
-use crate::binary::{Global, GlobalType};
+use crate::binary::{Global, GlobalType, Mut};
 #[cfg(not(feature = "std"))]
 use crate::lib::*;
-use core::fmt::Debug;
+use core::fmt::{Debug, Write};
+use core::iter::{Enumerate, FilterMap};
+use core::slice::{Iter, IterMut};
 use opt_vec::OptVec;
 
+pub type LiveIter<'a, T> =
+    FilterMap<Enumerate<Iter<'a, Option<T>>>, fn((usize, &'a Option<T>)) -> Option<(usize, &'a T)>>;
+
+pub type LiveIterMut<'a, T> = FilterMap<
+    Enumerate<IterMut<'a, Option<T>>>,
+    fn((usize, &'a mut Option<T>)) -> Option<(usize, &'a mut T)>,
+>;
+
+/// Iteration over the live entries of an [`OptVec`] without consuming it.
+pub trait OptVecExt<T> {
+    /// Yields each occupied index with its value, skipping removed slots.
+    fn iter(&self) -> LiveIter<'_, T>;
+    fn iter_mut(&mut self) -> LiveIterMut<'_, T>;
+}
+
+impl<T> OptVecExt<T> for OptVec<T> {
+    fn iter(&self) -> LiveIter<'_, T> {
+        self.into_iter()
+            .enumerate()
+            .filter_map(|(i, v)| v.as_ref().map(|v| (i, v)))
+    }
+
+    fn iter_mut(&mut self) -> LiveIterMut<'_, T> {
+        self.into_iter()
+            .enumerate()
+            .filter_map(|(i, v)| v.as_mut().map(|v| (i, v)))
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum FuncInst {
     InnerFunc {
@@ -23,6 +55,9 @@ pub enum FuncInst {
     HostFunc {
         functype: FuncType,
         name: String,
+        /// Set for imports left unresolved by `Runtime::instantiate_partial`;
+        /// calling one traps instead of reaching the environment.
+        stub: bool,
     },
 }
 
@@ -55,9 +90,36 @@ pub struct ElemInst {
 #[derive(Debug, PartialEq, Clone)]
 pub struct MemInst {
     pub limits: Limits,
+    pub shared: bool,
     pub data: Vec<u8>,
 }
 
+/// Bounds-checked access for host functions. Each fails with
+/// `MemoryOutOfBounds` if any byte lies past the end of memory.
+impl MemInst {
+    pub fn read<T: LittleEndian>(&self, addr: usize) -> Result<T, Trap> {
+        let addr = effective_addr(addr, 0, core::mem::size_of::<T>(), self.data.len())?;
+        Ok(T::read(&self.data, addr))
+    }
+
+    pub fn write<T: LittleEndian>(&mut self, addr: usize, v: T) -> Result<(), Trap> {
+        let addr = effective_addr(addr, 0, core::mem::size_of::<T>(), self.data.len())?;
+        T::write(&mut self.data, addr, v);
+        Ok(())
+    }
+
+    pub fn read_bytes(&self, addr: usize, len: usize) -> Result<&[u8], Trap> {
+        let addr = effective_addr(addr, 0, len, self.data.len())?;
+        Ok(&self.data[addr..addr + len])
+    }
+
+    pub fn write_bytes(&mut self, addr: usize, bytes: &[u8]) -> Result<(), Trap> {
+        let addr = effective_addr(addr, 0, bytes.len(), self.data.len())?;
+        self.data[addr..addr + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct DataInst {
     pub data: Vec<u8>,
@@ -73,6 +135,12 @@ pub struct Store {
     pub datas: OptVec<DataInst>,
 }
 
+impl Default for Store {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Store {
     pub fn new() -> Self {
         Self {
@@ -87,42 +155,61 @@ impl Store {
 
     pub fn update_func_inst(&mut self, funcaddrs: &Vec<Addr>, instance_addr: Addr) {
         for &funcaddr in funcaddrs {
-            match &mut self.funcs[funcaddr as usize] {
-                FuncInst::InnerFunc {
-                    instance_addr: addr,
-                    ..
-                } => {
-                    *addr = instance_addr;
-                }
-                _ => {}
+            if let FuncInst::InnerFunc {
+                instance_addr: addr,
+                ..
+            } = &mut self.funcs[funcaddr]
+            {
+                *addr = instance_addr;
             }
         }
     }
 
-    pub fn allocate_global(&mut self, global: Global) -> Result<Addr, RuntimeError> {
+    pub fn allocate_global(
+        &mut self,
+        global: Global,
+        funcaddrs: &[Addr],
+    ) -> Result<Addr, RuntimeError> {
         Ok(self.globals.push(GlobalInst {
             globaltype: global.type_,
-            value: eval_const(&global.value)?,
+            value: eval_const(&global.value, funcaddrs)?,
         }))
     }
 
-    pub fn allocate_table(&mut self, table: Table) -> Addr {
+    pub fn allocate_table(
+        &mut self,
+        table: Table,
+        funcaddrs: &[Addr],
+    ) -> Result<Addr, RuntimeError> {
         let min = table.limits.min() as usize;
-        self.tables.push(TableInst {
+        let init = match &table.init {
+            Some(expr) => Ref::from(eval_const(expr, funcaddrs)?),
+            None => Ref::Null(table.reftype),
+        };
+        Ok(self.tables.push(TableInst {
             tabletype: table,
-            elem: vec![Ref::Null; min],
-        })
+            elem: vec![init; min],
+        }))
     }
 
-    pub fn allocate_elem(&mut self, elem: Elem) -> Result<Option<Addr>, RuntimeError> {
+    pub fn allocate_elem(
+        &mut self,
+        elem: Elem,
+        funcaddrs: &[Addr],
+    ) -> Result<Option<Addr>, RuntimeError> {
         match &elem.mode {
-            ElemMode::Passiv => Ok(Some(elem_passiv(&mut self.elems, elem)?)),
+            ElemMode::Passiv => Ok(Some(elem_passiv(&mut self.elems, elem, funcaddrs)?)),
             ElemMode::Active { tableidx, offset } => {
-                let offset = match eval_const(&offset)? {
+                let offset = match eval_const(offset, funcaddrs)? {
                     Value::I32(v) => v,
                     _ => unreachable!(),
                 } as usize;
-                elem_active(&mut self.tables[*tableidx as usize], offset, elem)?;
+                elem_active(
+                    &mut self.tables[*tableidx as usize],
+                    offset,
+                    elem,
+                    funcaddrs,
+                )?;
                 Ok(None)
             }
             ElemMode::Declarative => Ok(None),
@@ -130,9 +217,10 @@ impl Store {
     }
 
     pub fn allocate_mem(&mut self, mem: &Memory) -> Addr {
-        let min = mem.0.min() as usize;
+        let min = mem.limits.min() as usize;
         self.mems.push(MemInst {
-            limits: mem.0.clone(),
+            limits: mem.limits.clone(),
+            shared: mem.shared,
             data: vec![0; min * PAGE_SIZE],
         })
     }
@@ -141,11 +229,12 @@ impl Store {
         &mut self,
         memidx: usize,
         data: Data,
+        funcaddrs: &[Addr],
     ) -> Result<Option<Addr>, RuntimeError> {
         match &data.mode {
             DataMode::Passive => Ok(Some(data_passiv(&mut self.datas, data))),
             DataMode::Active { offset, .. } => {
-                let offset = match eval_const(&offset)? {
+                let offset = match eval_const(offset, funcaddrs)? {
                     Value::I32(v) => v,
                     _ => unreachable!(),
                 } as usize;
@@ -155,12 +244,66 @@ impl Store {
         }
     }
 
+    /// Summary of every live entity, one per line under a count for each
+    /// kind, for inspecting the store when debugging.
+    pub fn dump(&self) -> String {
+        fn types(types: &[ValType]) -> String {
+            let types = types.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+            format!("({})", types.join(", "))
+        }
+
+        let mut out = String::new();
+        writeln!(out, "funcs: {}", self.funcs.iter().count()).unwrap();
+        for (addr, func) in self.funcs.iter() {
+            let FuncType(params, results) = func.functype();
+            let kind = match func {
+                FuncInst::InnerFunc { .. } => "func".to_string(),
+                FuncInst::HostFunc { name, .. } => format!("host {}", name),
+            };
+            let (params, results) = (types(&params.0), types(&results.0));
+            writeln!(out, "  [{}] {} {} -> {}", addr, kind, params, results).unwrap();
+        }
+        writeln!(out, "globals: {}", self.globals.iter().count()).unwrap();
+        for (addr, global) in self.globals.iter() {
+            let mut_ = match global.globaltype.mut_ {
+                Mut::Const => "const",
+                Mut::Var => "mut",
+            };
+            let valtype = global.globaltype.valtype;
+            writeln!(
+                out,
+                "  [{}] {} {} = {:?}",
+                addr, mut_, valtype, global.value
+            )
+            .unwrap();
+        }
+        writeln!(out, "tables: {}", self.tables.iter().count()).unwrap();
+        for (addr, table) in self.tables.iter() {
+            let reftype = table.tabletype.reftype;
+            writeln!(out, "  [{}] {} x {}", addr, reftype, table.elem.len()).unwrap();
+        }
+        writeln!(out, "mems: {}", self.mems.iter().count()).unwrap();
+        for (addr, mem) in self.mems.iter() {
+            let pages = mem.data.len() / PAGE_SIZE;
+            writeln!(out, "  [{}] {} pages", addr, pages).unwrap();
+        }
+        writeln!(out, "elems: {}", self.elems.iter().count()).unwrap();
+        for (addr, elem) in self.elems.iter() {
+            writeln!(out, "  [{}] {} x {}", addr, elem.reftype, elem.elem.len()).unwrap();
+        }
+        writeln!(out, "datas: {}", self.datas.iter().count()).unwrap();
+        for (addr, data) in self.datas.iter() {
+            writeln!(out, "  [{}] {} bytes", addr, data.data.len()).unwrap();
+        }
+        out
+    }
+
     pub fn free_runtime(&mut self, runtime: Runtime) {
         for inst in runtime.instances() {
             for faddr in inst.funcaddrs {
                 self.funcs.remove(faddr);
             }
-            for daddr in inst.dataaddrs {
+            for daddr in inst.dataaddrs.into_iter().flatten() {
                 self.datas.remove(daddr);
             }
             for eaddr in inst.elemaddrs {
@@ -178,3 +321,81 @@ impl Store {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{MemInst, OptVecExt};
+    use crate::binary::Limits;
+    use crate::exec::trap::Trap;
+    use crate::test_support::harness;
+    use crate::tests::wat2wasm;
+    use opt_vec::OptVec;
+
+    #[test]
+    fn live_entries() {
+        let mut v = OptVec::new();
+        v.push('a');
+        v.push('b');
+        v.push('c');
+        v.remove(1);
+        assert_eq!(v.iter().collect::<Vec<_>>(), vec![(0, &'a'), (2, &'c')]);
+
+        for (_, c) in v.iter_mut() {
+            *c = c.to_ascii_uppercase();
+        }
+        assert_eq!(v.to_vec(), vec!['A', 'C']);
+    }
+
+    #[test]
+    fn dump() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (import "env" "log" (func (param i32)))
+                  (global (mut i32) (i32.const 7))
+                  (table 3 funcref)
+                  (memory 2)
+                  (func $f (result i32) (i32.const 0))
+                  (func)
+                  (elem func $f)
+                  (data "abc"))"#,
+        )
+        .unwrap();
+        let (_runtime, store, _env) = harness(&wasm);
+        let dump = store.dump();
+        for line in [
+            "funcs: 3",
+            "globals: 1",
+            "tables: 1",
+            "mems: 1",
+            "elems: 1",
+            "datas: 1",
+            "  [0] mut i32 = I32(7)",
+            "  [0] funcref x 3",
+            "  [0] 2 pages",
+            "  [0] 3 bytes",
+        ] {
+            assert!(dump.lines().any(|l| l == line), "{:?} in\n{}", line, dump);
+        }
+    }
+
+    #[test]
+    fn mem_read_write() {
+        let mut mem = MemInst {
+            limits: Limits::Min(0),
+            shared: false,
+            data: vec![0; 8],
+        };
+        assert_eq!(mem.write(4, 0x0403_0201u32), Ok(()));
+        assert_eq!(mem.read::<u32>(4), Ok(0x0403_0201));
+        assert_eq!(mem.read::<u16>(3), Ok(0x0100));
+        assert_eq!(mem.read_bytes(5, 3), Ok(&[2, 3, 4][..]));
+        assert_eq!(mem.write_bytes(6, &[9, 9]), Ok(()));
+        assert_eq!(mem.data, [0, 0, 0, 0, 1, 2, 9, 9]);
+
+        assert_eq!(mem.read::<u32>(5), Err(Trap::MemoryOutOfBounds));
+        assert_eq!(mem.write(usize::MAX, 0u8), Err(Trap::MemoryOutOfBounds));
+        assert_eq!(mem.read_bytes(8, 1), Err(Trap::MemoryOutOfBounds));
+        assert_eq!(mem.write_bytes(7, &[0, 0]), Err(Trap::MemoryOutOfBounds));
+        assert_eq!(mem.read_bytes(8, 0), Ok(&[][..]));
+    }
+}
@@ -19,11 +19,16 @@ pub fn table_get(
 ) -> Result<(), Trap> {
     let a = instance.tableaddrs[*x as usize];
     let tab = &mut store.tables[a];
-    let i = stack.pop_value::<i32>() as usize;
-    if i < tab.elem.len() {
+    let i = stack.pop_value::<i32>()? as usize;
+    if i >= tab.elem.len() {
         return Err(Trap::TableOutOfRange);
     }
-    stack.push_value(Value::Ref(tab.elem[i]));
+    // A null slot reads back as a null of the table's own type.
+    let r = match tab.elem[i] {
+        Ref::Null(_) => Ref::Null(tab.tabletype.reftype),
+        r => r,
+    };
+    stack.push_value(Value::Ref(r));
     Ok(())
 }
 
@@ -35,37 +40,43 @@ pub fn table_set(
 ) -> Result<(), Trap> {
     let a = instance.tableaddrs[*x as usize];
     let tab = &mut store.tables[a];
-    let val = stack.pop_value::<Ref>();
-    let i = stack.pop_value::<i32>() as usize;
-    if i < tab.elem.len() {
+    let val = stack.pop_value::<Ref>()?;
+    let i = stack.pop_value::<i32>()? as usize;
+    if i >= tab.elem.len() {
         return Err(Trap::TableOutOfRange);
     }
     tab.elem[i] = val;
     Ok(())
 }
 
-pub fn table_grow(x: &u32, instance: &mut Instance, store: &mut Store, stack: &mut Stack) {
+pub fn table_grow(
+    x: &u32,
+    instance: &mut Instance,
+    store: &mut Store,
+    stack: &mut Stack,
+) -> Result<(), Trap> {
     let a = instance.tableaddrs[*x as usize];
     let tab = &mut store.tables[a];
     let sz = tab.elem.len() as i32;
     const ERR: i32 = -1;
-    let n = stack.pop_value::<i32>();
-    let init = stack.pop_value::<Ref>();
+    let n = stack.pop_value::<i32>()?;
+    let init = stack.pop_value::<Ref>()?;
     let len = n as u64 + tab.elem.len() as u64;
     if len > u32::MAX as u64 {
         stack.push_value(ERR);
-        return;
+        return Ok(());
     }
     let limits_ = tab.tabletype.limits.set_min(len as u32);
     if !limits_.valid() {
         stack.push_value(ERR);
-        return;
+        return Ok(());
     }
     for _ in tab.tabletype.limits.min()..limits_.min() {
         tab.elem.push(init);
     }
     tab.tabletype.limits = limits_;
     stack.push_value(sz);
+    Ok(())
 }
 
 pub fn table_fill(
@@ -76,9 +87,9 @@ pub fn table_fill(
 ) -> Result<(), Trap> {
     let ta = instance.tableaddrs[*x as usize];
     let tab = &mut store.tables[ta];
-    let n = stack.pop_value::<i32>();
-    let val = stack.pop_value::<Ref>();
-    let i = stack.pop_value::<i32>();
+    let n = stack.pop_value::<i32>()?;
+    let val = stack.pop_value::<Ref>()?;
+    let i = stack.pop_value::<i32>()?;
     if i + n > tab.elem.len() as i32 {
         return Err(Trap::TableOutOfRange);
     }
@@ -99,9 +110,9 @@ pub fn table_copy(
     let tab_x = &store.tables[ta_x];
     let ta_y = instance.tableaddrs[*y as usize];
     let tab_y = &store.tables[ta_y];
-    let n = stack.pop_value::<i32>() as usize;
-    let s = stack.pop_value::<i32>() as usize;
-    let d = stack.pop_value::<i32>() as usize;
+    let n = stack.pop_value::<i32>()? as usize;
+    let s = stack.pop_value::<i32>()? as usize;
+    let d = stack.pop_value::<i32>()? as usize;
     if s + n > tab_y.elem.len() || d + n > tab_x.elem.len() {
         return Err(Trap::TableOutOfRange);
     }
@@ -130,9 +141,9 @@ pub fn table_init(
     let tab = &mut store.tables[ta];
     let ea = instance.elemaddrs[*y as usize];
     let elem = &store.elems[ea];
-    let n = stack.pop_value::<i32>() as usize;
-    let s = stack.pop_value::<i32>() as usize;
-    let d = stack.pop_value::<i32>() as usize;
+    let n = stack.pop_value::<i32>()? as usize;
+    let s = stack.pop_value::<i32>()? as usize;
+    let d = stack.pop_value::<i32>()? as usize;
     if s + n > elem.elem.len() || d + n > elem.elem.len() {
         return Err(Trap::TableOutOfRange);
     }
@@ -143,7 +154,7 @@ pub fn table_init(
     Ok(())
 }
 
-pub fn table_init_manual(tab: &mut TableInst, offset: usize, elems: &Vec<Ref>) {
+pub fn table_init_manual(tab: &mut TableInst, offset: usize, elems: &[Ref]) {
     for (i, elem) in elems.iter().enumerate() {
         tab.elem[i + offset] = *elem;
     }
@@ -161,44 +172,32 @@ pub fn table_size(x: &u32, instance: &mut Instance, store: &mut Store, stack: &m
     stack.push_value(sz);
 }
 
-pub fn elem_passiv(elems: &mut OptVec<ElemInst>, elem: Elem) -> Result<Addr, RuntimeError> {
-    let vals = elem
-        .init
+fn eval_refs(elem: &Elem, funcaddrs: &[Addr]) -> Result<Vec<Ref>, RuntimeError> {
+    elem.init
         .iter()
-        .map(|expr| eval_const(expr))
-        .collect::<Result<Vec<_>, _>>()?;
-    let refs = vals
-        .into_iter()
-        .map(|value| match value {
-            Value::I32(addr) => Ref::Func(addr as Addr),
-            Value::I64(addr) => Ref::Func(addr as Addr),
-            Value::F32(addr) => Ref::Func(addr as Addr),
-            Value::F64(addr) => Ref::Func(addr as Addr),
-            Value::Ref(r) => r,
-        })
-        .collect();
+        .map(|expr| eval_const(expr, funcaddrs).map(Ref::from))
+        .collect()
+}
+
+pub fn elem_passiv(
+    elems: &mut OptVec<ElemInst>,
+    elem: Elem,
+    funcaddrs: &[Addr],
+) -> Result<Addr, RuntimeError> {
+    let refs = eval_refs(&elem, funcaddrs)?;
     Ok(elems.push(ElemInst {
-        reftype: elem.type_.clone(),
+        reftype: elem.type_,
         elem: refs,
     }))
 }
 
-pub fn elem_active(table: &mut TableInst, offset: usize, elem: Elem) -> Result<(), RuntimeError> {
-    let vals = elem
-        .init
-        .iter()
-        .map(|expr| eval_const(expr))
-        .collect::<Result<Vec<_>, _>>()?;
-    let refs = vals
-        .into_iter()
-        .map(|value| match value {
-            Value::I32(addr) => Ref::Func(addr as Addr),
-            Value::I64(addr) => Ref::Func(addr as Addr),
-            Value::F32(addr) => Ref::Func(addr as Addr),
-            Value::F64(addr) => Ref::Func(addr as Addr),
-            Value::Ref(r) => r,
-        })
-        .collect();
+pub fn elem_active(
+    table: &mut TableInst,
+    offset: usize,
+    elem: Elem,
+    funcaddrs: &[Addr],
+) -> Result<(), RuntimeError> {
+    let refs = eval_refs(&elem, funcaddrs)?;
     table_init_manual(table, offset, &refs);
     Ok(())
 }
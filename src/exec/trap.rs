@@ -1,16 +1,74 @@
+#[cfg(not(feature = "std"))]
+use crate::lib::*;
+
+/// Why execution stopped abnormally.
+///
+/// New kinds of traps may be added, so matches outside this crate need a
+/// wildcard arm:
+///
+/// ```
+/// use wasper::Trap;
+///
+/// fn describe(trap: &Trap) -> &'static str {
+///     match trap {
+///         Trap::Unreachable => "unreachable",
+///         Trap::MemoryOutOfBounds => "memory",
+///         _ => "other",
+///     }
+/// }
+/// assert_eq!(describe(&Trap::StackUnderflow), "other");
+/// ```
 #[derive(Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Trap {
+    /// Executed `unreachable`.
     Unreachable,
+    /// `call_indirect` past the end of the table.
     UndefinedElement,
+    /// `call_indirect` through a null slot.
+    UninitializedElement,
+    /// Signed division overflow or a float that does not fit the integer
+    /// type of a truncation.
     IntegerOverflow,
+    /// Truncation of a NaN to an integer.
     InvalidConversionInt,
+    /// Integer division or remainder by zero.
     DivideByZeroInt,
+    /// Table access or bulk table operation out of bounds.
     TableOutOfRange,
-    TableNullRef,
+    /// Memory access or bulk memory operation out of bounds.
     MemoryOutOfBounds,
+    /// `call_indirect` to a function of another type than expected.
     IndirectCallTypeMismatch,
-    NoStartFunction,
+    /// `call_indirect` through an `externref` slot.
     NotFundRef,
+    /// Values other than the results were left on the stack when an
+    /// invocation returned. Only checked in debug builds.
+    StackNotEmpty,
+    /// Calls nested deeper than `stack::MAX_CALL_DEPTH`.
+    CallStackExhausted,
+    /// `global.get` or `global.set` of a global the instance lacks.
+    UndefinedGlobal,
+    /// Call to a function import left unresolved by
+    /// `Runtime::instantiate_partial`, named `module.name`.
+    UnresolvedImport(String),
+    /// An instruction found fewer operands on the stack than it takes.
+    StackUnderflow,
+    /// A branch or `PopLabel` with no matching label on the stack.
+    UndefinedLabel,
+    /// A frame operation with no function frame on the stack.
+    NoFrame,
+    /// A float operation produced a NaN while `Runtime::set_trap_on_nan` is
+    /// on.
+    UnexpectedNaN,
+    /// `memory.atomic.wait` with no timeout on a matching value, which
+    /// would block forever without other threads to notify it.
+    AtomicWait,
+    /// `memory.atomic.wait` on a memory that is not shared.
+    UnsharedAtomicWait,
+    /// An atomic access at an address not aligned to its size.
+    UnalignedAtomic,
+    /// Error reported by the host environment.
     Env(&'static str),
 }
 
@@ -19,15 +77,25 @@ impl core::fmt::Display for Trap {
         match self {
             Trap::Unreachable => write!(f, "unreachable"),
             Trap::UndefinedElement => write!(f, "undefined element"),
+            Trap::UninitializedElement => write!(f, "uninitialized element"),
             Trap::IntegerOverflow => write!(f, "integer overflow"),
             Trap::InvalidConversionInt => write!(f, "invalid conversion to integer"),
             Trap::DivideByZeroInt => write!(f, "integer divide by zero"),
             Trap::TableOutOfRange => write!(f, "failed to refer to table: out of range"),
-            Trap::TableNullRef => write!(f, "failed to refer to table: null reference"),
             Trap::MemoryOutOfBounds => write!(f, "out of bounds memory access"),
             Trap::NotFundRef => write!(f, "attempted to call null or external reference"),
             Trap::IndirectCallTypeMismatch => write!(f, "indirect call type mismatch"),
-            Trap::NoStartFunction => write!(f, "no start function"),
+            Trap::StackNotEmpty => write!(f, "values left on the stack after return"),
+            Trap::CallStackExhausted => write!(f, "call stack exhausted"),
+            Trap::UndefinedGlobal => write!(f, "undefined global"),
+            Trap::UnresolvedImport(name) => write!(f, "unresolved import: {}", name),
+            Trap::StackUnderflow => write!(f, "value stack underflow"),
+            Trap::UndefinedLabel => write!(f, "undefined label"),
+            Trap::NoFrame => write!(f, "no function frame"),
+            Trap::UnexpectedNaN => write!(f, "float operation produced NaN"),
+            Trap::AtomicWait => write!(f, "atomic wait without other threads"),
+            Trap::UnalignedAtomic => write!(f, "unaligned atomic"),
+            Trap::UnsharedAtomicWait => write!(f, "expected shared memory"),
             Trap::Env(env) => write!(f, "environment error: {}", env),
         }
     }
@@ -1,4 +1,8 @@
-use super::runtime::Addr;
+#[cfg(not(feature = "std"))]
+use crate::lib::*;
+
+use super::runtime::{Addr, RuntimeError};
+use crate::binary::{RefType, ValType};
 
 #[derive(Debug, Clone, Copy)]
 pub enum Value {
@@ -6,6 +10,7 @@ pub enum Value {
     I64(i64),
     F32(f32),
     F64(f64),
+    V128([u8; 16]),
     Ref(Ref),
 }
 
@@ -26,6 +31,7 @@ impl PartialEq for Value {
                     || (a.is_infinite() && b.is_infinite())
                     || a == b
             }
+            (Value::V128(a), Value::V128(b)) => a == b,
             (Value::Ref(a), Value::Ref(b)) => a == b,
             _ => false,
         }
@@ -34,90 +40,187 @@ impl PartialEq for Value {
 
 impl Eq for Value {}
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-pub enum Ref {
-    Null,
-    Func(Addr),
-    Extern(Addr),
+macro_rules! impl_wrapping {
+    ($fnname: ident, $op: tt) => {
+        /// `None` unless both operands are numbers of the same type.
+        pub fn $fnname(self, rhs: Value) -> Option<Value> {
+            match (self, rhs) {
+                (Value::I32(a), Value::I32(b)) => Some(Value::I32(a.$fnname(b))),
+                (Value::I64(a), Value::I64(b)) => Some(Value::I64(a.$fnname(b))),
+                (Value::F32(a), Value::F32(b)) => Some(Value::F32(a $op b)),
+                (Value::F64(a), Value::F64(b)) => Some(Value::F64(a $op b)),
+                _ => None,
+            }
+        }
+    };
 }
 
-impl From<Value> for Ref {
-    fn from(value: Value) -> Self {
-        if let Value::Ref(value) = value {
-            value
-        } else {
-            unreachable!()
+impl Value {
+    impl_wrapping!(wrapping_add, +);
+    impl_wrapping!(wrapping_sub, -);
+    impl_wrapping!(wrapping_mul, *);
+
+    /// Signed division. `None` unless both operands are numbers of the same
+    /// type, or if an integer division would trap.
+    pub fn checked_div(self, rhs: Value) -> Option<Value> {
+        match (self, rhs) {
+            (Value::I32(a), Value::I32(b)) => a.checked_div(b).map(Value::I32),
+            (Value::I64(a), Value::I64(b)) => a.checked_div(b).map(Value::I64),
+            (Value::F32(a), Value::F32(b)) => Some(Value::F32(a / b)),
+            (Value::F64(a), Value::F64(b)) => Some(Value::F64(a / b)),
+            _ => None,
         }
     }
-}
 
-impl Into<Value> for Ref {
-    fn into(self) -> Value {
-        Value::Ref(self)
+    pub fn type_of(&self) -> ValType {
+        match self {
+            Value::I32(_) => ValType::I32,
+            Value::I64(_) => ValType::I64,
+            Value::F32(_) => ValType::F32,
+            Value::F64(_) => ValType::F64,
+            Value::V128(_) => ValType::V128,
+            Value::Ref(Ref::Null(RefType::FuncRef) | Ref::Func(_)) => ValType::FuncRef,
+            Value::Ref(Ref::Null(RefType::ExternRef) | Ref::Extern(_)) => ValType::ExternRef,
+        }
     }
-}
 
-impl From<Value> for i32 {
-    fn from(value: Value) -> Self {
-        if let Value::I32(value) = value {
-            value
-        } else {
-            unreachable!()
+    /// Reads a value of type `ty` from exactly as many little-endian bytes
+    /// as it occupies in memory. References have no byte representation.
+    pub fn from_le_bytes(ty: ValType, bytes: &[u8]) -> Result<Value, RuntimeError> {
+        fn read<T: LittleEndian>(bytes: &[u8]) -> Result<T, RuntimeError> {
+            if bytes.len() != core::mem::size_of::<T>() {
+                return Err(RuntimeError::InvalidParams);
+            }
+            Ok(T::read(bytes, 0))
+        }
+        Ok(match ty {
+            ValType::I32 => Value::I32(read(bytes)?),
+            ValType::I64 => Value::I64(read(bytes)?),
+            ValType::F32 => Value::F32(read(bytes)?),
+            ValType::F64 => Value::F64(read(bytes)?),
+            ValType::V128 => Value::V128(read(bytes)?),
+            ValType::FuncRef | ValType::ExternRef => return Err(RuntimeError::InvalidParams),
+        })
+    }
+
+    /// The little-endian bytes of a number or vector, as it would be stored
+    /// to memory. References have no byte representation and give none.
+    pub fn to_le_bytes(&self) -> Vec<u8> {
+        match self {
+            Value::I32(v) => v.to_le_bytes().to_vec(),
+            Value::I64(v) => v.to_le_bytes().to_vec(),
+            Value::F32(v) => v.to_le_bytes().to_vec(),
+            Value::F64(v) => v.to_le_bytes().to_vec(),
+            Value::V128(v) => v.to_vec(),
+            Value::Ref(_) => vec![],
         }
     }
+
+    /// Whether this value can be passed where `valtype` is expected.
+    pub fn is_type(&self, valtype: &ValType) -> bool {
+        matches!(
+            (self, valtype),
+            (Value::I32(_), ValType::I32)
+                | (Value::I64(_), ValType::I64)
+                | (Value::F32(_), ValType::F32)
+                | (Value::F64(_), ValType::F64)
+                | (Value::V128(_), ValType::V128)
+                | (Value::Ref(Ref::Null(RefType::FuncRef)), ValType::FuncRef)
+                | (
+                    Value::Ref(Ref::Null(RefType::ExternRef)),
+                    ValType::ExternRef
+                )
+                | (Value::Ref(Ref::Func(_)), ValType::FuncRef)
+                | (Value::Ref(Ref::Extern(_)), ValType::ExternRef)
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Ref {
+    Null(RefType),
+    Func(Addr),
+    Extern(Addr),
 }
 
-impl Into<Value> for i32 {
-    fn into(self) -> Value {
-        Value::I32(self)
+/// Panics if `found` is not the `expected` variant. Validated code never
+/// does this, so debug builds say what was on the stack to help find the
+/// interpreter bug.
+#[cold]
+fn mismatch(expected: &str, found: &Value) -> ! {
+    #[cfg(debug_assertions)]
+    {
+        let found = match found {
+            Value::I32(_) => "I32",
+            Value::I64(_) => "I64",
+            Value::F32(_) => "F32",
+            Value::F64(_) => "F64",
+            Value::V128(_) => "V128",
+            Value::Ref(_) => "Ref",
+        };
+        panic!("expected {}, found {}", expected, found)
+    }
+    #[cfg(not(debug_assertions))]
+    {
+        let _ = (expected, found);
+        unreachable!()
     }
 }
 
-impl From<Value> for i64 {
-    fn from(value: Value) -> Self {
-        if let Value::I64(value) = value {
-            value
-        } else {
-            unreachable!("{:?}", value)
+macro_rules! impl_from_value {
+    ($t:ty, $variant:ident, $name:literal) => {
+        impl From<Value> for $t {
+            fn from(value: Value) -> Self {
+                if let Value::$variant(value) = value {
+                    value
+                } else {
+                    mismatch($name, &value)
+                }
+            }
         }
+    };
+}
+
+impl_from_value!(Ref, Ref, "ref");
+impl_from_value!(i32, I32, "i32");
+impl_from_value!(i64, I64, "i64");
+impl_from_value!(f32, F32, "f32");
+impl_from_value!(f64, F64, "f64");
+impl_from_value!([u8; 16], V128, "v128");
+
+impl From<Ref> for Value {
+    fn from(val: Ref) -> Self {
+        Value::Ref(val)
     }
 }
 
-impl Into<Value> for i64 {
-    fn into(self) -> Value {
-        Value::I64(self)
+impl From<i32> for Value {
+    fn from(val: i32) -> Self {
+        Value::I32(val)
     }
 }
 
-impl From<Value> for f32 {
-    fn from(value: Value) -> Self {
-        if let Value::F32(value) = value {
-            value
-        } else {
-            unreachable!()
-        }
+impl From<i64> for Value {
+    fn from(val: i64) -> Self {
+        Value::I64(val)
     }
 }
 
-impl Into<Value> for f32 {
-    fn into(self) -> Value {
-        Value::F32(self)
+impl From<f32> for Value {
+    fn from(val: f32) -> Self {
+        Value::F32(val)
     }
 }
 
-impl From<Value> for f64 {
-    fn from(value: Value) -> Self {
-        if let Value::F64(value) = value {
-            value
-        } else {
-            unreachable!()
-        }
+impl From<f64> for Value {
+    fn from(val: f64) -> Self {
+        Value::F64(val)
     }
 }
 
-impl Into<Value> for f64 {
-    fn into(self) -> Value {
-        Value::F64(self)
+impl From<[u8; 16]> for Value {
+    fn from(val: [u8; 16]) -> Self {
+        Value::V128(val)
     }
 }
 
@@ -137,9 +240,7 @@ where
 }
 
 fn write_bytes(buf: &mut [u8], addr: usize, bytes: &[u8]) {
-    for i in 0..bytes.len() {
-        buf[addr + i] = bytes[i];
-    }
+    buf[addr..addr + bytes.len()].copy_from_slice(bytes);
 }
 
 macro_rules! impl_le_rw {
@@ -174,7 +275,17 @@ impl LittleEndian for u8 {
 impl_le_rw!(u16);
 impl_le_rw!(u32);
 
+impl LittleEndian for [u8; 16] {
+    fn read(buf: &[u8], addr: usize) -> Self {
+        read_bytes(buf, addr)
+    }
+    fn write(buf: &mut [u8], addr: usize, v: Self) {
+        write_bytes(buf, addr, &v);
+    }
+}
+
 // Trait to handle f32 and f64 in the same way
+#[allow(dead_code)]
 pub(crate) trait Float: Clone + Copy + PartialEq + PartialOrd {
     type UInt: Copy + core::ops::BitOr<Output = Self::UInt> + core::ops::BitAnd<Output = Self::UInt>;
     const ARITHMETIC_NAN: Self::UInt;
@@ -214,3 +325,61 @@ macro_rules! impl_float {
 
 impl_float!(f32, u32);
 impl_float!(f64, u64);
+
+#[cfg(test)]
+mod tests {
+    use super::{Ref, Value};
+    use crate::binary::{RefType, ValType};
+    use crate::exec::runtime::RuntimeError;
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(
+            Value::I32(5).wrapping_add(Value::I32(3)),
+            Some(Value::I32(8))
+        );
+        assert_eq!(Value::I32(1).wrapping_add(Value::I64(1)), None);
+        assert_eq!(
+            Value::I64(i64::MAX).wrapping_add(Value::I64(1)),
+            Some(Value::I64(i64::MIN))
+        );
+        assert!(matches!(
+            Value::F64(1.5).wrapping_mul(Value::F64(2.0)),
+            Some(Value::F64(v)) if v == 3.0
+        ));
+        assert_eq!(
+            Value::Ref(Ref::Null(RefType::FuncRef)).wrapping_sub(Value::I32(1)),
+            None
+        );
+
+        assert_eq!(
+            Value::I32(-7).checked_div(Value::I32(2)),
+            Some(Value::I32(-3))
+        );
+        assert_eq!(Value::I32(1).checked_div(Value::I32(0)), None);
+        assert_eq!(Value::I64(i64::MIN).checked_div(Value::I64(-1)), None);
+    }
+
+    #[test]
+    fn le_bytes() {
+        let v = Value::I64(-2);
+        let bytes = v.to_le_bytes();
+        assert_eq!(bytes, [0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(Value::from_le_bytes(ValType::I64, &bytes), Ok(v));
+
+        let bytes = Value::F32(1.5).to_le_bytes();
+        assert_eq!(bytes, 1.5f32.to_le_bytes());
+        assert!(matches!(
+            Value::from_le_bytes(ValType::F32, &bytes),
+            Ok(Value::F32(v)) if v == 1.5
+        ));
+
+        assert_eq!(
+            Value::from_le_bytes(ValType::I32, &bytes[..2]),
+            Err(RuntimeError::InvalidParams)
+        );
+        assert!(Value::Ref(Ref::Null(RefType::FuncRef))
+            .to_le_bytes()
+            .is_empty());
+    }
+}
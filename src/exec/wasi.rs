@@ -0,0 +1,531 @@
+use super::env::Env;
+use super::store::MemInst;
+use super::value::Value;
+use crate::binary::ValType;
+use std::io::{self, Read, Write};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+const ERRNO_SUCCESS: i32 = 0;
+const ERRNO_BADF: i32 = 8;
+const ERRNO_FAULT: i32 = 21;
+const ERRNO_INVAL: i32 = 28;
+const ERRNO_IO: i32 = 29;
+const ERRNO_OVERFLOW: i32 = 61;
+
+const CLOCK_REALTIME: i32 = 0;
+const CLOCK_MONOTONIC: i32 = 1;
+
+/// Error `call` returns from `proc_exit`, which stops execution with a
+/// `Trap::Env` trap; the status is then available from
+/// [`WasiEnv::exit_code`].
+pub const PROC_EXIT: &str = "proc_exit";
+
+/// Error `call` returns when the parameters do not match the WASI
+/// signature of the function, as when it was imported with another type.
+const WRONG_SIGNATURE: &str = "wrong signature for WASI function";
+
+type HostFn = fn(&mut WasiEnv, &[Value], &mut MemInst) -> i32;
+
+/// WASI preview1 host functions, imported from `wasi_snapshot_preview1`.
+pub struct WasiEnv {
+    stdin: Box<dyn Read>,
+    stdout: Box<dyn Write>,
+    stderr: Box<dyn Write>,
+    args: Vec<String>,
+    envs: Vec<String>,
+    start: Instant,
+    exit_code: Option<i32>,
+}
+
+impl Default for WasiEnv {
+    fn default() -> Self {
+        Self::new(Box::new(io::stdout()), Box::new(io::stderr()))
+    }
+}
+
+impl WasiEnv {
+    /// Creates an environment whose fd 1 and fd 2 write to the given sinks.
+    /// Fd 0 reads from the process's stdin, and there are no arguments or
+    /// environment variables.
+    pub fn new(stdout: Box<dyn Write>, stderr: Box<dyn Write>) -> Self {
+        Self {
+            stdin: Box::new(io::stdin()),
+            stdout,
+            stderr,
+            args: vec![],
+            envs: vec![],
+            start: Instant::now(),
+            exit_code: None,
+        }
+    }
+
+    pub fn set_stdin(&mut self, stdin: Box<dyn Read>) {
+        self.stdin = stdin;
+    }
+
+    /// Arguments seen by `args_get`, starting with the program name.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    /// Environment variables seen by `environ_get`.
+    pub fn set_envs(&mut self, envs: Vec<(String, String)>) {
+        self.envs = envs
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+    }
+
+    /// Status passed to `proc_exit`, once the guest has called it.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    fn fd_write(&mut self, params: &[Value], memory: &mut MemInst) -> i32 {
+        let fd = i32::from(params[0]);
+        let iovs = ptr(params[1]);
+        let iovs_len = ptr(params[2]);
+        let nwritten = ptr(params[3]);
+
+        let sink = match fd {
+            1 => &mut self.stdout,
+            2 => &mut self.stderr,
+            _ => return ERRNO_BADF,
+        };
+
+        let mut written = 0u32;
+        for i in 0..iovs_len {
+            let bytes = match iovec(memory, iovs, i)
+                .and_then(|(buf, len)| memory.read_bytes(buf, len).ok())
+            {
+                Some(bytes) => bytes,
+                None => return ERRNO_FAULT,
+            };
+            if sink.write_all(bytes).is_err() {
+                return ERRNO_IO;
+            }
+            written = match written.checked_add(bytes.len() as u32) {
+                Some(written) => written,
+                None => return ERRNO_OVERFLOW,
+            };
+        }
+        if sink.flush().is_err() {
+            return ERRNO_IO;
+        }
+        errno(memory.write(nwritten, written).ok())
+    }
+
+    fn fd_read(&mut self, params: &[Value], memory: &mut MemInst) -> i32 {
+        let fd = i32::from(params[0]);
+        let iovs = ptr(params[1]);
+        let iovs_len = ptr(params[2]);
+        let nread = ptr(params[3]);
+
+        if fd != 0 {
+            return ERRNO_BADF;
+        }
+
+        let mut read = 0u32;
+        for i in 0..iovs_len {
+            let (buf, len) = match iovec(memory, iovs, i) {
+                Some(iov) => iov,
+                None => return ERRNO_FAULT,
+            };
+            let bytes = match slice_mut(memory, buf, len) {
+                Some(bytes) => bytes,
+                None => return ERRNO_FAULT,
+            };
+            let n = match self.stdin.read(bytes) {
+                Ok(n) => n,
+                Err(_) => return ERRNO_IO,
+            };
+            read = match read.checked_add(n as u32) {
+                Some(read) => read,
+                None => return ERRNO_OVERFLOW,
+            };
+            // A short read means nothing more is available right now.
+            if n < len {
+                break;
+            }
+        }
+        errno(memory.write(nread, read).ok())
+    }
+
+    fn args_sizes_get(&mut self, params: &[Value], memory: &mut MemInst) -> i32 {
+        strings_sizes_get(&self.args, params, memory)
+    }
+
+    fn args_get(&mut self, params: &[Value], memory: &mut MemInst) -> i32 {
+        strings_get(&self.args, params, memory)
+    }
+
+    fn environ_sizes_get(&mut self, params: &[Value], memory: &mut MemInst) -> i32 {
+        strings_sizes_get(&self.envs, params, memory)
+    }
+
+    fn environ_get(&mut self, params: &[Value], memory: &mut MemInst) -> i32 {
+        strings_get(&self.envs, params, memory)
+    }
+
+    fn clock_time_get(&mut self, params: &[Value], memory: &mut MemInst) -> i32 {
+        let id = i32::from(params[0]);
+        let time = ptr(params[2]);
+        let nanos = match id {
+            CLOCK_REALTIME => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos()),
+            CLOCK_MONOTONIC => self.start.elapsed().as_nanos(),
+            _ => return ERRNO_INVAL,
+        };
+        errno(memory.write(time, nanos as u64).ok())
+    }
+}
+
+/// Writes the number of `strings` and the size of their NUL-terminated
+/// contents, for `args_sizes_get` and `environ_sizes_get`.
+fn strings_sizes_get(strings: &[String], params: &[Value], memory: &mut MemInst) -> i32 {
+    let count = ptr(params[0]);
+    let buf_size = ptr(params[1]);
+    let size: usize = strings.iter().map(|s| s.len() + 1).sum();
+    errno(
+        memory
+            .write(count, strings.len() as u32)
+            .and_then(|_| memory.write(buf_size, size as u32))
+            .ok(),
+    )
+}
+
+/// Writes each of `strings` NUL-terminated into the buffer and a pointer to
+/// it into the array, for `args_get` and `environ_get`.
+fn strings_get(strings: &[String], params: &[Value], memory: &mut MemInst) -> i32 {
+    let mut array = ptr(params[0]);
+    let mut buf = ptr(params[1]);
+    for s in strings {
+        let written = memory
+            .write(array, buf as u32)
+            .and_then(|_| memory.write_bytes(buf, s.as_bytes()))
+            .and_then(|_| memory.write(buf + s.len(), 0u8));
+        if written.is_err() {
+            return ERRNO_FAULT;
+        }
+        array += 4;
+        buf += s.len() + 1;
+    }
+    ERRNO_SUCCESS
+}
+
+/// A guest pointer or length, which is an unsigned 32-bit value.
+fn ptr(value: Value) -> usize {
+    i32::from(value) as u32 as usize
+}
+
+fn errno(result: Option<()>) -> i32 {
+    match result {
+        Some(()) => ERRNO_SUCCESS,
+        None => ERRNO_FAULT,
+    }
+}
+
+/// The part of guest memory at `addr..addr + len`, `None` if any of it is
+/// out of bounds.
+fn slice_mut(memory: &mut MemInst, addr: usize, len: usize) -> Option<&mut [u8]> {
+    memory.data.get_mut(addr..addr.checked_add(len)?)
+}
+
+/// Buffer address and length of the `i`th entry of the iovec array at
+/// `iovs`.
+fn iovec(memory: &MemInst, iovs: usize, i: usize) -> Option<(usize, usize)> {
+    let iov = i.checked_mul(8)?.checked_add(iovs)?;
+    let buf = memory.read::<u32>(iov).ok()? as usize;
+    let len = memory.read::<u32>(iov.checked_add(4)?).ok()? as usize;
+    Some((buf, len))
+}
+
+impl Env for WasiEnv {
+    fn call(
+        &mut self,
+        name: &str,
+        params: Vec<Value>,
+        memory: Option<&mut MemInst>,
+    ) -> Result<Vec<Value>, &'static str> {
+        const I32: ValType = ValType::I32;
+        const I64: ValType = ValType::I64;
+        // `proc_exit` has no pointers and so is handled without `HostFn`.
+        let (f, signature): (Option<HostFn>, &[ValType]) = match name {
+            "fd_write" => (Some(Self::fd_write), &[I32; 4]),
+            "fd_read" => (Some(Self::fd_read), &[I32; 4]),
+            "args_sizes_get" => (Some(Self::args_sizes_get), &[I32; 2]),
+            "args_get" => (Some(Self::args_get), &[I32; 2]),
+            "environ_sizes_get" => (Some(Self::environ_sizes_get), &[I32; 2]),
+            "environ_get" => (Some(Self::environ_get), &[I32; 2]),
+            "clock_time_get" => (Some(Self::clock_time_get), &[I32, I64, I32]),
+            "proc_exit" => (None, &[I32]),
+            _ => return Err("not found"),
+        };
+        if params.len() != signature.len()
+            || !params.iter().zip(signature).all(|(p, t)| p.is_type(t))
+        {
+            return Err(WRONG_SIGNATURE);
+        }
+        let f = match f {
+            Some(f) => f,
+            None => {
+                self.exit_code = Some(i32::from(params[0]));
+                return Err(PROC_EXIT);
+            }
+        };
+        let memory = memory.ok_or("WASI functions require a memory")?;
+        Ok(vec![Value::I32(f(self, &params, memory))])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WasiEnv, PROC_EXIT, WRONG_SIGNATURE};
+    use crate::exec::runtime::{Runtime, RuntimeError};
+    use crate::exec::store::Store;
+    use crate::exec::trap::Trap;
+    use crate::exec::value::Value;
+    use crate::loader::parser::Parser;
+    use crate::tests::wat2wasm;
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct Sink(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn fd_write() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                  (memory 1)
+                  (data (i32.const 0) "\10\00\00\00\05\00\00\00")
+                  (data (i32.const 8) "\20\00\00\00\04\00\00\00")
+                  (data (i32.const 16) "hello")
+                  (data (i32.const 32) "oops")
+                  (func (export "main") (result i32)
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 64)))
+                    (call $fd_write (i32.const 2) (i32.const 8) (i32.const 1) (i32.const 64))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("wasi_snapshot_preview1");
+        runtime.add_module(&mut store, module).unwrap();
+
+        let stdout = Sink::default();
+        let stderr = Sink::default();
+        let mut env = WasiEnv::new(Box::new(stdout.clone()), Box::new(stderr.clone()));
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "main", vec![]),
+            Ok(vec![Value::I32(0)])
+        );
+        assert_eq!(&*stdout.0.borrow(), b"hello");
+        assert_eq!(&*stderr.0.borrow(), b"oops");
+    }
+
+    fn instantiate(wat: &str) -> (Runtime, Store) {
+        let wasm = wat2wasm(wat).unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("wasi_snapshot_preview1");
+        runtime.add_module(&mut store, module).unwrap();
+        (runtime, store)
+    }
+
+    // 65536 iovecs at 0, each naming the same 64 KiB buffer, describe
+    // 4 GiB in total: one byte more than the `u32` count can hold.
+    const OVERFLOWING_IOVS: &str = r#"(module
+          (import "wasi_snapshot_preview1" "fd_write"
+            (func $fd_write (param i32 i32 i32 i32) (result i32)))
+          (import "wasi_snapshot_preview1" "fd_read"
+            (func $fd_read (param i32 i32 i32 i32) (result i32)))
+          (memory 10)
+          (func (export "fill") (local $i i32)
+            (loop $l
+              (i32.store (i32.mul (local.get $i) (i32.const 8)) (i32.const 0x80000))
+              (i32.store offset=4 (i32.mul (local.get $i) (i32.const 8)) (i32.const 0x10000))
+              (br_if $l (i32.lt_u
+                (local.tee $i (i32.add (local.get $i) (i32.const 1)))
+                (i32.const 0x10000)))))
+          (func (export "write") (result i32)
+            (call $fd_write (i32.const 1) (i32.const 0) (i32.const 0x10000) (i32.const 0x90000)))
+          (func (export "read") (result i32)
+            (call $fd_read (i32.const 0) (i32.const 0) (i32.const 0x10000) (i32.const 0x90000))))"#;
+
+    /// Claims to fill every buffer without touching it.
+    struct Endless;
+
+    impl io::Read for Endless {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn iovs_overflow() {
+        let (mut runtime, mut store) = instantiate(OVERFLOWING_IOVS);
+        let mut env = WasiEnv::new(Box::new(io::sink()), Box::new(io::sink()));
+        runtime
+            .invoke(&mut store, &mut env, "fill", vec![])
+            .unwrap();
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "write", vec![]),
+            Ok(vec![Value::I32(61)])
+        );
+        env.set_stdin(Box::new(Endless));
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "read", vec![]),
+            Ok(vec![Value::I32(61)])
+        );
+    }
+
+    #[test]
+    fn args_and_environ() {
+        let (mut runtime, mut store) = instantiate(
+            r#"(module
+                  (import "wasi_snapshot_preview1" "args_sizes_get"
+                    (func $args_sizes_get (param i32 i32) (result i32)))
+                  (import "wasi_snapshot_preview1" "args_get"
+                    (func $args_get (param i32 i32) (result i32)))
+                  (import "wasi_snapshot_preview1" "environ_sizes_get"
+                    (func $environ_sizes_get (param i32 i32) (result i32)))
+                  (import "wasi_snapshot_preview1" "environ_get"
+                    (func $environ_get (param i32 i32) (result i32)))
+                  (memory 1)
+                  (func (export "args") (result i32 i32 i32)
+                    (call $args_sizes_get (i32.const 0) (i32.const 4))
+                    (call $args_get (i32.const 16) (i32.const 32))
+                    (i32.add (i32.load (i32.const 0)) (i32.load (i32.const 4))))
+                  (func (export "environ") (result i32 i32)
+                    (call $environ_sizes_get (i32.const 0) (i32.const 4))
+                    (call $environ_get (i32.const 16) (i32.const 65535))))"#,
+        );
+        let mut env = WasiEnv::new(Box::new(io::sink()), Box::new(io::sink()));
+        env.set_args(vec!["prog".to_string(), "-v".to_string()]);
+        env.set_envs(vec![("A".to_string(), "1".to_string())]);
+
+        // Two arguments taking 5 + 3 bytes.
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "args", vec![]),
+            Ok(vec![Value::I32(0), Value::I32(0), Value::I32(10)])
+        );
+        let memory = &store.mems[0].data;
+        assert_eq!(&memory[16..24], [32, 0, 0, 0, 37, 0, 0, 0]);
+        assert_eq!(&memory[32..40], b"prog\0-v\0");
+
+        // "A=1\0" does not fit at the last byte of memory.
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "environ", vec![]),
+            Ok(vec![Value::I32(0), Value::I32(21)])
+        );
+    }
+
+    #[test]
+    fn fd_read() {
+        let (mut runtime, mut store) = instantiate(
+            r#"(module
+                  (import "wasi_snapshot_preview1" "fd_read"
+                    (func $fd_read (param i32 i32 i32 i32) (result i32)))
+                  (memory 1)
+                  (data (i32.const 0) "\20\00\00\00\03\00\00\00\30\00\00\00\08\00\00\00")
+                  (data (i32.const 16) "\00\00\ff\ff\04\00\00\00")
+                  (func (export "read") (result i32 i32)
+                    (call $fd_read (i32.const 0) (i32.const 0) (i32.const 2) (i32.const 64))
+                    (i32.load (i32.const 64)))
+                  (func (export "fault") (result i32)
+                    (call $fd_read (i32.const 0) (i32.const 16) (i32.const 1) (i32.const 64))))"#,
+        );
+        let mut env = WasiEnv::new(Box::new(io::sink()), Box::new(io::sink()));
+        env.set_stdin(Box::new(io::Cursor::new(b"hello".to_vec())));
+
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "read", vec![]),
+            Ok(vec![Value::I32(0), Value::I32(5)])
+        );
+        let memory = &store.mems[0].data;
+        assert_eq!(&memory[32..35], b"hel");
+        assert_eq!(&memory[48..50], b"lo");
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "fault", vec![]),
+            Ok(vec![Value::I32(21)])
+        );
+    }
+
+    #[test]
+    fn clock_and_exit() {
+        let (mut runtime, mut store) = instantiate(
+            r#"(module
+                  (import "wasi_snapshot_preview1" "clock_time_get"
+                    (func $clock_time_get (param i32 i64 i32) (result i32)))
+                  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+                  (memory 1)
+                  (func (export "now") (result i32 i64)
+                    (call $clock_time_get (i32.const 0) (i64.const 1) (i32.const 8))
+                    (i64.load (i32.const 8)))
+                  (func (export "bad_clock") (result i32)
+                    (call $clock_time_get (i32.const 9) (i64.const 1) (i32.const 8)))
+                  (func (export "_start")
+                    (call $proc_exit (i32.const 3))
+                    unreachable))"#,
+        );
+        let mut env = WasiEnv::new(Box::new(io::sink()), Box::new(io::sink()));
+
+        match &runtime.invoke(&mut store, &mut env, "now", vec![]).unwrap()[..] {
+            [Value::I32(0), Value::I64(nanos)] => assert!(*nanos > 0),
+            results => panic!("{:?}", results),
+        }
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "bad_clock", vec![]),
+            Ok(vec![Value::I32(28)])
+        );
+        assert!(matches!(
+            runtime.invoke(&mut store, &mut env, "_start", vec![]),
+            Err(RuntimeError::Trap {
+                trap: Trap::Env(PROC_EXIT),
+                ..
+            })
+        ));
+        assert_eq!(env.exit_code(), Some(3));
+    }
+
+    #[test]
+    fn wrong_signature() {
+        let (mut runtime, mut store) = instantiate(
+            r#"(module
+                  (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32) (result i32)))
+                  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i64)))
+                  (memory 1)
+                  (func (export "write") (result i32)
+                    (call $fd_write (i32.const 1)))
+                  (func (export "exit")
+                    (call $proc_exit (i64.const 3))))"#,
+        );
+        let mut env = WasiEnv::new(Box::new(io::sink()), Box::new(io::sink()));
+
+        for name in ["write", "exit"] {
+            assert!(matches!(
+                runtime.invoke(&mut store, &mut env, name, vec![]),
+                Err(RuntimeError::Trap {
+                    trap: Trap::Env(WRONG_SIGNATURE),
+                    ..
+                })
+            ));
+        }
+        assert_eq!(env.exit_code(), None);
+    }
+}
@@ -1,3 +1,36 @@
+//! wasper is a WebAssembly interpreter written in Rust without standard library.
+//!
+//! ```
+//! use wasper::{parse, Env, MemInst, Runtime, Store, Value};
+//!
+//! struct NoEnv;
+//! impl Env for NoEnv {
+//!     fn call(
+//!         &mut self,
+//!         _name: &str,
+//!         _params: Vec<Value>,
+//!         _memory: Option<&mut MemInst>,
+//!     ) -> Result<Vec<Value>, &'static str> {
+//!         Err("not found")
+//!     }
+//! }
+//!
+//! let wasm = wat::parse_str(
+//!     r#"(module
+//!          (func (export "add") (param i32 i32) (result i32)
+//!            local.get 0
+//!            local.get 1
+//!            i32.add))"#,
+//! )
+//! .unwrap();
+//! let module = parse(&wasm).unwrap();
+//!
+//! let mut store = Store::new();
+//! let mut runtime = Runtime::new("env");
+//! runtime.add_module(&mut store, module).unwrap();
+//! let result = runtime.invoke(&mut store, &mut NoEnv, "add", vec![Value::I32(1), Value::I32(2)]);
+//! assert_eq!(result, Ok(vec![Value::I32(3)]));
+//! ```
 #![cfg_attr(not(feature = "std"), no_std)]
 
 #[cfg(feature = "alloc")]
@@ -32,6 +65,18 @@ pub mod lib {
 pub mod binary;
 pub mod exec;
 pub mod loader;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_support;
+
+pub use binary::Module;
+pub use exec::compiled::CompiledModule;
+pub use exec::env::Env;
+pub use exec::importer::Importer;
+pub use exec::runtime::{Runtime, PAGE_SIZE};
+pub use exec::store::{MemInst, Store};
+pub use exec::trap::Trap;
+pub use exec::value::Value;
+pub use loader::parse;
 
 #[cfg(test)]
 mod tests {
@@ -40,4 +85,9 @@ mod tests {
     pub fn wat2wasm<T: AsRef<str>>(wat: T) -> Result<Vec<u8>> {
         parse_str(wat.as_ref())
     }
+
+    #[test]
+    fn page_size() {
+        assert_eq!(crate::PAGE_SIZE, 65536);
+    }
 }
@@ -0,0 +1,88 @@
+//! The `dylink.0` custom section carried by Emscripten side modules.
+
+#[cfg(not(feature = "std"))]
+use crate::lib::*;
+
+use super::{error::Error, parser::Parser};
+
+pub const SECTION_NAME: &str = "dylink.0";
+
+const MEM_INFO: u8 = 1;
+const NEEDED: u8 = 2;
+
+/// Memory and table requirements and library dependencies of a side module.
+/// Alignments are powers of two, stored as their exponent.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct DylinkInfo {
+    pub memory_size: u32,
+    pub memory_align: u32,
+    pub table_size: u32,
+    pub table_align: u32,
+    pub needed: Vec<String>,
+}
+
+/// Parses the payload of a `dylink.0` section, ignoring subsections other
+/// than memory info and needed libraries.
+pub fn parse(bytes: &[u8]) -> Result<DylinkInfo, Error> {
+    let mut parser = Parser::new(bytes);
+    let mut info = DylinkInfo::default();
+    while let Some(id) = parser.next() {
+        let size = parser.u32()? as usize;
+        let body = parser
+            .rest()
+            .get(..size)
+            .ok_or(Error::UnexpectedEof("dylink subsection".into()))?;
+        parser.skip(size);
+
+        let mut sub = Parser::new(body);
+        match id {
+            MEM_INFO => {
+                info.memory_size = sub.u32()?;
+                info.memory_align = sub.u32()?;
+                info.table_size = sub.u32()?;
+                info.table_align = sub.u32()?;
+            }
+            NEEDED => info.needed = sub.vec(Parser::name)?,
+            _ => {}
+        }
+    }
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, DylinkInfo, SECTION_NAME};
+    use crate::loader::parser::Parser;
+    use crate::tests::wat2wasm;
+
+    #[test]
+    fn side_module() {
+        // mem_info: 1024 bytes aligned to 2^4, 2 table slots aligned to 2^0;
+        // needed: "libc.so"; then an export-info subsection to skip.
+        let wasm = wat2wasm(
+            r#"(module
+                  (@custom "dylink.0" (before first)
+                    "\01\05\80\08\04\02\00"
+                    "\02\09\01\07libc.so"
+                    "\03\01\00")
+                  (func))"#,
+        )
+        .unwrap();
+        let (_, customs) = Parser::new(&wasm).module_with_customs().unwrap();
+        let dylink = customs
+            .sec1
+            .iter()
+            .find(|custom| custom.name == SECTION_NAME)
+            .unwrap();
+        assert_eq!(
+            parse(&dylink.bytes),
+            Ok(DylinkInfo {
+                memory_size: 1024,
+                memory_align: 4,
+                table_size: 2,
+                table_align: 0,
+                needed: vec!["libc.so".into()],
+            })
+        );
+    }
+}
@@ -12,6 +12,104 @@ pub enum Error {
     InvalidUtf8(Utf8Error),
     UnexpectedEof(String),
     Expected(String),
+    FuncCodeCountMismatch { funcs: usize, codes: usize },
+    DataCountMismatch { count: usize, datas: usize },
+    TooManyLocals,
+    LengthExceedsInput,
+    UndeclaredFuncRef(u32),
+    UnknownIndex { space: IndexSpace, idx: u32 },
+    TableTypeMismatch { dst: u32, src: u32 },
+    TypeMismatch(String),
+    TrailingBytes(usize),
     Other(String),
     Or(Box<Error>, Box<Error>),
 }
+
+/// The index spaces a module refers into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexSpace {
+    Type,
+    Func,
+    Table,
+    Mem,
+    Global,
+    Local,
+    Label,
+}
+
+impl core::fmt::Display for IndexSpace {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let name = match self {
+            IndexSpace::Type => "type",
+            IndexSpace::Func => "function",
+            IndexSpace::Table => "table",
+            IndexSpace::Mem => "memory",
+            IndexSpace::Global => "global",
+            IndexSpace::Local => "local",
+            IndexSpace::Label => "label",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Error {
+    /// The leaves of a tree of `Or` errors, left to right. Any other error
+    /// is its own single alternative.
+    pub fn flatten_alternatives(&self) -> Vec<&Error> {
+        match self {
+            Error::Or(a, b) => {
+                let mut alternatives = a.flatten_alternatives();
+                alternatives.extend(b.flatten_alternatives());
+                alternatives
+            }
+            err => vec![err],
+        }
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::InvalidMagicNumber => write!(f, "invalid magic number"),
+            Error::InvalidVersion => write!(f, "invalid version"),
+            Error::InvalidSectionHeader => write!(f, "invalid section header"),
+            Error::IntOverflow(ty) => write!(f, "integer overflow reading {:?}", ty),
+            Error::InvalidUtf8(err) => write!(f, "invalid utf-8: {}", err),
+            Error::UnexpectedEof(what) => write!(f, "unexpected end of input: {}", what),
+            Error::Expected(what) => write!(f, "expected {}", what),
+            Error::FuncCodeCountMismatch { funcs, codes } => write!(
+                f,
+                "{} function declarations but {} code entries",
+                funcs, codes
+            ),
+            Error::DataCountMismatch { count, datas } => {
+                write!(f, "data count {} but {} data segments", count, datas)
+            }
+            Error::TooManyLocals => write!(f, "too many locals"),
+            Error::LengthExceedsInput => write!(f, "length exceeds input"),
+            Error::UndeclaredFuncRef(idx) => write!(f, "undeclared function reference {}", idx),
+            Error::UnknownIndex { space, idx } => write!(f, "unknown {} {}", space, idx),
+            Error::TableTypeMismatch { dst, src } => write!(
+                f,
+                "table.copy from table {} into table {} of another type",
+                src, dst
+            ),
+            Error::TypeMismatch(msg) => write!(f, "type mismatch: {}", msg),
+            Error::TrailingBytes(n) => write!(f, "{} trailing bytes after module", n),
+            Error::Other(msg) => write!(f, "{}", msg),
+            Error::Or(..) => {
+                write!(f, "expected one of: ")?;
+                for (i, err) in self.flatten_alternatives().into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    match err {
+                        Error::Expected(what) => write!(f, "{}", what)?,
+                        err => write!(f, "{}", err)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -66,9 +66,9 @@ pub fn read_64(bytes: &[u8], signed: bool) -> Result<(u64, usize), Error> {
         }
     }
 
-    Err(Error::UnexpectedEof(format!(
-        "part of LEB128-encoded integer"
-    )))
+    Err(Error::UnexpectedEof(
+        "part of LEB128-encoded integer".into(),
+    ))
 }
 
 pub fn read_32(bytes: &[u8], signed: bool) -> Result<(u32, usize), Error> {
@@ -98,9 +98,9 @@ pub fn read_32(bytes: &[u8], signed: bool) -> Result<(u32, usize), Error> {
         }
     }
 
-    Err(Error::UnexpectedEof(format!(
-        "part of LEB128-encoded integer"
-    )))
+    Err(Error::UnexpectedEof(
+        "part of LEB128-encoded integer".into(),
+    ))
 }
 
 #[cfg(test)]
@@ -194,7 +194,7 @@ mod tests {
 
     #[test]
     fn u32_value_ok() {
-        for (input, expected) in vec![
+        for (input, expected) in [
             (vec![0x00], 0),
             (vec![0x80, 0x01], 128),
             (vec![0xc0, 0xc4, 0x07], 123456),
@@ -220,7 +220,7 @@ mod tests {
 
     #[test]
     fn u64_value_ok() {
-        for (input, expected) in vec![
+        for (input, expected) in [
             (vec![0x00], 0),
             (vec![0x80, 0x01], 128),
             (vec![0xc0, 0xc4, 0x07], 123456),
@@ -276,6 +276,19 @@ mod tests {
         assert_eq!(u64::read_leb128(&b), Err(Error::IntOverflow(Type::U64)));
     }
 
+    #[test]
+    fn sign_bits_error() {
+        // The unused bits of the final byte must all equal the sign bit.
+        let b = [0xff, 0xff, 0xff, 0xff, 0x4f];
+        assert_eq!(i32::read_leb128(&b), Err(Error::IntOverflow(Type::I32)));
+        let b = [0x80, 0x80, 0x80, 0x80, 0x70];
+        assert_eq!(i32::read_leb128(&b), Err(Error::IntOverflow(Type::I32)));
+        let b = [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x40];
+        assert_eq!(i64::read_leb128(&b), Err(Error::IntOverflow(Type::I64)));
+        let b = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x3f];
+        assert_eq!(i64::read_leb128(&b), Err(Error::IntOverflow(Type::I64)));
+    }
+
     #[test]
     fn unexpected_eof() {
         let b = [0xc0, 0xc4];
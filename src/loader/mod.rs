@@ -1,10 +1,13 @@
+pub mod dylink;
 pub mod error;
 pub mod instructions;
 pub mod leb128;
 pub mod module;
+pub mod names;
 pub mod parser;
 pub mod sections;
 pub mod types;
+pub mod validate;
 pub mod values;
 
 use super::binary::Module;
@@ -13,5 +16,7 @@ use parser::Parser;
 
 pub fn parse(input: &[u8]) -> Result<Module, Error> {
     let mut parser = Parser::new(input);
-    parser.module()
+    let module = parser.module()?;
+    validate::validate(&module)?;
+    Ok(module)
 }
@@ -3,59 +3,67 @@ use crate::lib::*;
 
 use crate::binary::*;
 
-use super::{error::Error, parser::Parser};
+use super::{error::Error, names, parser::Parser};
+
+/// Upper bound on the number of locals a single function may declare.
+pub const MAX_LOCALS: u64 = 50000;
+
+fn expand_locals(locals: Vec<Local>) -> Result<Vec<ValType>, Error> {
+    let total: u64 = locals.iter().map(|local| local.n as u64).sum();
+    if total > MAX_LOCALS {
+        return Err(Error::TooManyLocals);
+    }
+    Ok(locals
+        .into_iter()
+        .flat_map(|local| vec![local.type_; local.n as usize])
+        .collect())
+}
+
+/// Function names from a trailing `name` section. A malformed one is
+/// ignored, since custom sections never make a module invalid.
+fn func_names<'c>(customs: impl IntoIterator<Item = &'c Custom>) -> Vec<(FuncIdx, String)> {
+    customs
+        .into_iter()
+        .find(|custom| custom.name == names::SECTION_NAME)
+        .and_then(|custom| names::func_names(&custom.bytes).ok())
+        .unwrap_or_default()
+}
 
 impl<'a> Parser<'a> {
     pub fn typeidx(&mut self) -> Result<TypeIdx, Error> {
-        Ok(self
-            .u32()
-            .map_err(|_| Error::Expected(format!("typeidx")))?)
+        self.u32().map_err(|_| Error::Expected("typeidx".into()))
     }
 
     pub fn funcidx(&mut self) -> Result<TypeIdx, Error> {
-        Ok(self
-            .u32()
-            .map_err(|_| Error::Expected(format!("funcidx")))?)
+        self.u32().map_err(|_| Error::Expected("funcidx".into()))
     }
 
     pub fn tableidx(&mut self) -> Result<TypeIdx, Error> {
-        Ok(self
-            .u32()
-            .map_err(|_| Error::Expected(format!("tableidx")))?)
+        self.u32().map_err(|_| Error::Expected("tableidx".into()))
     }
 
     pub fn memidx(&mut self) -> Result<TypeIdx, Error> {
-        Ok(self.u32().map_err(|_| Error::Expected(format!("memidx")))?)
+        self.u32().map_err(|_| Error::Expected("memidx".into()))
     }
 
     pub fn globalidx(&mut self) -> Result<TypeIdx, Error> {
-        Ok(self
-            .u32()
-            .map_err(|_| Error::Expected(format!("globalidx")))?)
+        self.u32().map_err(|_| Error::Expected("globalidx".into()))
     }
 
     pub fn elemidx(&mut self) -> Result<TypeIdx, Error> {
-        Ok(self
-            .u32()
-            .map_err(|_| Error::Expected(format!("elemidx")))?)
+        self.u32().map_err(|_| Error::Expected("elemidx".into()))
     }
 
     pub fn dataidx(&mut self) -> Result<TypeIdx, Error> {
-        Ok(self
-            .u32()
-            .map_err(|_| Error::Expected(format!("dataidx")))?)
+        self.u32().map_err(|_| Error::Expected("dataidx".into()))
     }
 
     pub fn localidx(&mut self) -> Result<TypeIdx, Error> {
-        Ok(self
-            .u32()
-            .map_err(|_| Error::Expected(format!("localidx")))?)
+        self.u32().map_err(|_| Error::Expected("localidx".into()))
     }
 
     pub fn labelidx(&mut self) -> Result<TypeIdx, Error> {
-        Ok(self
-            .u32()
-            .map_err(|_| Error::Expected(format!("labelidx")))?)
+        self.u32().map_err(|_| Error::Expected("labelidx".into()))
     }
 
     pub fn custom_sections(&mut self) -> Vec<Custom> {
@@ -80,50 +88,67 @@ impl<'a> Parser<'a> {
     }
 
     pub fn module(&mut self) -> Result<Module, Error> {
+        let mut module = Module::default();
+        self.module_into(&mut module)?;
+        Ok(module)
+    }
+
+    /// Parses a module into `module`, appending each section straight to
+    /// its vectors so that their capacity is reused. On error `module` is
+    /// left partly overwritten.
+    pub fn module_into(&mut self, module: &mut Module) -> Result<(), Error> {
+        self.take_code_ranges();
         // magic
         self.magic()?;
         // version
-        let version = self.version()?;
+        module.version = self.version()?;
         self.ignore_custom_sections();
 
         // types
-        let types = self.many0(Self::typesec).into_iter().flatten().collect();
+        module.types.clear();
+        self.sections_into(1, &mut module.types, Self::functype)?;
         self.ignore_custom_sections();
 
         // imports
-        let imports = self.many0(Self::importsec).into_iter().flatten().collect();
+        module.imports.clear();
+        self.sections_into(2, &mut module.imports, Self::import)?;
         self.ignore_custom_sections();
 
         // funcs 1
-        let funcs = self
-            .many0(Self::funcsec)
+        let typeidxs = self
+            .sections(3, Self::funcsec)?
             .into_iter()
             .flatten()
             .collect::<Vec<_>>();
         self.ignore_custom_sections();
 
         // tables
-        let tables = self.many0(Self::tablesec).into_iter().flatten().collect();
+        module.tables.clear();
+        self.sections_into(4, &mut module.tables, Self::table_entry)?;
         self.ignore_custom_sections();
 
         // mems
-        let mems = self.many0(Self::memsec).into_iter().flatten().collect();
+        module.mems.clear();
+        self.sections_into(5, &mut module.mems, Self::memory)?;
         self.ignore_custom_sections();
 
         // globals
-        let globals = self.many0(Self::globalsec).into_iter().flatten().collect();
+        module.globals.clear();
+        self.sections_into(6, &mut module.globals, Self::global)?;
         self.ignore_custom_sections();
 
         // exports
-        let exports = self.many0(Self::exportsec).into_iter().flatten().collect();
+        module.exports.clear();
+        self.sections_into(7, &mut module.exports, Self::export)?;
         self.ignore_custom_sections();
 
         // start
-        let start = self.startsec()?.map(|s| s.value);
+        module.start = self.startsec()?.map(|s| s.value);
         self.ignore_custom_sections();
 
         // elems
-        let elems = self.many0(Self::elemsec).into_iter().flatten().collect();
+        module.elems.clear();
+        self.sections_into(9, &mut module.elems, Self::elem)?;
         self.ignore_custom_sections();
 
         // datacount
@@ -132,64 +157,132 @@ impl<'a> Parser<'a> {
 
         // funcs 2
         let codes = self
-            .many0(Self::codesec)
+            .sections(10, Self::codesec)?
             .into_iter()
             .flatten()
             .collect::<Vec<_>>();
-        self.ignore_custom_sections();
+        // The name section follows the code section, or the data section
+        // if there is one.
+        let mut trailing = self.custom_sections();
 
         // funcs validation
-        if funcs.len() != codes.len() {
-            return Err(Error::Other(format!("functypes length != codes length")));
+        if typeidxs.len() != codes.len() {
+            return Err(Error::FuncCodeCountMismatch {
+                funcs: typeidxs.len(),
+                codes: codes.len(),
+            });
         }
 
-        let funcs = funcs
-            .into_iter()
-            .zip(codes.into_iter())
-            .map(|(typeidx, code)| Func {
+        module.funcs.clear();
+        for (typeidx, code) in typeidxs.into_iter().zip(codes) {
+            module.funcs.push(Func {
                 typeidx,
-                locals: code
-                    .func
-                    .locals
-                    .into_iter()
-                    .map(|local| vec![local.type_; local.n as usize])
-                    .flatten()
-                    .collect(),
+                locals: expand_locals(code.func.locals)?,
                 body: code.func.body,
-            })
-            .collect();
+            });
+        }
 
         // data
-        let data = self
-            .many0(Self::datasec)
-            .into_iter()
-            .flatten()
-            .collect::<Vec<_>>();
-        self.ignore_custom_sections();
+        module.datas.clear();
+        self.sections_into(11, &mut module.datas, Self::data)?;
+        trailing.extend(self.custom_sections());
+        module.func_names = func_names(&trailing);
+        self.end()?;
 
         // data validation
         if let Some(count) = data_count {
-            if count as usize != data.len() {
-                return Err(Error::Other(format!("datacount != data length")));
+            if count as usize != module.datas.len() {
+                return Err(Error::DataCountMismatch {
+                    count: count as usize,
+                    datas: module.datas.len(),
+                });
             }
         }
 
-        Ok(Module {
+        module.code_ranges.clear();
+        module.code_ranges.extend(self.take_code_ranges());
+        Ok(())
+    }
+
+    /// Parses magic, version, types, imports and exports, skipping the
+    /// sections in between and stopping before the code section.
+    pub fn module_header(&mut self) -> Result<ModuleHeader, Error> {
+        // magic
+        self.magic()?;
+        // version
+        let version = self.version()?;
+        self.ignore_custom_sections();
+
+        // types
+        let types = self
+            .sections(1, Self::typesec)?
+            .into_iter()
+            .flatten()
+            .collect();
+        self.ignore_custom_sections();
+
+        // imports
+        let imports = self
+            .sections(2, Self::importsec)?
+            .into_iter()
+            .flatten()
+            .collect();
+        self.ignore_custom_sections();
+
+        // funcs, tables, mems, globals
+        while let Some(3..=6) = self.peek() {
+            self.skip_section()?;
+            self.ignore_custom_sections();
+        }
+
+        // exports
+        let exports = self
+            .sections(7, Self::exportsec)?
+            .into_iter()
+            .flatten()
+            .collect();
+
+        Ok(ModuleHeader {
             version,
             types,
-            funcs,
-            tables,
-            mems,
-            globals,
-            elems,
-            datas: data,
-            start,
             imports,
             exports,
         })
     }
 
+    /// Skips whole sections until one with `id`, returning its size and body
+    /// and leaving the parser just past it. Returns `None` if the input ends
+    /// first.
+    pub fn skip_to_section(&mut self, id: u8) -> Result<Option<(u32, &'a [u8])>, Error> {
+        while let Some(next) = self.peek() {
+            if next != id {
+                self.skip_section()?;
+                continue;
+            }
+            self.next();
+            let size = self.u32()?;
+            let body = self
+                .rest()
+                .get(..size as usize)
+                .ok_or(Error::UnexpectedEof("section".into()))?;
+            self.skip(size as usize);
+            return Ok(Some((size, body)));
+        }
+        Ok(None)
+    }
+
+    fn skip_section(&mut self) -> Result<(), Error> {
+        self.next();
+        let size = self.u32()? as usize;
+        if size > self.rest().len() {
+            return Err(Error::UnexpectedEof("section".into()));
+        }
+        self.skip(size);
+        Ok(())
+    }
+
     pub fn module_with_customs(&mut self) -> Result<(Module, CustomSecList), Error> {
+        self.take_code_ranges();
         // magic
         self.magic()?;
         // version
@@ -197,35 +290,59 @@ impl<'a> Parser<'a> {
         let sec1 = self.custom_sections();
 
         // types
-        let types = self.many0(Self::typesec).into_iter().flatten().collect();
+        let types = self
+            .sections(1, Self::typesec)?
+            .into_iter()
+            .flatten()
+            .collect();
         let sec2 = self.custom_sections();
 
         // imports
-        let imports = self.many0(Self::importsec).into_iter().flatten().collect();
+        let imports = self
+            .sections(2, Self::importsec)?
+            .into_iter()
+            .flatten()
+            .collect();
         let sec3 = self.custom_sections();
 
         // funcs 1
         let funcs = self
-            .many0(Self::funcsec)
+            .sections(3, Self::funcsec)?
             .into_iter()
             .flatten()
             .collect::<Vec<_>>();
         let sec4 = self.custom_sections();
 
         // tables
-        let tables = self.many0(Self::tablesec).into_iter().flatten().collect();
+        let tables = self
+            .sections(4, Self::tablesec)?
+            .into_iter()
+            .flatten()
+            .collect();
         let sec5 = self.custom_sections();
 
         // mems
-        let mems = self.many0(Self::memsec).into_iter().flatten().collect();
+        let mems = self
+            .sections(5, Self::memsec)?
+            .into_iter()
+            .flatten()
+            .collect();
         let sec6 = self.custom_sections();
 
         // globals
-        let globals = self.many0(Self::globalsec).into_iter().flatten().collect();
+        let globals = self
+            .sections(6, Self::globalsec)?
+            .into_iter()
+            .flatten()
+            .collect();
         let sec7 = self.custom_sections();
 
         // exports
-        let exports = self.many0(Self::exportsec).into_iter().flatten().collect();
+        let exports = self
+            .sections(7, Self::exportsec)?
+            .into_iter()
+            .flatten()
+            .collect();
         let sec8 = self.custom_sections();
 
         // start
@@ -233,7 +350,11 @@ impl<'a> Parser<'a> {
         let sec9 = self.custom_sections();
 
         // elems
-        let elems = self.many0(Self::elemsec).into_iter().flatten().collect();
+        let elems = self
+            .sections(9, Self::elemsec)?
+            .into_iter()
+            .flatten()
+            .collect();
         let sec10 = self.custom_sections();
 
         // datacount
@@ -242,7 +363,7 @@ impl<'a> Parser<'a> {
 
         // funcs 2
         let codes = self
-            .many0(Self::codesec)
+            .sections(10, Self::codesec)?
             .into_iter()
             .flatten()
             .collect::<Vec<_>>();
@@ -250,37 +371,41 @@ impl<'a> Parser<'a> {
 
         // funcs validation
         if funcs.len() != codes.len() {
-            return Err(Error::Other(format!("functypes length != codes length")));
+            return Err(Error::FuncCodeCountMismatch {
+                funcs: funcs.len(),
+                codes: codes.len(),
+            });
         }
 
         let funcs = funcs
             .into_iter()
-            .zip(codes.into_iter())
-            .map(|(typeidx, code)| Func {
-                typeidx,
-                locals: code
-                    .func
-                    .locals
-                    .into_iter()
-                    .map(|local| vec![local.type_; local.n as usize])
-                    .flatten()
-                    .collect(),
-                body: code.func.body,
+            .zip(codes)
+            .map(|(typeidx, code)| {
+                Ok(Func {
+                    typeidx,
+                    locals: expand_locals(code.func.locals)?,
+                    body: code.func.body,
+                })
             })
-            .collect();
+            .collect::<Result<_, Error>>()?;
 
         // data
         let data = self
-            .many0(Self::datasec)
+            .sections(11, Self::datasec)?
             .into_iter()
             .flatten()
             .collect::<Vec<_>>();
         let sec13 = self.custom_sections();
+        let func_names = func_names(sec12.iter().chain(&sec13));
+        self.end()?;
 
         // data validation
         if let Some(count) = data_count {
             if count as usize != data.len() {
-                return Err(Error::Other(format!("datacount != data length")));
+                return Err(Error::DataCountMismatch {
+                    count: count as usize,
+                    datas: data.len(),
+                });
             }
         }
 
@@ -297,6 +422,8 @@ impl<'a> Parser<'a> {
                 start,
                 imports,
                 exports,
+                code_ranges: self.take_code_ranges(),
+                func_names,
             },
             CustomSecList {
                 sec1,
@@ -319,7 +446,8 @@ impl<'a> Parser<'a> {
 
 #[cfg(test)]
 mod tests {
-    use crate::loader::{module::Module, parser::Parser};
+    use crate::binary::{Custom, Export, ExportDesc};
+    use crate::loader::{error::Error, module::Module, parser::Parser};
     use crate::tests::wat2wasm;
 
     #[test]
@@ -455,4 +583,203 @@ mod tests {
                 && types.len() == 1
         ));
     }
+
+    #[test]
+    fn func_code_count_mismatch() {
+        let wasm = [
+            0x00, 0x61, 0x73, 0x6D, 0x01, 0x00, 0x00, 0x00, // magic, version
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // types: [] -> []
+            0x03, 0x03, 0x02, 0x00, 0x00, // funcs: 2 entries
+            0x0A, 0x04, 0x01, 0x02, 0x00, 0x0B, // codes: 1 entry
+        ];
+        let mut parser = Parser::new(&wasm);
+        assert_eq!(
+            parser.module(),
+            Err(Error::FuncCodeCountMismatch { funcs: 2, codes: 1 })
+        );
+        let mut parser = Parser::new(&wasm);
+        assert_eq!(
+            parser.module_with_customs(),
+            Err(Error::FuncCodeCountMismatch { funcs: 2, codes: 1 })
+        );
+    }
+
+    #[test]
+    fn data_count_mismatch() {
+        let wasm = [
+            0x00, 0x61, 0x73, 0x6D, 0x01, 0x00, 0x00, 0x00, // magic, version
+            0x05, 0x03, 0x01, 0x00, 0x01, // mems: 1 page
+            0x0C, 0x01, 0x02, // datacount: 2
+            0x0B, 0x04, 0x01, 0x01, 0x01, 0xFF, // datas: 1 passive segment
+        ];
+        let mut parser = Parser::new(&wasm);
+        assert_eq!(
+            parser.module(),
+            Err(Error::DataCountMismatch { count: 2, datas: 1 })
+        );
+    }
+
+    #[test]
+    fn too_many_locals() {
+        let wasm = [
+            0x00, 0x61, 0x73, 0x6D, 0x01, 0x00, 0x00, 0x00, // magic, version
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // types: [] -> []
+            0x03, 0x02, 0x01, 0x00, // funcs: 1 entry
+            0x0A, 0x0A, 0x01, 0x08, // codes: 1 entry of 8 bytes
+            0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F, 0x7F, // u32::MAX i32 locals
+            0x0B, // end
+        ];
+        let mut parser = Parser::new(&wasm);
+        assert_eq!(parser.module(), Err(Error::TooManyLocals));
+    }
+
+    #[test]
+    fn module_header() {
+        let mut wasm = wat2wasm(
+            r#"(module
+                  (import "env" "print" (func (param i32)))
+                  (memory 1)
+                  (global i32 (i32.const 0))
+                  (func)
+                  (export "f" (func 1))
+                  (export "mem" (memory 0)))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        let header = Parser::new(&wasm).module_header().unwrap();
+        assert_eq!(header.types, module.types);
+        assert_eq!(header.imports, module.imports);
+        assert_eq!(header.exports, module.exports);
+
+        // Corrupt the last code byte: the header must not look at it.
+        let len = wasm.len();
+        wasm[len - 1] = 0xFF;
+        assert!(Parser::new(&wasm).module().is_err());
+        assert_eq!(Parser::new(&wasm).module_header(), Ok(header));
+    }
+
+    #[test]
+    fn skip_to_section() {
+        let mut wasm = wat2wasm(
+            r#"(module
+                  (memory 1)
+                  (func)
+                  (export "f" (func 0))
+                  (export "mem" (memory 0)))"#,
+        )
+        .unwrap();
+        // Corrupt the last code byte: only section headers may be read.
+        let len = wasm.len();
+        wasm[len - 1] = 0xFF;
+
+        let mut parser = Parser::new(&wasm);
+        parser.magic().unwrap();
+        parser.version().unwrap();
+        let (size, body) = parser.skip_to_section(7).unwrap().unwrap();
+        assert_eq!(size as usize, body.len());
+        assert_eq!(
+            Parser::new(body).vec(Parser::export),
+            Ok(vec![
+                Export {
+                    name: "f".into(),
+                    desc: ExportDesc::Func(0),
+                },
+                Export {
+                    name: "mem".into(),
+                    desc: ExportDesc::Mem(0),
+                },
+            ])
+        );
+        assert_eq!(parser.skip_to_section(7), Ok(None));
+    }
+
+    #[test]
+    fn custom_before_code() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (table 1 funcref)
+                  (func $f)
+                  (elem (i32.const 0) $f)
+                  (@custom "meta" (after elem) "\01\02\03"))"#,
+        )
+        .unwrap();
+        let (module, customs) = Parser::new(&wasm).module_with_customs().unwrap();
+        assert_eq!(module, Parser::new(&wasm).module().unwrap());
+        assert_eq!(module.funcs.len(), 1);
+        assert_eq!(module.elems.len(), 1);
+        assert_eq!(
+            customs.sec10,
+            vec![Custom {
+                name: "meta".into(),
+                bytes: vec![1, 2, 3],
+            }]
+        );
+    }
+
+    #[test]
+    fn module_into() {
+        let first = wat2wasm(
+            r#"(module
+                  (func) (func) (func)
+                  (export "a" (func 0)))"#,
+        )
+        .unwrap();
+        let second = wat2wasm(
+            r#"(module
+                  (func (result i32) (i32.const 1))
+                  (export "b" (func 0)))"#,
+        )
+        .unwrap();
+
+        let mut module = Parser::new(&first).module().unwrap();
+        Parser::new(&first).module_into(&mut module).unwrap();
+        let funcs = module.funcs.as_ptr();
+        let capacity = module.funcs.capacity();
+        let exports = module.exports.as_ptr();
+        let types = module.types.as_ptr();
+
+        Parser::new(&second).module_into(&mut module).unwrap();
+        assert_eq!(module, Parser::new(&second).module().unwrap());
+        assert_eq!(module.funcs.as_ptr(), funcs);
+        assert_eq!(module.funcs.capacity(), capacity);
+        assert_eq!(module.exports.as_ptr(), exports);
+        assert_eq!(module.types.as_ptr(), types);
+
+        assert!(Parser::new(&[0x00]).module_into(&mut module).is_err());
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let mut wasm = wat2wasm(r#"(module (func) (@custom "tail" (after last) "x"))"#).unwrap();
+        assert!(Parser::new(&wasm).module().is_ok());
+        wasm.push(0xFF);
+        assert_eq!(Parser::new(&wasm).module(), Err(Error::TrailingBytes(1)));
+        assert_eq!(
+            Parser::new(&wasm).module_with_customs(),
+            Err(Error::TrailingBytes(1))
+        );
+    }
+
+    #[test]
+    fn code_ranges() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func (result i32) i32.const 1)
+                  (func (local i64) nop))"#,
+        )
+        .unwrap();
+        assert!(Parser::new(&wasm).module().unwrap().code_ranges.is_empty());
+
+        let mut parser = Parser::new(&wasm);
+        parser.set_record_code_ranges(true);
+        let module = parser.module().unwrap();
+        // no locals, i32.const 1, end
+        let first = [0x00, 0x41, 0x01, 0x0B];
+        // one i64 local, nop, end
+        let second = [0x01, 0x01, 0x7E, 0x01, 0x0B];
+        assert_eq!(module.code_ranges.len(), 2);
+        assert_eq!(wasm[module.code_ranges[0].clone()], first);
+        assert_eq!(wasm[module.code_ranges[1].clone()], second);
+        assert_eq!(module.code_ranges[1].end, wasm.len());
+    }
 }
@@ -0,0 +1,51 @@
+//! The `name` custom section, which maps indices back to source symbols.
+
+#[cfg(not(feature = "std"))]
+use crate::lib::*;
+
+use super::{error::Error, parser::Parser};
+use crate::binary::FuncIdx;
+
+pub const SECTION_NAME: &str = "name";
+
+const FUNCTION_NAMES: u8 = 1;
+
+/// Parses the function names from the payload of a `name` section, sorted
+/// by index. Module and local names are skipped.
+pub fn func_names(bytes: &[u8]) -> Result<Vec<(FuncIdx, String)>, Error> {
+    let mut parser = Parser::new(bytes);
+    let mut names = vec![];
+    while let Some(id) = parser.next() {
+        let size = parser.u32()? as usize;
+        let body = parser
+            .rest()
+            .get(..size)
+            .ok_or(Error::UnexpectedEof("name subsection".into()))?;
+        parser.skip(size);
+
+        if id == FUNCTION_NAMES {
+            let mut sub = Parser::new(body);
+            names = sub.vec(|p| Ok((p.funcidx()?, p.name()?)))?;
+        }
+    }
+    names.sort_by_key(|&(idx, _)| idx);
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::func_names;
+
+    #[test]
+    fn function_subsection() {
+        // module name "m", then function names 1 -> "b" and 0 -> "a".
+        let bytes = [
+            0x00, 0x02, 0x01, b'm', //
+            0x01, 0x07, 0x02, 0x01, 0x01, b'b', 0x00, 0x01, b'a',
+        ];
+        assert_eq!(
+            func_names(&bytes),
+            Ok(vec![(0, "a".into()), (1, "b".into())])
+        );
+    }
+}
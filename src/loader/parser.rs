@@ -2,10 +2,13 @@
 use crate::lib::*;
 
 use super::error::Error;
+use core::ops::Range;
 
 pub struct Parser<'a> {
     bytes: &'a [u8],
     cursor: usize,
+    record_code_ranges: bool,
+    code_ranges: Vec<Range<usize>>,
 }
 
 pub trait Target {
@@ -14,22 +17,20 @@ pub trait Target {
 
 impl Target for &[u8] {
     fn target(parser: &mut Parser, target: Self) -> Option<()> {
-        (&parser.bytes[parser.cursor..])
+        parser.bytes[parser.cursor..]
             .strip_prefix(target)
-            .and_then(|rest| {
+            .map(|rest| {
                 parser.cursor = parser.bytes.len() - rest.len();
-                Some(())
             })
     }
 }
 
 impl<const N: usize> Target for &[u8; N] {
     fn target(parser: &mut Parser, target: Self) -> Option<()> {
-        (&parser.bytes[parser.cursor..])
+        parser.bytes[parser.cursor..]
             .strip_prefix(target)
-            .and_then(|rest| {
+            .map(|rest| {
                 parser.cursor = parser.bytes.len() - rest.len();
-                Some(())
             })
     }
 }
@@ -45,9 +46,31 @@ impl Target for u8 {
 
 impl<'a> Parser<'a> {
     pub fn new(bytes: &'a [u8]) -> Self {
-        Self { bytes, cursor: 0 }
+        Self {
+            bytes,
+            cursor: 0,
+            record_code_ranges: false,
+            code_ranges: vec![],
+        }
+    }
+
+    /// Record the byte range of each function body into
+    /// `Module::code_ranges`. Off by default.
+    pub fn set_record_code_ranges(&mut self, record: bool) {
+        self.record_code_ranges = record;
+    }
+
+    pub(super) fn record_code_range(&mut self, range: Range<usize>) {
+        if self.record_code_ranges {
+            self.code_ranges.push(range);
+        }
     }
 
+    pub(super) fn take_code_ranges(&mut self) -> Vec<Range<usize>> {
+        core::mem::take(&mut self.code_ranges)
+    }
+
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<u8> {
         if let Some(&value) = self.bytes.get(self.cursor) {
             self.cursor += 1;
@@ -73,15 +96,33 @@ impl<'a> Parser<'a> {
         Target::target(self, target)
     }
 
-    pub fn rest(&self) -> &[u8] {
+    /// Offset of the next unread byte from the start of the input.
+    pub fn position(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn rest(&self) -> &'a [u8] {
         &self.bytes[self.cursor..]
     }
 
+    /// Fails with the number of unconsumed bytes unless the input is used up.
+    pub fn end(&self) -> Result<(), Error> {
+        match self.rest().len() {
+            0 => Ok(()),
+            n => Err(Error::TrailingBytes(n)),
+        }
+    }
+
     pub fn vec<T, F>(&mut self, mut f: F) -> Result<Vec<T>, Error>
     where
         F: FnMut(&mut Self) -> Result<T, Error>,
     {
         let len = self.u32()?;
+        // Every element takes at least one byte, so a longer vector
+        // cannot be backed by the remaining input.
+        if len as usize > self.rest().len() {
+            return Err(Error::LengthExceedsInput);
+        }
         let mut vec = Vec::new();
         for _ in 0..len {
             vec.push(f(self)?);
@@ -89,6 +130,49 @@ impl<'a> Parser<'a> {
         Ok(vec)
     }
 
+    /// Like `vec`, but appends the elements to `dst`.
+    pub fn vec_into<T, F>(&mut self, dst: &mut Vec<T>, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&mut Self) -> Result<T, Error>,
+    {
+        let len = self.u32()?;
+        if len as usize > self.rest().len() {
+            return Err(Error::LengthExceedsInput);
+        }
+        dst.reserve(len as usize);
+        for _ in 0..len {
+            dst.push(f(self)?);
+        }
+        Ok(())
+    }
+
+    /// Runs the section parser `f` for each consecutive section `id`. Once
+    /// a section's id has matched, its errors are returned, not backed out.
+    pub fn sections<T, F>(&mut self, id: u8, mut f: F) -> Result<Vec<T>, Error>
+    where
+        F: FnMut(&mut Self) -> Result<T, Error>,
+    {
+        let mut vec = vec![];
+        while self.peek() == Some(id) {
+            vec.push(f(self)?);
+        }
+        Ok(vec)
+    }
+
+    /// Like `sections`, but appends the entries of each section `id` to
+    /// `dst`, parsing each entry with `f`.
+    pub fn sections_into<T, F>(&mut self, id: u8, dst: &mut Vec<T>, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&mut Self) -> Result<T, Error>,
+    {
+        while self.peek() == Some(id) {
+            self.next();
+            self.u32()?;
+            self.vec_into(dst, &mut f)?;
+        }
+        Ok(())
+    }
+
     pub fn take_while0<T, F, C>(&mut self, mut f: F, cond: C) -> Result<Vec<T>, Error>
     where
         F: FnMut(&mut Self) -> Result<T, Error>,
@@ -104,7 +188,7 @@ impl<'a> Parser<'a> {
                 Some(_) => {
                     vec.push(f(self)?);
                 }
-                None => return Err(Error::Expected(format!("next element or terminator"))),
+                None => return Err(Error::Expected("next element or terminator".into())),
             }
         }
         Ok(vec)
@@ -170,7 +254,7 @@ mod tests {
         let mut parser = Parser::new(b"abcabcabce");
         assert_eq!(
             parser.take_while0(
-                |p| p.target(b"abc").ok_or(Error::Expected(format!("abc"))),
+                |p| p.target(b"abc").ok_or(Error::Expected("abc".into())),
                 |b| b == b'e'
             ),
             Ok(vec![(), (), ()])
@@ -182,22 +266,47 @@ mod tests {
         let mut parser = Parser::new(b"abcdef");
         assert_eq!(
             parser.or(
-                |p| p.target(b"def").ok_or(Error::Expected(format!("def"))),
-                |p| p.target(b"abc").ok_or(Error::Expected(format!("abc")))
+                |p| p.target(b"def").ok_or(Error::Expected("def".into())),
+                |p| p.target(b"abc").ok_or(Error::Expected("abc".into()))
             ),
             Ok(())
         );
         assert_eq!(parser.rest(), b"def");
         assert_eq!(
             parser.or(
-                |p| p.target(b"abc").ok_or(Error::Expected(format!("abc"))),
-                |p| p.target(b"def").ok_or(Error::Expected(format!("def"))),
+                |p| p.target(b"abc").ok_or(Error::Expected("abc".into())),
+                |p| p.target(b"def").ok_or(Error::Expected("def".into())),
             ),
             Ok(())
         );
         assert_eq!(parser.rest(), b"");
     }
 
+    #[test]
+    fn test_or_error() {
+        let mut parser = Parser::new(b"xyz");
+        let err = parser
+            .or(
+                |p| p.target(b"abc").ok_or(Error::Expected("abc".into())),
+                |p| {
+                    p.or(
+                        |p| p.target(b"def").ok_or(Error::Expected("def".into())),
+                        |p| p.target(b"ghi").ok_or(Error::Expected("ghi".into())),
+                    )
+                },
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.flatten_alternatives(),
+            vec![
+                &Error::Expected("abc".into()),
+                &Error::Expected("def".into()),
+                &Error::Expected("ghi".into())
+            ]
+        );
+        assert_eq!(err.to_string(), "expected one of: abc, def, ghi");
+    }
+
     #[test]
     fn test_name() {
         assert_eq!(
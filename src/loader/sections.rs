@@ -9,7 +9,7 @@ impl<'a> Parser<'a> {
     /// 1. Type Section
     pub fn typesec(&mut self) -> Result<TypeSec, Error> {
         self.target(1)
-            .ok_or(Error::Expected(format!("section id: 1")))?;
+            .ok_or(Error::Expected("section id: 1".into()))?;
         Ok(Section {
             size: self.u32()?,
             value: self.vec(Self::functype)?,
@@ -19,7 +19,7 @@ impl<'a> Parser<'a> {
     /// 2. Import Section
     pub fn importsec(&mut self) -> Result<ImportSec, Error> {
         self.target(2)
-            .ok_or(Error::Expected(format!("section id: 2")))?;
+            .ok_or(Error::Expected("section id: 2".into()))?;
         Ok(Section {
             size: self.u32()?,
             value: self.vec(Self::import)?,
@@ -40,15 +40,15 @@ impl<'a> Parser<'a> {
             Some(0x01) => Ok(ImportDesc::Table(self.table()?)),
             Some(0x02) => Ok(ImportDesc::Mem(self.memory()?)),
             Some(0x03) => Ok(ImportDesc::Global(self.globaltype()?)),
-            Some(_) => Err(Error::Expected(format!("importdesc"))),
-            None => Err(Error::UnexpectedEof(format!("importdesc"))),
+            Some(_) => Err(Error::Expected("importdesc".into())),
+            None => Err(Error::UnexpectedEof("importdesc".into())),
         }
     }
 
     /// 3. Function Section
     pub fn funcsec(&mut self) -> Result<FuncSec, Error> {
         self.target(3)
-            .ok_or(Error::Expected(format!("section id: 3")))?;
+            .ok_or(Error::Expected("section id: 3".into()))?;
         Ok(Section {
             size: self.u32()?,
             value: self.vec(Self::u32)?,
@@ -58,17 +58,30 @@ impl<'a> Parser<'a> {
     /// 4. Table Section
     pub fn tablesec(&mut self) -> Result<TableSec, Error> {
         self.target(4)
-            .ok_or(Error::Expected(format!("section id: 4")))?;
+            .ok_or(Error::Expected("section id: 4".into()))?;
         Ok(Section {
             size: self.u32()?,
-            value: self.vec(Self::table)?,
+            value: self.vec(Self::table_entry)?,
         })
     }
 
+    /// A table definition, optionally prefixed by `0x40 0x00` and followed by
+    /// an initializer expression.
+    pub fn table_entry(&mut self) -> Result<Table, Error> {
+        if self.peek() != Some(0x40) {
+            return self.table();
+        }
+        self.skip(1);
+        self.target(0x00u8).ok_or(Error::Expected("0x00".into()))?;
+        let mut table = self.table()?;
+        table.init = Some(self.expr()?);
+        Ok(table)
+    }
+
     /// 5. Memory Section
     pub fn memsec(&mut self) -> Result<MemSec, Error> {
         self.target(5)
-            .ok_or(Error::Expected(format!("section id: 5")))?;
+            .ok_or(Error::Expected("section id: 5".into()))?;
         Ok(Section {
             size: self.u32()?,
             value: self.vec(Self::memory)?,
@@ -78,22 +91,24 @@ impl<'a> Parser<'a> {
     /// 6. Global Section
     pub fn globalsec(&mut self) -> Result<GlobalSec, Error> {
         self.target(6)
-            .ok_or(Error::Expected(format!("section id: 6")))?;
+            .ok_or(Error::Expected("section id: 6".into()))?;
         Ok(Section {
             size: self.u32()?,
-            value: self.vec(|p| {
-                Ok(Global {
-                    type_: p.globaltype()?,
-                    value: p.expr()?,
-                })
-            })?,
+            value: self.vec(Self::global)?,
+        })
+    }
+
+    pub fn global(&mut self) -> Result<Global, Error> {
+        Ok(Global {
+            type_: self.globaltype()?,
+            value: self.expr()?,
         })
     }
 
     /// 7. Export Section
     pub fn exportsec(&mut self) -> Result<ExportSec, Error> {
         self.target(7)
-            .ok_or(Error::Expected(format!("section id: 7")))?;
+            .ok_or(Error::Expected("section id: 7".into()))?;
         Ok(Section {
             size: self.u32()?,
             value: self.vec(Self::export)?,
@@ -133,7 +148,7 @@ impl<'a> Parser<'a> {
     /// 9. Element Section
     pub fn elemsec(&mut self) -> Result<ElemSec, Error> {
         self.target(9)
-            .ok_or(Error::Expected(format!("section id: 9")))?;
+            .ok_or(Error::Expected("section id: 9".into()))?;
         Ok(Section {
             size: self.u32()?,
             value: self.vec(Self::elem)?,
@@ -235,10 +250,9 @@ impl<'a> Parser<'a> {
     }
 
     pub fn elemkind(&mut self) -> Result<RefType, Error> {
-        Ok(self
-            .target(0x00)
-            .ok_or(Error::Expected(format!("0x00")))
-            .map(|_| RefType::FuncRef)?)
+        self.target(0x00)
+            .ok_or(Error::Expected("0x00".into()))
+            .map(|_| RefType::FuncRef)
     }
 
     pub fn elem_init(&mut self) -> Result<Vec<Expr>, Error> {
@@ -252,7 +266,7 @@ impl<'a> Parser<'a> {
     /// 10. Code Section
     pub fn codesec(&mut self) -> Result<CodeSec, Error> {
         self.target(10)
-            .ok_or(Error::Expected(format!("section id: 10")))?;
+            .ok_or(Error::Expected("section id: 10".into()))?;
         Ok(Section {
             size: self.u32()?,
             value: self.vec(Self::code)?,
@@ -260,10 +274,11 @@ impl<'a> Parser<'a> {
     }
 
     pub fn code(&mut self) -> Result<Code, Error> {
-        Ok(Code {
-            size: self.u32()?,
-            func: self.func0()?,
-        })
+        let size = self.u32()?;
+        let start = self.position();
+        let func = self.func0()?;
+        self.record_code_range(start..self.position());
+        Ok(Code { size, func })
     }
 
     pub fn func0(&mut self) -> Result<Func0, Error> {
@@ -283,7 +298,7 @@ impl<'a> Parser<'a> {
     /// 11. Data Section
     pub fn datasec(&mut self) -> Result<DataSec, Error> {
         self.target(11)
-            .ok_or(Error::Expected(format!("section id: 11")))?;
+            .ok_or(Error::Expected("section id: 11".into()))?;
         Ok(Section {
             size: self.u32()?,
             value: self.vec(Self::data)?,
@@ -294,14 +309,14 @@ impl<'a> Parser<'a> {
         match self.byte() {
             Some(0) => {
                 let offset = self.expr()?;
-                let init = self.vec(|p| p.byte().ok_or(Error::Expected(format!("byte"))))?;
+                let init = self.vec(|p| p.byte().ok_or(Error::Expected("byte".into())))?;
                 Ok(Data {
                     init,
                     mode: DataMode::Active { memidx: 0, offset },
                 })
             }
             Some(1) => {
-                let init = self.vec(|p| p.byte().ok_or(Error::Expected(format!("byte"))))?;
+                let init = self.vec(|p| p.byte().ok_or(Error::Expected("byte".into())))?;
                 Ok(Data {
                     init,
                     mode: DataMode::Passive,
@@ -310,7 +325,7 @@ impl<'a> Parser<'a> {
             Some(2) => {
                 let memory = self.memidx()?;
                 let offset = self.expr()?;
-                let init = self.vec(|p| p.byte().ok_or(Error::Expected(format!("byte"))))?;
+                let init = self.vec(|p| p.byte().ok_or(Error::Expected("byte".into())))?;
                 Ok(Data {
                     init,
                     mode: DataMode::Active {
@@ -339,15 +354,20 @@ impl<'a> Parser<'a> {
     /// 0. Custom Section
     pub fn custom_section(&mut self) -> Result<CustomSec, Error> {
         self.target(0)
-            .ok_or(Error::Expected(format!("section id: 0")))?;
-        let (size, bytes) = self.u32_bytes()?;
-        let name = self.name()?;
-        let name_len = name.len();
+            .ok_or(Error::Expected("section id: 0".into()))?;
+        let size = self.u32()?;
+        let body = self
+            .rest()
+            .get(..size as usize)
+            .ok_or(Error::UnexpectedEof("custom section".into()))?;
+        let mut inner = Parser::new(body);
+        let name = inner.name()?;
+        self.skip(size as usize);
         Ok(Section {
             size,
             value: Custom {
                 name,
-                bytes: (&self.rest()[..(size as usize - name_len - bytes)]).into(),
+                bytes: inner.rest().into(),
             },
         })
     }
@@ -356,7 +376,7 @@ impl<'a> Parser<'a> {
 #[cfg(test)]
 mod tests {
     use crate::binary::{FuncType, Mut, ResultType, ValType};
-    use crate::loader::{parser::Parser, sections::*};
+    use crate::loader::{self, parser::Parser, sections::*};
     use crate::tests::wat2wasm;
 
     #[test]
@@ -465,7 +485,8 @@ mod tests {
                 size: 4,
                 value: vec![Table {
                     reftype: RefType::FuncRef,
-                    limits: Limits::Min(2)
+                    limits: Limits::Min(2),
+                    init: None,
                 }]
             })
         );
@@ -482,7 +503,10 @@ mod tests {
             parser.memsec(),
             Ok(Section {
                 size: 4,
-                value: vec![Memory(Limits::MinMax(1, 2))]
+                value: vec![Memory {
+                    limits: Limits::MinMax(1, 2),
+                    shared: false
+                }]
             })
         );
     }
@@ -684,4 +708,14 @@ mod tests {
             })
         );
     }
+
+    #[test]
+    fn test_length_exceeds_input() {
+        // A type section declaring 1,000,000 function types.
+        let wasm = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x05, 0xC0, 0x84, 0x3D, 0x60,
+            0x00,
+        ];
+        assert_eq!(loader::parse(&wasm), Err(Error::LengthExceedsInput));
+    }
 }
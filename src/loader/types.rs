@@ -6,17 +6,17 @@ use crate::lib::*;
 impl<'a> Parser<'a> {
     pub fn reftype(&mut self) -> Result<RefType, Error> {
         if let Some(byte) = self.byte() {
-            FromByte::from_byte(byte).ok_or(Error::Expected(format!("reftype")))
+            FromByte::from_byte(byte).ok_or(Error::Expected("reftype".into()))
         } else {
-            Err(Error::UnexpectedEof(format!("reftype")))
+            Err(Error::UnexpectedEof("reftype".into()))
         }
     }
 
     pub fn valtype(&mut self) -> Result<ValType, Error> {
         if let Some(byte) = self.byte() {
-            FromByte::from_byte(byte).ok_or(Error::Expected(format!("valtype")))
+            FromByte::from_byte(byte).ok_or(Error::Expected("valtype".into()))
         } else {
-            Err(Error::UnexpectedEof(format!("valtype")))
+            Err(Error::UnexpectedEof("valtype".into()))
         }
     }
 
@@ -37,7 +37,7 @@ impl<'a> Parser<'a> {
     pub fn functype(&mut self) -> Result<FuncType, Error> {
         if let Some(byte) = self.byte() {
             if byte != 0x60 {
-                return Err(Error::Expected(format!("0x60")));
+                return Err(Error::Expected("0x60".into()));
             }
         }
 
@@ -48,19 +48,40 @@ impl<'a> Parser<'a> {
         match self.byte() {
             Some(0x00) => Ok(Limits::Min(self.u32()?)),
             Some(0x01) => Ok(Limits::MinMax(self.u32()?, self.u32()?)),
-            Some(_) => Err(Error::Expected(format!("limits"))),
-            None => Err(Error::UnexpectedEof(format!("limits"))),
+            Some(_) => Err(Error::Expected("limits".into())),
+            None => Err(Error::UnexpectedEof("limits".into())),
         }
     }
 
     pub fn memory(&mut self) -> Result<Memory, Error> {
-        Ok(Memory(self.limits()?))
+        // Flags 0x02 and 0x03 are the shared variants of 0x00 and 0x01.
+        let limits = match self.peek() {
+            Some(0x02) => {
+                self.next();
+                Limits::Min(self.u32()?)
+            }
+            Some(0x03) => {
+                self.next();
+                Limits::MinMax(self.u32()?, self.u32()?)
+            }
+            _ => {
+                return Ok(Memory {
+                    limits: self.limits()?,
+                    shared: false,
+                })
+            }
+        };
+        Ok(Memory {
+            limits,
+            shared: true,
+        })
     }
 
     pub fn table(&mut self) -> Result<Table, Error> {
         Ok(Table {
             reftype: self.reftype()?,
             limits: self.limits()?,
+            init: None,
         })
     }
 
@@ -68,7 +89,7 @@ impl<'a> Parser<'a> {
         match self.byte() {
             Some(0x00) => Ok(Mut::Const),
             Some(0x01) => Ok(Mut::Var),
-            _ => Err(Error::Expected(format!("0x00 or 0x01"))),
+            _ => Err(Error::Expected("0x00 or 0x01".into())),
         }
     }
 
@@ -0,0 +1,806 @@
+#[cfg(not(feature = "std"))]
+use crate::lib::*;
+
+use crate::binary::*;
+
+use super::error::{Error, IndexSpace};
+
+/// Checks the parts of a module that the parser alone does not reject.
+pub fn validate(module: &Module) -> Result<(), Error> {
+    validate_indices(module)?;
+    validate_func_refs(module)?;
+    validate_table_copies(module)?;
+    validate_stack_types(module)
+}
+
+/// Exports, the start function and segments must refer to entries that
+/// exist, or instantiation would index out of bounds.
+fn validate_indices(module: &Module) -> Result<(), Error> {
+    let ctx = Context::new(module);
+    for export in &module.exports {
+        match export.desc {
+            ExportDesc::Func(idx) => ctx.func(idx).map(|_| ())?,
+            ExportDesc::Table(idx) => ctx.table(idx).map(|_| ())?,
+            ExportDesc::Mem(idx) => ctx.mem(idx)?,
+            ExportDesc::Global(idx) => ctx.global(idx).map(|_| ())?,
+        }
+    }
+    if let Some(idx) = module.start {
+        ctx.func(idx)?;
+    }
+    for elem in &module.elems {
+        if let ElemMode::Active { tableidx, .. } = elem.mode {
+            ctx.table(tableidx)?;
+        }
+        for instr in elem.init.iter().flat_map(|expr| &expr.0) {
+            if let Instr::RefFunc(idx) = instr {
+                ctx.func(*idx)?;
+            }
+        }
+    }
+    for data in &module.datas {
+        if let DataMode::Active { memidx, .. } = data.mode {
+            ctx.mem(memidx)?;
+        }
+    }
+    Ok(())
+}
+
+/// A `ref.func` inside a function body may only name a function that is
+/// declared elsewhere in the module: in a global or table initializer, an
+/// element segment of any mode, or an export.
+fn validate_func_refs(module: &Module) -> Result<(), Error> {
+    let mut declared = vec![];
+    let consts = module
+        .globals
+        .iter()
+        .map(|global| &global.value)
+        .chain(module.tables.iter().filter_map(|table| table.init.as_ref()))
+        .chain(module.elems.iter().flat_map(|elem| elem.init.iter()));
+    for expr in consts {
+        declared.extend(expr.0.iter().filter_map(|instr| match instr {
+            Instr::RefFunc(idx) => Some(*idx),
+            _ => None,
+        }));
+    }
+    declared.extend(
+        module
+            .exports
+            .iter()
+            .filter_map(|export| match export.desc {
+                ExportDesc::Func(idx) => Some(idx),
+                _ => None,
+            }),
+    );
+
+    for func in &module.funcs {
+        for instr in &func.body.0 {
+            if let Instr::RefFunc(idx) = instr {
+                if !declared.contains(idx) {
+                    return Err(Error::UndeclaredFuncRef(*idx));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `table.copy` requires both tables to hold the same reference type.
+fn validate_table_copies(module: &Module) -> Result<(), Error> {
+    let tables = module.table_requirements();
+    for func in &module.funcs {
+        for instr in &func.body.0 {
+            if let Instr::TableCopy(dst, src) = instr {
+                let reftype = |idx: &u32| tables.get(*idx as usize).map(|(reftype, _)| *reftype);
+                if reftype(dst) != reftype(src) {
+                    return Err(Error::TableTypeMismatch {
+                        dst: *dst,
+                        src: *src,
+                    });
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Every block, and each function body as a whole, must leave exactly the
+/// values its type declares.
+fn validate_stack_types(module: &Module) -> Result<(), Error> {
+    let ctx = Context::new(module);
+    for func in &module.funcs {
+        let FuncType(params, results) = ctx.type_(func.typeidx)?;
+        let locals = params.0.iter().chain(&func.locals).copied().collect();
+        TypeChecker::new(&ctx, locals).check(&func.body.0, &results.0)?;
+    }
+    Ok(())
+}
+
+/// Types of everything a function body can refer to, imports first.
+struct Context<'a> {
+    types: &'a [FuncType],
+    funcs: Vec<TypeIdx>,
+    globals: Vec<ValType>,
+    tables: Vec<ValType>,
+    mems: usize,
+}
+
+impl<'a> Context<'a> {
+    fn new(module: &'a Module) -> Self {
+        let funcs = module
+            .imports
+            .iter()
+            .filter_map(|import| match import.desc {
+                ImportDesc::Func(idx) => Some(idx),
+                _ => None,
+            })
+            .chain(module.funcs.iter().map(|func| func.typeidx))
+            .collect();
+        let globals = module
+            .imports
+            .iter()
+            .filter_map(|import| match &import.desc {
+                ImportDesc::Global(globaltype) => Some(globaltype.valtype),
+                _ => None,
+            })
+            .chain(module.globals.iter().map(|global| global.type_.valtype))
+            .collect();
+        let tables = module
+            .table_requirements()
+            .into_iter()
+            .map(|(reftype, _)| ref_valtype(reftype))
+            .collect();
+        let mems = module
+            .imports
+            .iter()
+            .filter(|import| matches!(import.desc, ImportDesc::Mem(_)))
+            .count()
+            + module.mems.len();
+        Self {
+            types: &module.types,
+            funcs,
+            globals,
+            tables,
+            mems,
+        }
+    }
+
+    fn type_(&self, idx: TypeIdx) -> Result<&'a FuncType, Error> {
+        let types = self.types;
+        types.get(idx as usize).ok_or(Error::UnknownIndex {
+            space: IndexSpace::Type,
+            idx,
+        })
+    }
+
+    fn func(&self, idx: FuncIdx) -> Result<&'a FuncType, Error> {
+        let typeidx = self.funcs.get(idx as usize).ok_or(Error::UnknownIndex {
+            space: IndexSpace::Func,
+            idx,
+        })?;
+        self.type_(*typeidx)
+    }
+
+    fn global(&self, idx: GlobalIdx) -> Result<ValType, Error> {
+        self.globals
+            .get(idx as usize)
+            .copied()
+            .ok_or(Error::UnknownIndex {
+                space: IndexSpace::Global,
+                idx,
+            })
+    }
+
+    fn mem(&self, idx: MemIdx) -> Result<(), Error> {
+        if (idx as usize) < self.mems {
+            Ok(())
+        } else {
+            Err(Error::UnknownIndex {
+                space: IndexSpace::Mem,
+                idx,
+            })
+        }
+    }
+
+    /// Like `mem`, but instructions can only execute on memory 0.
+    fn executed_mem(&self, idx: MemIdx) -> Result<(), Error> {
+        self.mem(idx)?;
+        if idx != 0 {
+            return Err(Error::Other(format!("unsupported memory {}", idx)));
+        }
+        Ok(())
+    }
+
+    fn table(&self, idx: TableIdx) -> Result<ValType, Error> {
+        self.tables
+            .get(idx as usize)
+            .copied()
+            .ok_or(Error::UnknownIndex {
+                space: IndexSpace::Table,
+                idx,
+            })
+    }
+}
+
+fn ref_valtype(reftype: RefType) -> ValType {
+    match reftype {
+        RefType::FuncRef => ValType::FuncRef,
+        RefType::ExternRef => ValType::ExternRef,
+    }
+}
+
+#[derive(PartialEq)]
+enum FrameKind {
+    Block,
+    Loop,
+    /// The then branch of an `if`, with the position of the `PopLabel`
+    /// that ends it if an else branch follows.
+    Then(Option<usize>),
+}
+
+struct ControlFrame {
+    kind: FrameKind,
+    params: Vec<ValType>,
+    results: Vec<ValType>,
+    height: usize,
+    unreachable: bool,
+}
+
+/// The validation algorithm from the spec appendix, run over the flattened
+/// instruction stream. An operand of `None` has unknown type; it is left
+/// behind by an unconditional branch and matches anything.
+struct TypeChecker<'a> {
+    ctx: &'a Context<'a>,
+    locals: Vec<ValType>,
+    vals: Vec<Option<ValType>>,
+    frames: Vec<ControlFrame>,
+}
+
+impl<'a> TypeChecker<'a> {
+    fn new(ctx: &'a Context<'a>, locals: Vec<ValType>) -> Self {
+        Self {
+            ctx,
+            locals,
+            vals: vec![],
+            frames: vec![],
+        }
+    }
+
+    fn check(mut self, body: &[Instr], results: &[ValType]) -> Result<(), Error> {
+        self.push_ctrl(FrameKind::Block, vec![], results.to_vec());
+        let mut pc = 0;
+        while pc < body.len() {
+            pc = self.instr(body, pc)?;
+        }
+        self.pop_ctrl()?;
+        Ok(())
+    }
+
+    fn push(&mut self, valtype: ValType) {
+        self.vals.push(Some(valtype));
+    }
+
+    fn push_all(&mut self, valtypes: &[ValType]) {
+        self.vals.extend(valtypes.iter().copied().map(Some));
+    }
+
+    /// Pops an operand, which must be `expected` if given.
+    fn pop_operand(&mut self, expected: Option<ValType>) -> Result<Option<ValType>, Error> {
+        let frame = self.frames.last().unwrap();
+        let actual = if self.vals.len() == frame.height {
+            if !frame.unreachable {
+                return Err(Error::TypeMismatch(match expected {
+                    Some(expected) => format!("expected {}, found nothing", expected),
+                    None => "expected a value, found nothing".into(),
+                }));
+            }
+            None
+        } else {
+            self.vals.pop().unwrap()
+        };
+        match (actual, expected) {
+            (Some(actual), Some(expected)) if actual != expected => Err(Error::TypeMismatch(
+                format!("expected {}, found {}", expected, actual),
+            )),
+            (None, expected) => Ok(expected),
+            (actual, _) => Ok(actual),
+        }
+    }
+
+    fn pop(&mut self, expected: ValType) -> Result<(), Error> {
+        self.pop_operand(Some(expected)).map(|_| ())
+    }
+
+    fn pop_all(&mut self, expected: &[ValType]) -> Result<Vec<Option<ValType>>, Error> {
+        let mut popped = expected
+            .iter()
+            .rev()
+            .map(|&valtype| self.pop_operand(Some(valtype)))
+            .collect::<Result<Vec<_>, _>>()?;
+        popped.reverse();
+        Ok(popped)
+    }
+
+    fn push_ctrl(&mut self, kind: FrameKind, params: Vec<ValType>, results: Vec<ValType>) {
+        self.frames.push(ControlFrame {
+            kind,
+            height: self.vals.len(),
+            params,
+            results,
+            unreachable: false,
+        });
+        let params = self.frames.last().unwrap().params.clone();
+        self.push_all(&params);
+    }
+
+    fn pop_ctrl(&mut self) -> Result<ControlFrame, Error> {
+        let results = self.frames.last().unwrap().results.clone();
+        self.pop_all(&results)?;
+        let frame = self.frames.pop().unwrap();
+        if self.vals.len() != frame.height {
+            return Err(Error::TypeMismatch(format!(
+                "expected {} values at end of block, found {}",
+                results.len(),
+                self.vals.len() - frame.height + results.len()
+            )));
+        }
+        Ok(frame)
+    }
+
+    fn set_unreachable(&mut self) {
+        let frame = self.frames.last_mut().unwrap();
+        self.vals.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    fn label_types(&self, l: LabelIdx) -> Result<Vec<ValType>, Error> {
+        let frame = (self.frames.len())
+            .checked_sub(l as usize + 1)
+            .map(|i| &self.frames[i])
+            .ok_or(Error::UnknownIndex {
+                space: IndexSpace::Label,
+                idx: l,
+            })?;
+        Ok(if frame.kind == FrameKind::Loop {
+            frame.params.clone()
+        } else {
+            frame.results.clone()
+        })
+    }
+
+    fn blocktype(&self, bt: &Block) -> Result<(Vec<ValType>, Vec<ValType>), Error> {
+        Ok(match bt {
+            Block::Empty => (vec![], vec![]),
+            Block::ValType(valtype) => (vec![], vec![*valtype]),
+            Block::TypeIdx(idx) => {
+                let FuncType(params, results) = self.ctx.type_(*idx)?;
+                (params.0.clone(), results.0.clone())
+            }
+        })
+    }
+
+    fn local(&self, idx: LocalIdx) -> Result<ValType, Error> {
+        self.locals
+            .get(idx as usize)
+            .copied()
+            .ok_or(Error::UnknownIndex {
+                space: IndexSpace::Local,
+                idx,
+            })
+    }
+
+    /// Checks the instruction at `pc` and returns the position of the next.
+    fn instr(&mut self, body: &[Instr], pc: usize) -> Result<usize, Error> {
+        use ValType::*;
+
+        match &body[pc] {
+            Instr::Unreachable => self.set_unreachable(),
+            Instr::Nop | Instr::ElemDrop(_) | Instr::DataDrop(_) => {}
+            Instr::Block { bt, .. } => {
+                let (params, results) = self.blocktype(bt)?;
+                self.pop_all(&params)?;
+                self.push_ctrl(FrameKind::Block, params, results);
+            }
+            Instr::Loop { bt } => {
+                let (params, results) = self.blocktype(bt)?;
+                self.pop_all(&params)?;
+                self.push_ctrl(FrameKind::Loop, params, results);
+            }
+            Instr::If {
+                bt, else_offset, ..
+            } => {
+                self.pop(I32)?;
+                let (params, results) = self.blocktype(bt)?;
+                self.pop_all(&params)?;
+                // The then branch ends with `PopLabel` and an `RJump` over
+                // the else branch.
+                let else_at = else_offset.map(|offset| pc + offset - 2);
+                self.push_ctrl(FrameKind::Then(else_at), params, results);
+            }
+            Instr::PopLabel => {
+                if self.frames.len() == 1 {
+                    return Err(Error::Other(format!("PopLabel at {} closes no block", pc)));
+                }
+                let frame = self.pop_ctrl()?;
+                match frame.kind {
+                    FrameKind::Then(Some(else_at)) if else_at == pc => {
+                        self.push_ctrl(FrameKind::Block, frame.params, frame.results);
+                        return Ok(pc + 2);
+                    }
+                    FrameKind::Then(None) if frame.params != frame.results => {
+                        return Err(Error::TypeMismatch(
+                            "if without else must leave its params".into(),
+                        ));
+                    }
+                    _ => {}
+                }
+                self.push_all(&frame.results);
+            }
+            Instr::RJump(_) => {
+                return Err(Error::Other(format!("RJump at {} outside an if", pc)));
+            }
+            Instr::Br(l) => {
+                let types = self.label_types(*l)?;
+                self.pop_all(&types)?;
+                self.set_unreachable();
+            }
+            Instr::BrIf(l) => {
+                self.pop(I32)?;
+                let types = self.label_types(*l)?;
+                self.pop_all(&types)?;
+                self.push_all(&types);
+            }
+            Instr::BrTable { indexs, default } => {
+                self.pop(I32)?;
+                let types = self.label_types(*default)?;
+                for l in indexs {
+                    let label = self.label_types(*l)?;
+                    if label.len() != types.len() {
+                        return Err(Error::TypeMismatch(format!(
+                            "br_table targets carry {} and {} values",
+                            label.len(),
+                            types.len()
+                        )));
+                    }
+                    let popped = self.pop_all(&label)?;
+                    self.vals.extend(popped);
+                }
+                self.pop_all(&types)?;
+                self.set_unreachable();
+            }
+            Instr::Return => {
+                let results = self.frames[0].results.clone();
+                self.pop_all(&results)?;
+                self.set_unreachable();
+            }
+            Instr::Call(idx) => {
+                let FuncType(params, results) = self.ctx.func(*idx)?;
+                self.pop_all(&params.0)?;
+                self.push_all(&results.0);
+            }
+            Instr::CallIndirect(typeidx, tableidx) => {
+                self.ctx.table(*tableidx)?;
+                self.pop(I32)?;
+                let FuncType(params, results) = self.ctx.type_(*typeidx)?;
+                self.pop_all(&params.0)?;
+                self.push_all(&results.0);
+            }
+            Instr::RefNull(reftype) => self.push(ref_valtype(*reftype)),
+            Instr::RefIsNull => {
+                if let Some(valtype @ (I32 | I64 | F32 | F64 | V128)) = self.pop_operand(None)? {
+                    return Err(Error::TypeMismatch(format!(
+                        "expected a reference, found {}",
+                        valtype
+                    )));
+                }
+                self.push(I32);
+            }
+            Instr::RefFunc(_) => self.push(FuncRef),
+            Instr::Drop => {
+                self.pop_operand(None)?;
+            }
+            Instr::Select => {
+                self.pop(I32)?;
+                let first = self.pop_operand(None)?;
+                let second = self.pop_operand(first)?;
+                self.vals.push(second);
+            }
+            Instr::LocalGet(idx) => self.push(self.local(*idx)?),
+            Instr::LocalSet(idx) => self.pop(self.local(*idx)?)?,
+            Instr::LocalTee(idx) => {
+                let valtype = self.local(*idx)?;
+                self.pop(valtype)?;
+                self.push(valtype);
+            }
+            Instr::GlobalGet(idx) => self.push(self.ctx.global(*idx)?),
+            Instr::GlobalSet(idx) => self.pop(self.ctx.global(*idx)?)?,
+            Instr::TableGet(idx) => {
+                let reftype = self.ctx.table(*idx)?;
+                self.pop(I32)?;
+                self.push(reftype);
+            }
+            Instr::TableSet(idx) => {
+                let reftype = self.ctx.table(*idx)?;
+                self.pop_all(&[I32, reftype])?;
+            }
+            Instr::TableInit(_, idx) | Instr::TableCopy(idx, _) => {
+                self.ctx.table(*idx)?;
+                self.pop_all(&[I32, I32, I32])?;
+            }
+            Instr::TableGrow(idx) => {
+                let reftype = self.ctx.table(*idx)?;
+                self.pop_all(&[reftype, I32])?;
+                self.push(I32);
+            }
+            Instr::TableSize(idx) => {
+                self.ctx.table(*idx)?;
+                self.push(I32);
+            }
+            Instr::TableFill(idx) => {
+                let reftype = self.ctx.table(*idx)?;
+                self.pop_all(&[I32, reftype, I32])?;
+            }
+            Instr::MemorySize(idx) => {
+                self.ctx.executed_mem(*idx)?;
+                self.push(I32);
+            }
+            Instr::MemoryGrow(idx) => {
+                self.ctx.executed_mem(*idx)?;
+                self.pop(I32)?;
+                self.push(I32);
+            }
+            Instr::MemoryInit(_) | Instr::MemoryCopy | Instr::MemoryFill => {
+                self.pop_all(&[I32, I32, I32])?;
+            }
+            Instr::I32Const(_) => self.push(I32),
+            Instr::I64Const(_) => self.push(I64),
+            Instr::F32Const(_) => self.push(F32),
+            Instr::F64Const(_) => self.push(F64),
+            Instr::V128Const(_) => self.push(V128),
+            instr => {
+                let (params, result) = operator_type(instr);
+                self.pop_all(params)?;
+                if let Some(result) = result {
+                    self.push(result);
+                }
+            }
+        }
+        Ok(pc + 1)
+    }
+}
+
+/// Operand and result types of a memory, numeric, vector or atomic
+/// instruction.
+fn operator_type(instr: &Instr) -> (&'static [ValType], Option<ValType>) {
+    use Instr::*;
+    use ValType::{F32, F64, I32, I64, V128};
+
+    match instr {
+        I32Load(_) | I32Load8S(_) | I32Load8U(_) | I32Load16S(_) | I32Load16U(_) => {
+            (&[I32], Some(I32))
+        }
+        I64Load(_) | I64Load8S(_) | I64Load8U(_) | I64Load16S(_) | I64Load16U(_)
+        | I64Load32S(_) | I64Load32U(_) => (&[I32], Some(I64)),
+        F32Load(_) => (&[I32], Some(F32)),
+        F64Load(_) => (&[I32], Some(F64)),
+        I32Store(_) | I32Store8(_) | I32Store16(_) => (&[I32, I32], None),
+        I64Store(_) | I64Store8(_) | I64Store16(_) | I64Store32(_) => (&[I32, I64], None),
+        F32Store(_) => (&[I32, F32], None),
+        F64Store(_) => (&[I32, F64], None),
+
+        I32Eqz | I32Clz | I32Ctz | I32Popcnt | I32Extend8S | I32Extend16S => (&[I32], Some(I32)),
+        I32Eq | I32Ne | I32LtS | I32LtU | I32GtS | I32GtU | I32LeS | I32LeU | I32GeS | I32GeU
+        | I32Add | I32Sub | I32Mul | I32DivS | I32DivU | I32RemS | I32RemU | I32And | I32Or
+        | I32Xor | I32Shl | I32ShrS | I32ShrU | I32RotL | I32RotR => (&[I32, I32], Some(I32)),
+        I64Eqz => (&[I64], Some(I32)),
+        I64Eq | I64Ne | I64LtS | I64LtU | I64GtS | I64GtU | I64LeS | I64LeU | I64GeS | I64GeU => {
+            (&[I64, I64], Some(I32))
+        }
+        I64Clz | I64Ctz | I64Popcnt | I64Extend8S | I64Extend16S | I64Extend32S => {
+            (&[I64], Some(I64))
+        }
+        I64Add | I64Sub | I64Mul | I64DivS | I64DivU | I64RemS | I64RemU | I64And | I64Or
+        | I64Xor | I64Shl | I64ShrS | I64ShrU | I64RotL | I64RotR => (&[I64, I64], Some(I64)),
+        F32Eq | F32Ne | F32Lt | F32Gt | F32Le | F32Ge => (&[F32, F32], Some(I32)),
+        F32Abs | F32Neg | F32Ceil | F32Floor | F32Trunc | F32Nearest | F32Sqrt => {
+            (&[F32], Some(F32))
+        }
+        F32Add | F32Sub | F32Mul | F32Div | F32Min | F32Max | F32Copysign => {
+            (&[F32, F32], Some(F32))
+        }
+        F64Eq | F64Ne | F64Lt | F64Gt | F64Le | F64Ge => (&[F64, F64], Some(I32)),
+        F64Abs | F64Neg | F64Ceil | F64Floor | F64Trunc | F64Nearest | F64Sqrt => {
+            (&[F64], Some(F64))
+        }
+        F64Add | F64Sub | F64Mul | F64Div | F64Min | F64Max | F64Copysign => {
+            (&[F64, F64], Some(F64))
+        }
+
+        I32WrapI64 => (&[I64], Some(I32)),
+        I32TruncF32S | I32TruncF32U | I32TruncSatF32S | I32TruncSatF32U | I32ReinterpretF32 => {
+            (&[F32], Some(I32))
+        }
+        I32TruncF64S | I32TruncF64U | I32TruncSatF64S | I32TruncSatF64U => (&[F64], Some(I32)),
+        I64ExtendI32S | I64ExtendI32U => (&[I32], Some(I64)),
+        I64TruncF32S | I64TruncF32U | I64TruncSatF32S | I64TruncSatF32U => (&[F32], Some(I64)),
+        I64TruncF64S | I64TruncF64U | I64TruncSatF64S | I64TruncSatF64U | I64ReinterpretF64 => {
+            (&[F64], Some(I64))
+        }
+        F32ConvertI32S | F32ConvertI32U | F32ReinterpretI32 => (&[I32], Some(F32)),
+        F32ConvertI64S | F32ConvertI64U => (&[I64], Some(F32)),
+        F32DemoteF64 => (&[F64], Some(F32)),
+        F64ConvertI32S | F64ConvertI32U => (&[I32], Some(F64)),
+        F64ConvertI64S | F64ConvertI64U | F64ReinterpretI64 => (&[I64], Some(F64)),
+        F64PromoteF32 => (&[F32], Some(F64)),
+
+        V128Load(_) => (&[I32], Some(V128)),
+        V128Store(_) => (&[I32, V128], None),
+        I8x16Splat | I16x8Splat | I32x4Splat => (&[I32], Some(V128)),
+        I64x2Splat => (&[I64], Some(V128)),
+        F32x4Splat => (&[F32], Some(V128)),
+        F64x2Splat => (&[F64], Some(V128)),
+        I8x16ExtractLaneS(_) | I8x16ExtractLaneU(_) | I16x8ExtractLaneS(_)
+        | I16x8ExtractLaneU(_) | I32x4ExtractLane(_) => (&[V128], Some(I32)),
+        I64x2ExtractLane(_) => (&[V128], Some(I64)),
+        F32x4ExtractLane(_) => (&[V128], Some(F32)),
+        F64x2ExtractLane(_) => (&[V128], Some(F64)),
+        I32x4Add | I32x4Sub | I32x4Mul | F32x4Add | F32x4Sub | F32x4Mul | F32x4Div => {
+            (&[V128, V128], Some(V128))
+        }
+
+        MemoryAtomicNotify(_) => (&[I32, I32], Some(I32)),
+        MemoryAtomicWait32(_) => (&[I32, I32, I64], Some(I32)),
+        MemoryAtomicWait64(_) => (&[I32, I64, I64], Some(I32)),
+        AtomicFence => (&[], None),
+
+        instr => unreachable!(
+            "{:?} is not a memory, numeric, vector or atomic instruction",
+            instr
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate;
+    use crate::loader::{
+        error::{Error, IndexSpace},
+        parser::Parser,
+    };
+    use crate::tests::wat2wasm;
+
+    #[test]
+    fn func_refs() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func $f)
+                  (func (result funcref) (ref.func $f)))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        assert_eq!(validate(&module), Err(Error::UndeclaredFuncRef(0)));
+
+        let wasm = wat2wasm(
+            r#"(module
+                  (func $f)
+                  (elem declare func $f)
+                  (func (result funcref) (ref.func $f)))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        assert_eq!(validate(&module), Ok(()));
+    }
+
+    #[test]
+    fn table_copies() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (table $f 1 funcref)
+                  (table $e 1 externref)
+                  (func (table.copy $e $f (i32.const 0) (i32.const 0) (i32.const 1))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        assert_eq!(
+            validate(&module),
+            Err(Error::TableTypeMismatch { dst: 1, src: 0 })
+        );
+
+        let wasm = wat2wasm(
+            r#"(module
+                  (table $a 1 funcref)
+                  (table $b 1 funcref)
+                  (func (table.copy $b $a (i32.const 0) (i32.const 0) (i32.const 1))))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        assert_eq!(validate(&module), Ok(()));
+    }
+
+    #[test]
+    fn block_results() {
+        let invalid = [
+            (
+                "(func (block (result i32) (f64.const 1)) drop)",
+                Error::TypeMismatch("expected i32, found f64".into()),
+            ),
+            (
+                "(func (block (result i32) (i32.const 1) (i32.const 2)) drop)",
+                Error::TypeMismatch("expected 1 values at end of block, found 2".into()),
+            ),
+            (
+                "(func (block (result i32)) drop)",
+                Error::TypeMismatch("expected i32, found nothing".into()),
+            ),
+            (
+                "(func (i32.const 0) (if (then (i32.const 1))))",
+                Error::TypeMismatch("expected 0 values at end of block, found 1".into()),
+            ),
+        ];
+        for (func, err) in invalid {
+            let wasm = wat2wasm(format!("(module {})", func)).unwrap();
+            let module = Parser::new(&wasm).module().unwrap();
+            assert_eq!(validate(&module), Err(err), "{}", func);
+        }
+
+        let wasm = wat2wasm(
+            r#"(module
+                  (func (param i32) (result i32)
+                    (block (result i32)
+                      (if (result i32) (local.get 0)
+                        (then (br 1 (i32.const 1)))
+                        (else (i32.const 2))))
+                    (loop (param i32) (result i32)
+                      (br_if 0 (i32.const 0))
+                      (i32.add (i32.const 1)))
+                    (unreachable)))"#,
+        )
+        .unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        assert_eq!(validate(&module), Ok(()));
+    }
+
+    #[test]
+    fn indices() {
+        let unknown = |space, idx| Error::UnknownIndex { space, idx };
+        let invalid = [
+            (
+                r#"(func) (export "f" (func 99))"#,
+                unknown(IndexSpace::Func, 99),
+            ),
+            ("(func) (start 1)", unknown(IndexSpace::Func, 1)),
+            (r#"(export "m" (memory 0))"#, unknown(IndexSpace::Mem, 0)),
+            (
+                r#"(memory 1) (data (memory 1) (i32.const 0) "")"#,
+                unknown(IndexSpace::Mem, 1),
+            ),
+            (
+                "(func) (elem (table 0) (i32.const 0) func 0)",
+                unknown(IndexSpace::Table, 0),
+            ),
+            ("(func (drop (memory.size)))", unknown(IndexSpace::Mem, 0)),
+            (
+                "(func (drop (global.get 3)))",
+                unknown(IndexSpace::Global, 3),
+            ),
+            ("(func (drop (local.get 0)))", unknown(IndexSpace::Local, 0)),
+            (
+                "(memory 1) (memory 1) (func (drop (memory.grow 1 (i32.const 1))))",
+                Error::Other("unsupported memory 1".into()),
+            ),
+        ];
+        for (fields, err) in invalid {
+            let wasm = wat2wasm(format!("(module {})", fields)).unwrap();
+            let module = Parser::new(&wasm).module().unwrap();
+            assert_eq!(validate(&module), Err(err), "{}", fields);
+        }
+        assert_eq!(
+            unknown(IndexSpace::Func, 99).to_string(),
+            "unknown function 99"
+        );
+
+        let wasm = wat2wasm(r#"(module (func) (export "f" (func 0)) (start 0))"#).unwrap();
+        let module = Parser::new(&wasm).module().unwrap();
+        assert_eq!(validate(&module), Ok(()));
+    }
+}
@@ -52,7 +52,7 @@ impl<'a> Parser<'a> {
             self.skip(4);
             Ok(f32::from_le_bytes(bytes))
         } else {
-            Err(Error::UnexpectedEof(format!("f32")))
+            Err(Error::UnexpectedEof("f32".into()))
         }
     }
 
@@ -62,7 +62,17 @@ impl<'a> Parser<'a> {
             self.skip(8);
             Ok(f64::from_le_bytes(bytes))
         } else {
-            Err(Error::UnexpectedEof(format!("f64")))
+            Err(Error::UnexpectedEof("f64".into()))
+        }
+    }
+
+    pub fn v128(&mut self) -> Result<[u8; 16], Error> {
+        if self.rest().len() >= 16 {
+            let bytes: [u8; 16] = self.rest()[0..16].try_into().unwrap();
+            self.skip(16);
+            Ok(bytes)
+        } else {
+            Err(Error::UnexpectedEof("v128".into()))
         }
     }
 
@@ -70,12 +80,12 @@ impl<'a> Parser<'a> {
         let byte = |self_: &mut Self| {
             self_
                 .byte()
-                .ok_or(Error::UnexpectedEof(format!("part of utf8-encoded bytes")))
+                .ok_or(Error::UnexpectedEof("part of utf8-encoded bytes".into()))
         };
         let name = self.vec(byte)?;
-        Ok(core::str::from_utf8(&name)
-            .and_then(|v| Ok(v.to_string()))
-            .map_err(|e| Error::InvalidUtf8(e))?)
+        core::str::from_utf8(&name)
+            .map(|v| v.to_string())
+            .map_err(Error::InvalidUtf8)
     }
 }
 
@@ -114,4 +124,29 @@ mod tests {
         let mut parser = Parser::new(&[0x00, 0x00, 0x48, 0x41]);
         assert!(matches!(parser.f64(), Err(..)))
     }
+
+    #[test]
+    fn test_nan_bits() {
+        // A signaling NaN must keep its exact payload.
+        let mut parser = Parser::new(&[0x01, 0x00, 0xA0, 0x7F]);
+        assert_eq!(parser.f32().map(f32::to_bits), Ok(0x7FA0_0001));
+
+        let mut parser = Parser::new(&[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0xF4, 0x7F]);
+        assert_eq!(parser.f64().map(f64::to_bits), Ok(0x7FF4_0000_0000_0001));
+    }
+
+    #[test]
+    fn test_i64_bounds() {
+        let mut bytes = vec![0x80; 9];
+        bytes.push(0x7F);
+        let mut parser = Parser::new(&bytes);
+        assert_eq!(parser.i64(), Ok(i64::MIN));
+        assert!(parser.rest().is_empty());
+
+        let mut bytes = vec![0xFF; 9];
+        bytes.push(0x00);
+        let mut parser = Parser::new(&bytes);
+        assert_eq!(parser.i64(), Ok(i64::MAX));
+        assert!(parser.rest().is_empty());
+    }
 }
@@ -0,0 +1,101 @@
+//! Helpers for tests that run a single module.
+
+use crate::binary::{Export, ExportDesc, Expr, Func, FuncType, Instr, Module, ResultType, ValType};
+use crate::exec::env::{DebugEnv, Env};
+use crate::exec::runtime::Runtime;
+use crate::exec::store::Store;
+use crate::loader::parse;
+
+/// Parse and instantiate `wasm` under the `env` import module, panicking on
+/// failure.
+///
+/// ```
+/// let wasm = wat::parse_str(r#"(module (func (export "one") (result i32) i32.const 1))"#).unwrap();
+/// let (mut runtime, mut store, mut env) = wasper::test_support::harness(&wasm);
+/// assert_eq!(
+///     runtime.invoke(&mut store, &mut env, "one", vec![]),
+///     Ok(vec![wasper::Value::I32(1)])
+/// );
+/// ```
+pub fn harness(wasm: &[u8]) -> (Runtime, Store, impl Env) {
+    let module = parse(wasm).expect("failed to parse module");
+    let mut store = Store::new();
+    let mut runtime = Runtime::new("env");
+    runtime
+        .add_module(&mut store, module)
+        .expect("failed to instantiate module");
+    (runtime, store, DebugEnv {})
+}
+
+/// A module whose only function has the given signature and `body`, with no
+/// locals beyond its params, exported as `main`.
+pub fn single_func_module(params: &[ValType], results: &[ValType], body: Vec<Instr>) -> Module {
+    Module {
+        version: 1,
+        types: vec![FuncType(
+            ResultType(params.to_vec()),
+            ResultType(results.to_vec()),
+        )],
+        funcs: vec![Func {
+            typeidx: 0,
+            locals: vec![],
+            body: Expr::new(body),
+        }],
+        tables: vec![],
+        mems: vec![],
+        globals: vec![],
+        elems: vec![],
+        datas: vec![],
+        start: None,
+        imports: vec![],
+        exports: vec![Export {
+            name: "main".to_string(),
+            desc: ExportDesc::Func(0),
+        }],
+        code_ranges: vec![],
+        func_names: vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{harness, single_func_module};
+    use crate::binary::{Instr, ValType};
+    use crate::exec::env::DebugEnv;
+    use crate::exec::runtime::Runtime;
+    use crate::exec::store::Store;
+    use crate::exec::value::Value;
+    use crate::tests::wat2wasm;
+
+    #[test]
+    fn add() {
+        let wasm = wat2wasm(
+            r#"(module
+                  (func (export "add") (param i32 i32) (result i32)
+                    (i32.add (local.get 0) (local.get 1))))"#,
+        )
+        .unwrap();
+        let (mut runtime, mut store, mut env) = harness(&wasm);
+        let args = vec![Value::I32(1), Value::I32(2)];
+        assert_eq!(
+            runtime.invoke(&mut store, &mut env, "add", args),
+            Ok(vec![Value::I32(3)])
+        );
+    }
+
+    #[test]
+    fn single_func() {
+        let module = single_func_module(
+            &[],
+            &[ValType::I32],
+            vec![Instr::I32Const(2), Instr::I32Const(3), Instr::I32Add],
+        );
+        let mut store = Store::new();
+        let mut runtime = Runtime::new("env");
+        runtime.add_module(&mut store, module).unwrap();
+        assert_eq!(
+            runtime.invoke(&mut store, &mut DebugEnv {}, "main", vec![]),
+            Ok(vec![Value::I32(5)])
+        );
+    }
+}
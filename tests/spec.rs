@@ -3,15 +3,19 @@ use log::info;
 use serde_json::Value;
 use std::io::Write;
 use std::{
+    collections::HashMap,
+    env,
     fmt::Debug,
     fs::{self, File},
-    io::Read,
-    path::PathBuf,
+    io::{self, Read},
+    panic::{self, AssertUnwindSafe},
+    path::Path,
     process::Command,
 };
 use wasper::exec::importer::Importer;
 use wasper::exec::runtime::RuntimeError;
 use wasper::exec::store::Store;
+use wasper::exec::trap::Trap;
 use wasper::exec::value::LittleEndian;
 use wasper::{
     binary::Module,
@@ -20,7 +24,11 @@ use wasper::{
 };
 
 const WAST_DIR: &str = "./tests/testsuite";
+const MANIFEST: &str = "./tests/spec_manifest.json";
 const WAST2JSON: &str = "wast2json";
+/// When set, a missing testsuite fails the spec tests instead of skipping
+/// them with a warning.
+const REQUIRE_SPEC: &str = "WASPER_REQUIRE_SPEC";
 
 #[test]
 fn main() {
@@ -41,6 +49,10 @@ enum TestCommand<'a> {
         action: Action<'a>,
         text: &'a str,
     },
+    AssertExhaustion {
+        action: Action<'a>,
+        text: &'a str,
+    },
     Module {
         filename: &'a str,
     },
@@ -74,6 +86,10 @@ impl<'a> TestCommand<'a> {
                 action: Action::from_value(v.get("action").unwrap())?,
                 text: v.get("text").unwrap().as_str().unwrap(),
             }),
+            "assert_exhaustion" => Some(TestCommand::AssertExhaustion {
+                action: Action::from_value(v.get("action").unwrap())?,
+                text: v.get("text").unwrap().as_str().unwrap(),
+            }),
             _ => None,
         }
     }
@@ -134,14 +150,14 @@ fn get_test_case<'a>(v: &'a Value) -> Vec<TestCommand<'a>> {
     let commands = v.get("commands").unwrap().as_array().unwrap();
     commands
         .iter()
-        .filter_map(|v| TestCommand::from_value(v))
+        .filter_map(TestCommand::from_value)
         .collect()
 }
 
 struct SpecTestImporter {}
 impl Importer for SpecTestImporter {
     fn import(&mut self, modname: &str) -> Option<Module> {
-        let mut file = File::open(&format!("{}/{}", WAST_DIR, modname)).unwrap();
+        let mut file = File::open(format!("{}/{}", WAST_DIR, modname)).unwrap();
         let mut buf = vec![];
         file.read_to_end(&mut buf).unwrap();
         let mut parser = Parser::new(&buf);
@@ -153,11 +169,10 @@ struct SpecTestEnv {}
 impl Env for SpecTestEnv {
     fn call(
         &mut self,
-        name: &str,
+        _name: &str,
         _params: Vec<WValue>,
         _memory: Option<&mut wasper::exec::store::MemInst>,
     ) -> Result<Vec<WValue>, &'static str> {
-        if name == "print" {}
         Ok(vec![])
     }
 }
@@ -186,7 +201,7 @@ fn run_test(
             *runtime = Runtime::new("spectest");
             let mut importer = SpecTestImporter {};
             runtime
-                .import_module(store, &mut importer, &filename)
+                .import_module(store, &mut importer, filename)
                 .unwrap();
             runtime.start(store, env).ok();
         }
@@ -200,7 +215,7 @@ fn run_test(
             Action::Invoke { fnname, args } => {
                 info!("{}({:?})", fnname, args);
                 match runtime.invoke(store, env, fnname, args.clone()) {
-                    Err(RuntimeError::Trap(trap)) => {
+                    Err(RuntimeError::Trap { trap, .. }) => {
                         assert_eq!(&format!("{}", trap), text);
                         info!("    => trap: {}", text);
                     }
@@ -208,68 +223,149 @@ fn run_test(
                 }
             }
         },
+        TestCommand::AssertExhaustion { action, text } => match action {
+            Action::Invoke { fnname, args } => {
+                info!("{}({:?})", fnname, args);
+                match runtime.invoke(store, env, fnname, args.clone()) {
+                    Err(RuntimeError::Trap {
+                        trap: trap @ Trap::CallStackExhausted,
+                        ..
+                    }) => {
+                        assert_eq!(&format!("{}", trap), text);
+                        info!("    => exhaustion: {}", text);
+                    }
+                    ret => panic!(
+                        "\nexpected call stack exhaustion, found {:?}\n fnname: {:?}",
+                        ret, fnname
+                    ),
+                }
+            }
+        },
     }
 }
 
-fn skip(filename: &str) -> bool {
-    // TODO
-    let skip_list = [
-        "./tests/testsuite/imports.wast",
-        "./tests/testsuite/exports.wast",
-        "./tests/testsuite/binary-leb128.wast",
-        "./tests/testsuite/data.wast",
-        "./tests/testsuite/elem.wast",
-        "./tests/testsuite/linking.wast",
-    ];
-    for s in skip_list.iter() {
-        if filename == *s {
-            return true;
+/// Expected outcome of each `.wast` file, keyed by file name. Files not
+/// listed are expected to pass; each entry gives the reason.
+struct Manifest {
+    skip: HashMap<String, String>,
+    fail: HashMap<String, String>,
+}
+
+impl Manifest {
+    fn read() -> Self {
+        let content = fs::read_to_string(MANIFEST).unwrap();
+        let v: Value = serde_json::from_str(&content).unwrap();
+        let entries = |key: &str| {
+            v.get(key)
+                .unwrap()
+                .as_object()
+                .unwrap()
+                .iter()
+                .map(|(name, reason)| (name.clone(), reason.as_str().unwrap().to_string()))
+                .collect()
+        };
+        Manifest {
+            skip: entries("skip"),
+            fail: entries("fail"),
         }
     }
-    false
 }
 
-pub fn run_tests() {
-    let entries = fs::read_dir(WAST_DIR).unwrap();
+#[test]
+fn manifest() {
+    let manifest = Manifest::read();
+    for (name, reason) in manifest.skip.iter().chain(manifest.fail.iter()) {
+        assert!(name.ends_with(".wast"), "{}: not a .wast file", name);
+        assert!(!reason.is_empty(), "{}: missing reason", name);
+    }
+    for name in manifest.skip.keys() {
+        assert!(
+            !manifest.fail.contains_key(name),
+            "{}: listed as both skip and fail",
+            name
+        );
+    }
+}
 
-    for entry in entries {
-        if let Ok(entry) = entry {
-            if entry.path().extension().and_then(|s| s.to_str()) == Some("wast") {
-                if skip(entry.path().to_str().unwrap()) {
-                    continue;
-                }
+fn run_file(path: &Path) {
+    wast2json(path);
+
+    let mut json = path.to_path_buf();
+    json.set_extension("json");
+    let mut file = File::open(json).unwrap();
+    let mut content = String::new();
+    file.read_to_string(&mut content).unwrap();
+
+    let v: Value = serde_json::from_str(&content).unwrap();
+    let commands = get_test_case(&v);
 
-                info!("{:?}", entry.path());
-                wast2json(&entry.path());
+    let mut runtime = Runtime::new("spectest");
+    let mut store = Store::new();
+    let mut env = SpecTestEnv {};
+    for command in commands.iter() {
+        run_test(&mut runtime, &mut store, &mut env, command);
+    }
+}
+
+pub fn run_tests() {
+    let manifest = Manifest::read();
+    let mut paths = fs::read_dir(WAST_DIR)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|s| s.to_str()) == Some("wast"))
+        .collect::<Vec<_>>();
+    paths.sort();
 
-                let mut json = entry.path().clone();
-                json.set_extension("json");
-                let mut file = File::open(json).unwrap();
-                let mut content = String::new();
-                file.read_to_string(&mut content).unwrap();
+    if paths.is_empty() {
+        let msg = format!(
+            "no .wast files in {}; run `git submodule update --init` to fetch the testsuite",
+            WAST_DIR
+        );
+        assert!(env::var_os(REQUIRE_SPEC).is_none(), "{}", msg);
+        // Written straight to stderr so that the test harness does not
+        // capture it.
+        #[allow(clippy::explicit_write)]
+        writeln!(io::stderr(), "warning: spec tests ran nothing: {}", msg).unwrap();
+        return;
+    }
+    assert!(
+        Command::new(WAST2JSON).arg("--version").output().is_ok(),
+        "{} is not installed; it is needed to run the spec tests",
+        WAST2JSON
+    );
 
-                let v: Value = serde_json::from_str(&content).unwrap();
-                let commands = get_test_case(&v);
+    let mut unexpected = vec![];
+    for path in paths {
+        let name = path.file_name().unwrap().to_str().unwrap().to_string();
+        if manifest.skip.contains_key(&name) {
+            continue;
+        }
 
-                let mut runtime = Runtime::new("spectest");
-                let mut store = Store::new();
-                let mut env = SpecTestEnv {};
-                for command in commands.iter() {
-                    run_test(&mut runtime, &mut store, &mut env, command);
-                }
-            }
+        info!("{:?}", path);
+        let passed = panic::catch_unwind(AssertUnwindSafe(|| run_file(&path))).is_ok();
+        match (passed, manifest.fail.contains_key(&name)) {
+            (false, false) => unexpected.push(format!("{}: failed", name)),
+            (true, true) => unexpected.push(format!("{}: passed but is listed as failing", name)),
+            _ => {}
         }
     }
     clean_up();
+    assert!(
+        unexpected.is_empty(),
+        "unexpected results:\n{}",
+        unexpected.join("\n")
+    );
 }
 
-fn wast2json(input_file: &PathBuf) {
+fn wast2json(input_file: &Path) {
     let input = input_file.to_str().unwrap();
-    let mut output = input_file.clone();
+    let mut output = input_file.to_path_buf();
     output.set_extension("json");
     let output = output.to_str().unwrap();
     Command::new(WAST2JSON)
-        .args(&[input, "-o", output])
+        .args([input, "-o", output])
         .output()
         .unwrap();
 }